@@ -0,0 +1,130 @@
+//! Benchmarks of rule matching, run with `cargo bench`. Pass a name to only run the benchmarks
+//! matching it, e.g. `cargo bench -- resolve_sound`.
+
+use std::hint::black_box;
+
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use onionbell::config::{Config, RuleState};
+use onionbell::hypr::{HyprClient, WindowState};
+
+const CLASSES: [&str; 8] = [
+    "kitty",
+    "foot",
+    "firefox",
+    "Slack",
+    "org.gnome.Nautilus",
+    "code",
+    "mpv",
+    "steam",
+];
+
+/// `count` windows spread over classes, titles and workspaces the way a busy session would be.
+fn clients(count: usize) -> Vec<HyprClient> {
+    let clients = (0..count)
+        .map(|i| {
+            format!(
+                r#"{{
+                    "address": "0x{:012x}",
+                    "mapped": true,
+                    "at": [{}, {}],
+                    "size": [800, 600],
+                    "workspace": {{ "id": {}, "name": "{}" }},
+                    "floating": {},
+                    "class": "{}",
+                    "title": "window {} - ~/src/project{}",
+                    "pid": {},
+                    "grouped": []
+                }}"#,
+                0x558e91920000u64 + i as u64,
+                i % 4 * 400,
+                i % 3 * 300,
+                i % 10 + 1,
+                i % 10 + 1,
+                i % 5 == 0,
+                CLASSES[i % CLASSES.len()],
+                i,
+                i % 7,
+                1000 + i,
+            )
+        })
+        .collect::<Vec<_>>();
+    HyprClient::parse_partial_clients(&format!("[{}]", clients.join(","))).unwrap()
+}
+
+/// A config with `count` rules using a mix of predicates, none of which match `firefox` windows
+/// on workspace 3 but the last one.
+fn config(count: usize) -> Config {
+    let mut source = String::from("sound = \"/tmp/global.wav\"\n");
+    for i in 0..count - 1 {
+        let class = CLASSES[i % CLASSES.len()];
+        let rule = match i % 4 {
+            0 => format!(
+                "class_regex = \"^{class}$\"\ntitle_regex = \"project{}$\"",
+                i % 7
+            ),
+            1 => format!("class_regex = \"^{class}$\"\nworkspace = {}", i % 10 + 4),
+            2 => format!("floating = true\ntitle_regex = \"^window {i} \""),
+            _ => format!(
+                "class_regex = \"^{class}$\"\ntitle_regex_all = [\"window\", \"src\", \"x{i}\"]"
+            ),
+        };
+        source += &format!("\n[[rule]]\n{rule}\nsound = \"/tmp/{i}.wav\"\n");
+    }
+    source += "\n[[rule]]\nclass_regex = \"^firefox$\"\nworkspace = 3\nsound = \"/tmp/last.wav\"\n";
+    Config::from_source(source).unwrap()
+}
+
+fn match_rule(c: &mut Criterion) {
+    let mut group = c.benchmark_group("match_rule");
+    for count in [10, 50, 200] {
+        let clients = clients(count);
+        // The window is the last one, so finding it takes longest.
+        let address = &clients[count - 1].address[2..];
+        let config = config(20);
+        group.bench_with_input(BenchmarkId::new("class", count), &count, |b, _| {
+            b.iter(|| {
+                HyprClient::match_rule(
+                    &clients,
+                    black_box(address),
+                    &config.rules[0],
+                    &WindowState::default(),
+                )
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("title_all", count), &count, |b, _| {
+            b.iter(|| {
+                HyprClient::match_rule(
+                    &clients,
+                    black_box(address),
+                    &config.rules[3],
+                    &WindowState::default(),
+                )
+            })
+        });
+    }
+    group.finish();
+}
+
+fn resolve_sound(c: &mut Criterion) {
+    let clients = clients(50);
+    // A firefox window on workspace 3, which only the last rule matches.
+    let address = clients
+        .iter()
+        .find(|x| x.class == "firefox" && x.workspace.id == 3)
+        .map(|x| x.address[2..].to_string())
+        .unwrap();
+    let mut group = c.benchmark_group("resolve_sound");
+    for count in [1, 10, 100, 500] {
+        let config = config(count);
+        let mut state = RuleState::default();
+        let resolved = config.resolve_sound(&clients, &address, &mut state);
+        assert_eq!(resolved.rule, Some(count - 1));
+        group.bench_with_input(BenchmarkId::from_parameter(count), &count, |b, _| {
+            b.iter(|| config.resolve_sound(&clients, black_box(&address), &mut state))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, match_rule, resolve_sound);
+criterion_main!(benches);
@@ -1,26 +1,174 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::env;
-use std::fs::OpenOptions;
-use std::io::{self, Read};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, IsTerminal, Read, Write};
+use std::num::NonZeroU32;
 use std::os::unix::net::UnixStream;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use log::{debug, trace, warn};
-use rodio::{Decoder, OutputStream, OutputStreamBuilder, Sink, Source};
+use log::{debug, info, trace, warn};
+use rand::rngs::StdRng;
+use rodio::{OutputStream, Sink, Source};
 
-use crate::config::Config;
+use crate::backend;
+use crate::builtin;
+use crate::bundle::{self, Bundles};
+use crate::cache;
+use crate::cli::Args;
+use crate::config::{self, Config, Rule, RuleState};
+use crate::control::{self, ControlCommand};
+use crate::cooldown::WindowCooldown;
+use crate::debounce::{DEBOUNCED_EVENTS, StateDebouncer};
+use crate::emit;
 use crate::error::AppError;
-use crate::hypr::HyprClient;
-use crate::util::reader_to_string;
+use crate::escalation::{self, Escalation};
+use crate::explain;
+use crate::fatigue::FatigueState;
+use crate::first_bell::FirstBell;
+use crate::health::Health;
+use crate::hypr::{self, HyprActiveWindow, HyprClient, HyprMonitor};
+use crate::lint;
+#[cfg(feature = "mqtt")]
+use crate::mqtt;
+use crate::playback::{AudioSink, AudioWorker, PlayRequest, Player, QUEUE_CAPACITY};
+use crate::ratelimit::RateLimiter;
+use crate::replay::{self, Recorder};
+use crate::schedule::Schedule;
+use crate::snapshot::{self, Snapshot};
+use crate::sound::{self, DecodedSound, Sound};
+use crate::state;
+use crate::stats::Stats;
+use crate::util::{self, make_rng, runtime_dir};
+
+/// Every preloaded sound, keyed by its path.
+pub type SoundMap = HashMap<PathBuf, Sound>;
+
+/// Log with `level` about the event being handled, unless its details are throttled, see
+/// `log_throttle_ms`.
+macro_rules! detail {
+    ($app:ident, $level:ident, $($arg:tt)+) => {
+        if $app.detailed_logs {
+            $level!($($arg)+)
+        }
+    };
+}
+
+/// Silence between two sounds in `--audition`.
+const AUDITION_PAUSE: Duration = Duration::from_millis(500);
+
+/// Messages handled by the event loop in [`App::run`].
+pub enum AppMessage {
+    /// A raw event line read from socket2.
+    Event(String),
+
+    /// A command line received from the control socket, together with the channel to reply to.
+    Control(String, Sender<Result<String, AppError>>),
+
+    /// Reading from socket2 failed, so no more events will come.
+    Disconnected(AppError),
+}
+
+/// The index of a rule with `window_cooldown_ms` and the address of the window whose cooldown
+/// starts when the rule's sound plays.
+type CooldownKey = (usize, String);
+
+/// A sound waiting in [`App::delayed`].
+#[derive(Debug)]
+pub struct DelayedSound {
+    pub request: PlayRequest,
+    pub cooldown: Option<CooldownKey>,
+}
 
 pub struct App {
     pub socket_path: PathBuf,
     pub socket2_path: PathBuf,
     pub config: Config,
 
-    pub audio_stream_handle: OutputStream,
+    /// The output stream, kept open for the audio worker. It's `None` when sounds don't go to
+    /// the output device, e.g. in tests.
+    pub audio_stream_handle: Option<OutputStream>,
     pub audio_sink: Sink,
-    pub sound_map: HashMap<PathBuf, Vec<u8>>,
+    pub sound_map: SoundMap,
+
+    /// Whether sounds are played, see [`state::read_enabled`].
+    pub enabled: bool,
+
+    /// Where state kept across restarts is written, see [`state::state_dir`]. It's `None` when
+    /// it can't be, e.g. in tests.
+    pub state_dir: Option<PathBuf>,
+
+    /// Addresses of the windows muted with `mute-active`, without `0x` like in events.
+    pub muted: HashSet<String>,
+
+    pub escalation: Escalation,
+
+    /// How far the volume of rules with `fatigue` has dropped, keyed by the index of the rule.
+    pub fatigue: HashMap<usize, FatigueState>,
+
+    /// The last window each rule with `window_cooldown_ms` played for, keyed by the index of the
+    /// rule.
+    pub window_cooldowns: HashMap<usize, WindowCooldown>,
+
+    /// Whether the next bell is the first of a session, see `first_bell_sound`.
+    pub first_bell: FirstBell,
+
+    /// Whether a clamped volume has been reported, which is only done once.
+    pub clamp_reported: bool,
+
+    /// What rules remember between bells, like how many times they matched.
+    pub rule_state: RuleState,
+
+    /// Playback latencies, recorded by the audio worker.
+    pub stats: Arc<Mutex<Stats>>,
+
+    /// Builds and enqueues sources off the event loop. This is an [`AudioWorker`] playing on the
+    /// output device, except in tests.
+    pub audio_worker: Box<dyn AudioSink>,
+    pub rng: StdRng,
+    pub recorder: Option<Recorder<File>>,
+
+    /// Whether to print every handled bell as JSON, see `--emit-json`.
+    pub emit_json: bool,
+
+    /// Publishes every handled bell, see `mqtt` in the config.
+    #[cfg(feature = "mqtt")]
+    pub mqtt: Option<mqtt::Publisher>,
+
+    /// Limits how often events are logged in detail, see `log_throttle_ms`.
+    pub log_limiter: RateLimiter,
+
+    /// Whether the event being handled is logged in detail, as decided by `log_limiter`.
+    pub detailed_logs: bool,
+
+    /// Whether events are read from socket2, which stops when it's lost.
+    pub socket2_connected: Arc<AtomicBool>,
+
+    /// When the last event arrived from socket2.
+    pub last_event: Option<Instant>,
+
+    /// When the app was created, which counts as the last event for `idle_exit_ms` until one
+    /// arrives.
+    pub started: Instant,
+
+    /// Debounced states of windows, keyed by the event type that reports the state and the
+    /// address of the window.
+    pub window_states: StateDebouncer<(String, String), String>,
+
+    /// The address of the focused window, from `activewindowv2` events, which `fullscreen`
+    /// events are about. It's only up to date when `activewindowv2` is handled, e.g. with
+    /// `debounce_ms`, see [`Config::handled_events`].
+    pub focused: Option<String>,
+
+    /// Sounds of rules with a delay, handed to the audio worker once they're due.
+    pub delayed: Schedule<DelayedSound>,
+
+    /// The types of events to handle, see [`Config::handled_events`].
+    pub handled_events: HashSet<String>,
 }
 
 impl App {
@@ -31,20 +179,16 @@ impl App {
     /// I really hope if there is a named tuple thing so I can mark them on the type, but
     /// unfortunately there isn't; And it feels really weird to actually have a different type for
     /// such a small thing so I keep it like that.
-    fn init_hyprland_socket_path() -> Result<(PathBuf, PathBuf), AppError> {
+    fn init_hyprland_socket_path(args: &Args) -> Result<(PathBuf, PathBuf), AppError> {
         trace!("Checking environment variables...");
-        let xdg_runtime = env::var("XDG_RUNTIME_DIR")?;
-        let hyprland_instance_signature = env::var("HYPRLAND_INSTANCE_SIGNATURE")?;
+        let xdg_runtime = runtime_dir()?;
+        let hyprland_instance_signature = Self::instance_signature(args)?;
         trace!(
             "xdg_runtime = {:?}, hyprland_instance_signature = {:?}",
             xdg_runtime, hyprland_instance_signature
         );
-        let path = PathBuf::from(xdg_runtime)
-            .join("hypr")
-            .join(hyprland_instance_signature);
-
-        let socket_path = path.join(".socket.sock");
-        let socket2_path = path.join(".socket2.sock");
+        let (socket_path, socket2_path) =
+            Self::hyprland_socket_paths(&xdg_runtime, &hyprland_instance_signature);
         for p in [&socket_path, &socket2_path] {
             trace!("checking p = {:?}", p);
             if !p.exists() {
@@ -58,8 +202,59 @@ impl App {
         Ok((socket_path, socket2_path))
     }
 
+    /// The signature of the Hyprland instance to connect to: `--instance` if it's given, then the
+    /// content of `--instance-file`, then `$HYPRLAND_INSTANCE_SIGNATURE`.
+    fn instance_signature(args: &Args) -> Result<String, AppError> {
+        let signature = if let Some(ref signature) = args.instance {
+            signature.clone()
+        } else if let Some(ref path) = args.instance_file {
+            fs::read_to_string(path)?.trim().to_string()
+        } else {
+            env::var("HYPRLAND_INSTANCE_SIGNATURE")?
+        };
+        // The signature is a directory name, so it mustn't lead anywhere else.
+        if signature.is_empty() || signature.contains('/') || signature == ".." {
+            return Err(AppError::InvalidArgument(format!(
+                "invalid Hyprland instance signature {signature:?}"
+            )));
+        }
+        Ok(signature)
+    }
+
+    /// Paths of the request and event sockets of the Hyprland instance with `signature`.
+    fn hyprland_socket_paths(xdg_runtime: &Path, signature: &str) -> (PathBuf, PathBuf) {
+        let path = xdg_runtime.join("hypr").join(signature);
+        (path.join(".socket.sock"), path.join(".socket2.sock"))
+    }
+
     /// Check and load config.
-    fn load_config() -> Result<Config, AppError> {
+    /// `path` overrides the default config location, where `-` means reading from stdin. Paths
+    /// ending in `.json` are parsed as JSON. Without `path`, the content of `$ONIONBELL_CONFIG` is
+    /// parsed as TOML if it's set.
+    fn load_config(path: Option<&Path>) -> Result<Config, AppError> {
+        Self::load_config_from(path, env::var("ONIONBELL_CONFIG").ok())
+    }
+
+    /// Like [`App::load_config`], with the content of `$ONIONBELL_CONFIG` passed in as
+    /// `env_config`. It's used when `path` is `None`, instead of the default location.
+    fn load_config_from(
+        path: Option<&Path>,
+        env_config: Option<String>,
+    ) -> Result<Config, AppError> {
+        if let Some(path) = path {
+            if path == Path::new("-") {
+                debug!("Reading config from stdin");
+                return Config::from_reader(io::stdin().lock());
+            }
+            debug!("Config: {}", path.to_string_lossy());
+            return Self::load_config_file(path);
+        }
+
+        if let Some(source) = env_config.filter(|x| !x.is_empty()) {
+            debug!("Reading config from ONIONBELL_CONFIG");
+            return Config::from_source(source);
+        }
+
         let config_home = env::var("XDG_CONFIG_HOME")
             .map(PathBuf::from)
             .or_else(|_| {
@@ -71,81 +266,487 @@ impl App {
             .unwrap_or("/etc/onionbell".into());
         debug!("Config Home: {}", config_home.to_string_lossy());
 
-        OpenOptions::new()
-            .read(true)
-            .open(config_home.join("config.toml"))
-            .map_err(AppError::from)
-            .and_then(reader_to_string)
-            .and_then(Config::from_source)
+        Self::load_config_file(&config_home.join("config.toml"))
+    }
+
+    /// Load the config file at `path`, and merge the rule files in `conf.d` next to it.
+    fn load_config_file(path: &Path) -> Result<Config, AppError> {
+        let mut config = Config::from_path(path)?;
+        let conf_d = path.parent().unwrap_or(Path::new("")).join("conf.d");
+        config.load_conf_d(&conf_d)?;
+        Ok(config)
     }
 
     /// Initialize audio and load all audio data into memory for fast access.
-    fn init_audio(
-        config: &Config,
-    ) -> Result<(OutputStream, Sink, HashMap<PathBuf, Vec<u8>>), AppError> {
-        let stream_handle = OutputStreamBuilder::open_default_stream()?;
-        let sink = Sink::connect_new(&stream_handle.mixer());
+    fn init_audio(config: &Config) -> Result<(OutputStream, Sink, SoundMap), AppError> {
+        let stream_handle = backend::open_stream(config.backend.as_deref())?;
+        let sink = Sink::connect_new(stream_handle.mixer());
+        Ok((stream_handle, sink, Self::load_sounds(config)))
+    }
+
+    /// Load every sound referenced by `config`. Sounds that fail to load are logged and left out.
+    /// Once preloaded sounds would take more than `max_preload_bytes`, the rest are loaded
+    /// lazily.
+    fn load_sounds(config: &Config) -> SoundMap {
         let mut sound_map = HashMap::new();
-        for sfx_path in config
-            .sound
-            .iter()
-            .chain(config.rules.iter().filter_map(|x| x.sound.as_ref()))
-        {
-            if !sound_map.contains_key(sfx_path) {
-                debug!("Loading SFX {}", sfx_path.to_string_lossy());
-                match OpenOptions::new()
-                    .read(true)
-                    .open(sfx_path)
-                    .map_err(AppError::from)
-                    .and_then(|mut x| {
-                        let mut buf = Vec::new();
-                        x.read_to_end(&mut buf).map(|_| buf).map_err(|e| e.into())
-                    }) {
-                    Ok(x) => {
-                        sound_map.insert(sfx_path.clone(), x);
-                    }
-                    Err(err) => {
+        let mut total = 0;
+        let mut over_limit = false;
+        let mut bundles = Bundles::default();
+        for sfx_path in config.sound_paths() {
+            if sound_map.contains_key(sfx_path) {
+                continue;
+            }
+            // Entries of bundles are never loaded lazily, as that would read the whole archive
+            // again.
+            let bundle = bundle::split(sfx_path);
+            let builtin = builtin::name(sfx_path).is_some() || bundle.is_some();
+            if over_limit && !builtin {
+                info!(
+                    "Not preloading {}, it will be read when played",
+                    sfx_path.to_string_lossy()
+                );
+                sound_map.insert(sfx_path.clone(), Sound::Lazy(sfx_path.clone()));
+                continue;
+            }
+            debug!("Loading SFX {}", sfx_path.to_string_lossy());
+            let sound = match bundle {
+                Some((archive, entry)) => bundles.read(archive, entry).map(Sound::Encoded),
+                None => Self::load_sound(sfx_path, config.disk_cache),
+            };
+            match sound {
+                Ok(x) => {
+                    if !builtin
+                        && let Some(max) = config.max_preload_bytes
+                        && total + x.size() > max
+                    {
                         warn!(
-                            "Failed to read or decode source {}: {}",
-                            sfx_path.to_string_lossy(),
-                            err
+                            "Preloaded sounds would take more than {} bytes, so {} and later \
+                            sounds will be read when played",
+                            max,
+                            sfx_path.to_string_lossy()
                         );
+                        over_limit = true;
+                        sound_map.insert(sfx_path.clone(), Sound::Lazy(sfx_path.clone()));
+                        continue;
                     }
+                    total += x.size();
+                    sound_map.insert(sfx_path.clone(), x);
+                }
+                Err(err) => {
+                    warn!(
+                        "Failed to read or decode source {}: {}",
+                        sfx_path.to_string_lossy(),
+                        err
+                    );
                 }
             }
         }
-        Ok((stream_handle, sink, sound_map))
+        // Beeps are synthesized when played, so they take no memory.
+        for beeps in config.rules.iter().filter_map(|x| x.beeps.as_ref()) {
+            sound_map.insert(beeps.path(), Sound::Beeps(beeps.clone()));
+        }
+        sound_map
+    }
+
+    /// Read the sound files of the current config again, e.g. after they were replaced on disk.
+    /// Sounds that fail to load keep their previous data. Returns how many sounds were loaded.
+    pub fn reload_sounds(&mut self) -> usize {
+        let reloaded = Self::reload_sound_map(&self.config, &mut self.sound_map);
+        self.audio_worker.reset();
+        info!("Reloaded {} sounds", reloaded);
+        reloaded
+    }
+
+    fn reload_sound_map(config: &Config, sound_map: &mut SoundMap) -> usize {
+        let mut reloaded = Self::load_sounds(config);
+        let count = reloaded.len();
+        for (sfx_path, sound) in sound_map.drain() {
+            reloaded.entry(sfx_path).or_insert(sound);
+        }
+        *sound_map = reloaded;
+        count
+    }
+
+    /// Load a sound, either bundled into the binary, from a zip archive or from the filesystem.
+    /// With `disk_cache` enabled, files are decoded ahead of time, reusing the samples cached on
+    /// disk if possible.
+    fn load_sound(sfx_path: &Path, disk_cache: bool) -> Result<Sound, AppError> {
+        if let Some(name) = builtin::name(sfx_path) {
+            return builtin::get(name)
+                .map(|x| Sound::Encoded(x.to_vec()))
+                .ok_or_else(|| AppError::UnknownBuiltinSound(name.to_string()));
+        }
+        if let Some((archive, entry)) = bundle::split(sfx_path) {
+            return Bundles::default().read(archive, entry).map(Sound::Encoded);
+        }
+        if disk_cache && let Some(cache_dir) = cache::cache_dir() {
+            let cache_file = cache_dir.join(cache::cache_key(sfx_path));
+            return Self::load_decoded(sfx_path, &cache_file).map(Sound::Decoded);
+        }
+        Self::read_file(sfx_path).map(Sound::Encoded)
+    }
+
+    /// Load the decoded samples of a sound file from `cache_file`, or decode it and update the
+    /// cache if the file has changed since it was cached.
+    fn load_decoded(sfx_path: &Path, cache_file: &Path) -> Result<DecodedSound, AppError> {
+        let mtime = fs::metadata(sfx_path)?.modified()?;
+        if let Some(sound) = cache::read(cache_file, mtime) {
+            debug!("Loaded {} from cache", sfx_path.to_string_lossy());
+            return Ok(sound);
+        }
+        let sound = DecodedSound::decode(Self::read_file(sfx_path)?)?;
+        if let Err(err) = cache::write(cache_file, mtime, &sound) {
+            warn!(
+                "Failed to write cache {}: {}",
+                cache_file.to_string_lossy(),
+                err
+            );
+        }
+        Ok(sound)
+    }
+
+    fn read_file(sfx_path: &Path) -> Result<Vec<u8>, AppError> {
+        // Opening a directory succeeds, and only reading it fails with a vague error.
+        if sfx_path.is_dir() {
+            return Err(AppError::SoundIsDirectory(sfx_path.to_path_buf()));
+        }
+        let mut buf = Vec::new();
+        OpenOptions::new()
+            .read(true)
+            .open(sfx_path)?
+            .read_to_end(&mut buf)?;
+        Ok(buf)
     }
 
     // }}}
 
-    pub fn new() -> Result<App, AppError> {
-        let (socket_path, socket2_path) = Self::init_hyprland_socket_path()?;
+    pub fn new(args: &Args) -> Result<App, AppError> {
+        let (socket_path, socket2_path) = Self::init_hyprland_socket_path(args)?;
 
-        let config = Self::load_config().unwrap_or_else(|err| {
+        let config = Self::load_config(args.config.as_deref()).unwrap_or_else(|err| {
             warn!("Failed to load configuration: {}", err);
             warn!("Will use default value as fallback. ");
             Config::default()
         });
+        for warning in config.validate() {
+            warn!("{}", warning);
+        }
 
         let (audio_stream_handle, audio_sink, sound_map) = Self::init_audio(&config)?;
+        Self::report_rules(&config, &sound_map);
+        let rng = make_rng(args.seed.or(config.seed));
+        let recorder = args.record.as_deref().map(Recorder::open).transpose()?;
+        let log_limiter = RateLimiter::new(config.log_throttle());
+        let handled_events = config.handled_events();
+        let state_dir = state::state_dir();
+        let enabled = state_dir
+            .as_deref()
+            .is_none_or(|x| state::read_enabled(&state::enabled_path(x)));
+        if !enabled {
+            info!("Sounds are disabled, run `onionbell --ctl enable` to play them again");
+        }
+        let stats = Arc::new(Mutex::new(Stats::default()));
+        let mut player = Player::new(
+            audio_stream_handle.mixer().clone(),
+            stats.clone(),
+            config.backend.clone(),
+        );
+        let audio_worker = AudioWorker::spawn(QUEUE_CAPACITY, move |x| player.run(x));
+        #[cfg(feature = "mqtt")]
+        let mqtt = config.mqtt.clone().map(mqtt::Publisher::spawn);
 
-        Ok(App {
+        let mut app = App {
             socket_path,
             socket2_path,
             config,
             sound_map,
-            audio_stream_handle,
+            audio_stream_handle: Some(audio_stream_handle),
             audio_sink,
-        })
+            enabled,
+            state_dir,
+            muted: HashSet::new(),
+            escalation: Escalation::default(),
+            fatigue: HashMap::new(),
+            window_cooldowns: HashMap::new(),
+            first_bell: FirstBell::default(),
+            clamp_reported: false,
+            rule_state: RuleState::default(),
+            stats,
+            audio_worker: Box::new(audio_worker),
+            rng,
+            recorder,
+            emit_json: args.emit_json,
+            #[cfg(feature = "mqtt")]
+            mqtt,
+            log_limiter,
+            detailed_logs: true,
+            socket2_connected: Arc::new(AtomicBool::new(false)),
+            last_event: None,
+            started: Instant::now(),
+            window_states: StateDebouncer::default(),
+            focused: None,
+            delayed: Schedule::default(),
+            handled_events,
+        };
+        app.restore_muted();
+        Ok(app)
+    }
+
+    /// Read the windows muted before a restart, with `persist_mutes`. Only the ones that are
+    /// still open are kept, so new windows reusing the address of a closed one aren't muted, and
+    /// the state file is written again without the others.
+    fn restore_muted(&mut self) {
+        if !self.config.persist_mutes {
+            return;
+        }
+        let Some(path) = self.state_dir.as_deref().map(state::muted_path) else {
+            return;
+        };
+        let muted = state::read_muted(&path);
+        if muted.is_empty() {
+            return;
+        }
+        let clients = match HyprClient::get_clients(&self.socket_path) {
+            Ok(clients) => clients,
+            Err(err) => {
+                warn!("Failed to get windows, so muted windows are not restored: {err}");
+                return;
+            }
+        };
+        self.muted = muted
+            .into_iter()
+            .filter(|x| {
+                clients
+                    .iter()
+                    .any(|client| client.address.strip_prefix("0x") == Some(x.as_str()))
+            })
+            .collect();
+        self.remember_muted();
+    }
+
+    /// Log every rule in a line, and which of its sounds failed to load, so misconfigurations
+    /// show up right at startup.
+    fn report_rules(config: &Config, sound_map: &SoundMap) {
+        info!("Loaded {} rules", config.rules.len());
+        for (i, rule) in config.rules.iter().enumerate() {
+            let missing = rule
+                .all_sounds()
+                .filter(|x| !sound_map.contains_key(*x))
+                .map(|x| x.to_string_lossy())
+                .collect::<Vec<_>>();
+            if missing.is_empty() {
+                info!("Rule #{i}: {}", rule.describe());
+            } else {
+                warn!(
+                    "Rule #{i}: {} (failed to load {})",
+                    rule.describe(),
+                    missing.join(", ")
+                );
+            }
+        }
+    }
+
+    /// Load the config like [`App::new`] does and serialize it back to TOML.
+    pub fn dump_effective(args: &Args) -> Result<String, AppError> {
+        Self::load_config(args.config.as_deref())?.to_toml()
+    }
+
+    /// Decode every sound referenced by the config to the end, printing whether it works. Returns
+    /// whether all of them do.
+    pub fn validate_sounds(args: &Args) -> Result<bool, AppError> {
+        let config = Self::load_config(args.config.as_deref())?;
+        let mut seen = HashSet::new();
+        let mut all_ok = true;
+        for sfx_path in config.sound_paths().filter(|x| seen.insert(*x)) {
+            match Self::validate_sound(sfx_path) {
+                Ok(()) => println!("ok   {}", sfx_path.to_string_lossy()),
+                Err(err) => {
+                    println!("fail {}: {}", sfx_path.to_string_lossy(), err);
+                    all_ok = false;
+                }
+            }
+        }
+        Ok(all_ok)
+    }
+
+    /// Run every lint check on the config and print the report. Returns whether there are no
+    /// errors.
+    pub fn lint(args: &Args) -> Result<bool, AppError> {
+        let config = Self::load_config(args.config.as_deref())?;
+        let diagnostics = lint::lint(&config, Self::validate_sound);
+        print!("{}", lint::report(&diagnostics));
+        Ok(!lint::has_errors(&diagnostics))
+    }
+
+    /// Read and decode the whole sound at `sfx_path`, bypassing the disk cache.
+    fn validate_sound(sfx_path: &Path) -> Result<(), AppError> {
+        let samples = Self::load_sound(sfx_path, false)?.source()?.count();
+        trace!("{} has {} samples", sfx_path.to_string_lossy(), samples);
+        Ok(())
     }
 
-    pub fn get_event(&self, socket: &mut UnixStream) -> Result<String, AppError> {
+    /// Play the sound of every rule that has one in turn, so the config can be checked by ear.
+    pub fn audition(args: &Args) -> Result<(), AppError> {
+        let config = Self::load_config(args.config.as_deref())?;
+        let (_audio_stream_handle, audio_sink, sound_map) = Self::init_audio(&config)?;
+        for (i, rule) in config.auditions() {
+            if let Some(ref beeps) = rule.beeps {
+                Self::audition_sound(&config, &audio_sink, &sound_map, i, rule, &beeps.path());
+                continue;
+            }
+            for sfx_path in rule.all_sounds() {
+                Self::audition_sound(&config, &audio_sink, &sound_map, i, rule, sfx_path);
+            }
+        }
+        Ok(())
+    }
+
+    fn audition_sound(
+        config: &Config,
+        audio_sink: &Sink,
+        sound_map: &SoundMap,
+        i: usize,
+        rule: &Rule,
+        sfx_path: &Path,
+    ) {
+        info!(
+            "Rule #{}{}: {} at volume {}",
+            i,
+            rule.name
+                .as_ref()
+                .map(|x| format!(" ({x})"))
+                .unwrap_or_default(),
+            sfx_path.to_string_lossy(),
+            rule.volume
+        );
+        let Some(sound) = sound_map.get(sfx_path) else {
+            // The reason is already logged by init_audio().
+            return;
+        };
+        match sound.source() {
+            Ok(audio) => {
+                let audio = sound::resample(audio, config.sample_rate.map(NonZeroU32::get));
+                audio_sink.append(audio.amplify_normalized(rule.volume));
+                Self::wait_for_playback(audio_sink);
+            }
+            Err(err) => {
+                warn!(
+                    "Failed to play audio {}: {}",
+                    sfx_path.to_string_lossy(),
+                    err
+                );
+            }
+        }
+        thread::sleep(AUDITION_PAUSE);
+    }
+
+    /// Feed events recorded with `--record` through rule matching, printing what would have been
+    /// played instead of playing it. Rules are matched against the current windows, if Hyprland
+    /// is available, and follow the recorded events that rules look at, like `urgent`.
+    pub fn replay(args: &Args, path: &Path) -> Result<(), AppError> {
+        let config = Self::load_config(args.config.as_deref())?;
+        let socket_path = Self::init_hyprland_socket_path(args)
+            .inspect_err(|err| {
+                warn!(
+                    "Hyprland is unavailable: {}. Rules will not be matched. ",
+                    err
+                )
+            })
+            .ok()
+            .map(|(socket_path, _)| socket_path);
+
+        let mut rule_state = RuleState::default();
+        let mut previous = None;
+        for (i, event) in replay::read_events(BufReader::new(File::open(path)?)).enumerate() {
+            let replay::RecordedEvent { time_ms, event } = event?;
+            thread::sleep(args.replay_speed.delay(previous, time_ms, i == 0));
+            previous = time_ms;
+            let resolved = match (hypr::parse_event(&event), socket_path.as_deref()) {
+                (Some(("bell", data)), Some(socket_path)) => {
+                    let clients = HyprClient::get_clients(socket_path)
+                        .inspect_err(|err| warn!("Failed to get clients from Hyprland {}. ", err))
+                        .unwrap_or_default();
+                    Self::fetch_rule_state(&config, &mut rule_state, socket_path);
+                    Some(config.resolve_sound(&clients, data, &mut rule_state))
+                }
+                (Some(("bell", data)), None) => {
+                    Some(config.resolve_sound(&[], data, &mut rule_state))
+                }
+                (Some((ev_type, data)), Some(socket_path)) => {
+                    Self::update_rule_state(&config, &mut rule_state, socket_path, ev_type, data);
+                    None
+                }
+                _ => None,
+            };
+            println!("{}", replay::format_decision(&event, resolved.as_ref()));
+        }
+        Ok(())
+    }
+
+    /// Print which rule every current window would match, and what it would play, as a table.
+    pub fn explain(args: &Args) -> Result<(), AppError> {
+        let config = Self::load_config(args.config.as_deref())?;
+        let (socket_path, _) = Self::init_hyprland_socket_path(args)?;
+        let clients = HyprClient::get_clients(&socket_path)?;
+        let active_window = match config.uses_active_window() {
+            true => Some(HyprActiveWindow::get(&socket_path)?),
+            false => None,
+        };
+        let monitors = match config.uses_monitors() {
+            true => Some(HyprMonitor::get(&socket_path)?),
+            false => None,
+        };
+        print!(
+            "{}",
+            explain::explain(
+                &config,
+                &clients,
+                active_window.as_ref(),
+                monitors.as_deref()
+            )
+        );
+        Ok(())
+    }
+
+    /// Block until everything appended to `sink` has been played.
+    fn wait_for_playback(sink: &Sink) {
+        sink.sleep_until_end();
+    }
+
+    /// Print every event from Hyprland's event socket to stdout as it arrives, without handling
+    /// it, until the socket is closed.
+    pub fn tail(args: &Args) -> Result<(), AppError> {
+        let (_, socket2_path) = Self::init_hyprland_socket_path(args)?;
+        let mut socket2 = UnixStream::connect(&socket2_path)?;
+        Self::tail_events(&mut socket2, &mut io::stdout().lock())
+    }
+
+    /// Write every event read from `socket` to `out` in a line. Reaching the end of `socket` ends
+    /// tailing without an error.
+    fn tail_events(socket: &mut impl Read, out: &mut impl Write) -> Result<(), AppError> {
+        loop {
+            match Self::get_event(socket, config::DEFAULT_MAX_EVENT_BYTES) {
+                Ok(event) => {
+                    writeln!(out, "{event}")?;
+                    out.flush()?;
+                }
+                Err(AppError::IOError(err)) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Ok(());
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Read an event line from `socket`. Lines longer than `max_len` bytes are skipped with a
+    /// warning, returning the next line instead. A `max_len` of 0 means no limit.
+    pub fn get_event(socket: &mut impl Read, max_len: usize) -> Result<String, AppError> {
         trace!("Waiting for an event");
         let mut buffer = Vec::new();
+        let mut discarding = false;
         loop {
             let mut character_buf = [0u8; 4];
-            socket.read(&mut character_buf[0..1])?;
+            socket.read_exact(&mut character_buf[0..1])?;
             trace!("Read byte {:02X}", character_buf[0]);
 
             // Check length of current UTF-8 character.
@@ -161,13 +762,30 @@ impl App {
             // is present. It's pretty weird because IMHO read() should just return when 0-sized
             // slice is passed, but anyway it is what it is.
             if len > 1 {
-                socket.read(&mut character_buf[1..len])?;
+                socket.read_exact(&mut character_buf[1..len])?;
             }
             let character = &character_buf[0..len];
             trace!("character: {:02X?}", character);
-            if character == &[0xA] {
+            if character == [0xA] {
+                if discarding {
+                    discarding = false;
+                    continue;
+                }
                 break;
             }
+            if discarding {
+                continue;
+            }
+            if max_len != 0 && buffer.len() + len > max_len {
+                warn!(
+                    "Discarding an event longer than {} bytes, starting with {:?}",
+                    max_len,
+                    String::from_utf8_lossy(&buffer[..buffer.len().min(64)])
+                );
+                buffer.clear();
+                discarding = true;
+                continue;
+            }
             buffer.extend_from_slice(character);
         }
         Ok(String::from_utf8_lossy(&buffer).to_string())
@@ -175,72 +793,1692 @@ impl App {
 
     pub fn run(&mut self) -> Result<(), AppError> {
         let mut socket2 = UnixStream::connect(&self.socket2_path)?;
-        loop {
-            let event = self.get_event(&mut socket2)?;
-            debug!("{}", event);
+        self.socket2_connected.store(true, Ordering::Relaxed);
+        let connected = self.socket2_connected.clone();
+        let (sender, receiver) = mpsc::channel();
 
-            // The response is always in format "event_type>>data" according to Hyprland's
-            // documentation (https://wiki.hypr.land/IPC/#xdg_runtime_dirhyprhissocket2sock).
-            let Some((ev_type, data)) = event.split_once(">>") else {
-                warn!("Weird response from socket2: {}", event);
-                continue;
-            };
-            trace!("ev_type = {ev_type}");
-            trace!("data = {data}");
-
-            match ev_type {
-                "bell" => {
-                    let mut sfx_path = None;
-                    let mut volume = None;
-                    match HyprClient::get_clients(&self.socket_path) {
-                        Ok(clients) => {
-                            for rule in &self.config.rules {
-                                if HyprClient::match_rule(&clients, data, rule) {
-                                    sfx_path = Some(rule.sound.clone());
-                                    volume = Some(rule.volume);
-                                    break;
-                                }
-                            }
-                        }
-                        Err(err) => {
-                            warn!(
-                                "Failed to get clients from Hyprland {}. Rules will not be matched. ",
-                                err
-                            );
+        if let Err(err) =
+            control::socket_path().and_then(|path| control::listen(&path, sender.clone()))
+        {
+            warn!("Control socket is unavailable: {}", err);
+        }
+
+        let max_event_bytes = self.config.max_event_bytes;
+        thread::spawn(move || {
+            loop {
+                match Self::get_event(&mut socket2, max_event_bytes) {
+                    Ok(event) => {
+                        if sender.send(AppMessage::Event(event)).is_err() {
+                            break;
                         }
                     }
-                    let sfx_path = sfx_path.unwrap_or(self.config.sound.clone());
-                    let volume = volume.unwrap_or(self.config.volume);
+                    Err(err) => {
+                        connected.store(false, Ordering::Relaxed);
+                        let _ = sender.send(AppMessage::Disconnected(err));
+                        break;
+                    }
+                }
+            }
+        });
 
-                    // Missing sfx_path = no sound
-                    if let Some(sfx_path) = sfx_path {
-                        self.play_sound(&sfx_path, volume);
+        loop {
+            let message = match self.next_deadline() {
+                Some(deadline) => {
+                    receiver.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                }
+                None => receiver.recv().map_err(RecvTimeoutError::from),
+            };
+            match message {
+                Ok(AppMessage::Event(event)) => {
+                    self.last_event = Some(Instant::now());
+                    if let Some(ref mut recorder) = self.recorder
+                        && let Err(err) = recorder.record(&event)
+                    {
+                        warn!("Failed to record event: {}", err);
                     }
+                    self.handle_event(&event);
+                }
+                Ok(AppMessage::Control(line, reply)) => {
+                    let _ = reply.send(self.handle_control(&line));
                 }
-                _ => {
-                    debug!("Unhandled event type: {ev_type}");
+                Ok(AppMessage::Disconnected(err)) => return Err(err),
+                Err(RecvTimeoutError::Timeout) => {
+                    let now = Instant::now();
+                    self.handle_timers(now);
+                    if self.is_idle(now) {
+                        info!(
+                            "No events for {:?}, exiting",
+                            self.config.idle_exit().unwrap()
+                        );
+                        return Ok(());
+                    }
                 }
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
             }
         }
     }
 
-    fn play_sound(&mut self, sfx_path: &PathBuf, volume: f32) {
-        if let Some(data) = self.sound_map.get(sfx_path) {
-            match Decoder::try_from(io::Cursor::new(data.clone())) {
-                Ok(audio) => {
-                    self.audio_stream_handle
-                        .mixer()
-                        .add(audio.amplify_normalized(volume));
-                }
-                Err(err) => {
+    /// When the event loop should wake up for [`App::handle_timers`] even without any message.
+    fn next_deadline(&self) -> Option<Instant> {
+        let debounce = self.window_states.next_deadline(self.config.debounce());
+        let delayed = self.delayed.next_deadline();
+        // Delayed sounds keep onionbell running, so waking up for being idle meanwhile would spin.
+        let idle = self.idle_deadline().filter(|_| self.delayed.is_empty());
+        debounce.into_iter().chain(delayed).chain(idle).min()
+    }
+
+    /// When onionbell exits for `idle_exit_ms` if no event arrives before.
+    fn idle_deadline(&self) -> Option<Instant> {
+        Some(self.last_event.unwrap_or(self.started) + self.config.idle_exit()?)
+    }
+
+    /// Whether onionbell has been idle for `idle_exit_ms` at `now`. Delayed sounds that are still
+    /// to be played keep it running.
+    fn is_idle(&self, now: Instant) -> bool {
+        self.delayed.is_empty() && self.idle_deadline().is_some_and(|x| x <= now)
+    }
+
+    fn handle_timers(&mut self, now: Instant) {
+        self.settle_window_states(now);
+        self.play_delayed(now);
+    }
+
+    /// Play the delayed sounds that are due at `now`. Whether sounds are enabled is checked now
+    /// rather than when they were delayed.
+    fn play_delayed(&mut self, now: Instant) {
+        for delayed in self.delayed.take_due(now) {
+            if !self.enabled {
+                debug!(
+                    "Sounds are disabled, dropping the delayed {}",
+                    delayed.request.path.to_string_lossy()
+                );
+                continue;
+            }
+            self.play_now(delayed.request, delayed.cooldown, now);
+        }
+    }
+
+    /// Play the `event_sound`s of the window states that have settled at `now`.
+    fn settle_window_states(&mut self, now: Instant) {
+        let settled = self
+            .window_states
+            .settle(self.config.debounce(), now)
+            .into_iter()
+            .map(|(key, state)| (key, state.clone()))
+            .collect::<Vec<_>>();
+        for ((ev_type, address), state) in settled {
+            debug!("{ev_type} of window {address:?} settled to {state}");
+            // The data of the events that reported the state.
+            let data = match ev_type.as_str() {
+                "fullscreen" => state,
+                _ => format!("{address},{state}"),
+            };
+            if let Some(event_sound) = self.config.event_sound(&ev_type, &data)
+                && self.enabled
+            {
+                let (sfx_path, volume) = (event_sound.sound.clone(), event_sound.volume);
+                self.play_sound(&sfx_path, volume, None);
+            }
+        }
+    }
+
+    fn handle_event(&mut self, event: &str) {
+        // Events that aren't handled are dropped before anything else, including logging.
+        if let Some((ev_type, _)) = hypr::parse_event(event)
+            && !self.handled_events.contains(ev_type)
+        {
+            return;
+        }
+        self.detailed_logs = match self.log_limiter.check(Instant::now()) {
+            Some(0) => true,
+            Some(suppressed) => {
+                debug!("{suppressed} events handled without detailed logs");
+                true
+            }
+            None => false,
+        };
+        self.dispatch_event(event);
+    }
+
+    fn dispatch_event(&mut self, event: &str) {
+        detail!(self, debug, "{}", event);
+
+        let Some((ev_type, data)) = hypr::parse_event(event) else {
+            warn!("Weird response from socket2: {}", event);
+            return;
+        };
+        detail!(self, trace, "ev_type = {ev_type}");
+        detail!(self, trace, "data = {data}");
+
+        let rule_state_event = Self::update_rule_state(
+            &self.config,
+            &mut self.rule_state,
+            &self.socket_path,
+            ev_type,
+            data,
+        );
+
+        // Sounds of debounced states play once they settle instead.
+        if let Some(event_sound) = self.config.event_sound(ev_type, data)
+            && self.enabled
+            && !(self.config.debounce_ms > 0 && DEBOUNCED_EVENTS.contains(&ev_type))
+        {
+            let (sfx_path, volume) = (event_sound.sound.clone(), event_sound.volume);
+            self.play_sound(&sfx_path, volume, None);
+        }
+
+        match ev_type {
+            "bell" if !self.enabled => {
+                detail!(self, debug, "Sounds are disabled, ignoring the bell")
+            }
+            "bell" if self.muted.contains(data) => {
+                detail!(self, debug, "Window {data} is muted, ignoring the bell")
+            }
+            "bell" => {
+                let clients = HyprClient::get_clients(&self.socket_path).unwrap_or_else(|err| {
                     warn!(
-                        "Failed to play audio {}: {}",
-                        sfx_path.to_string_lossy(),
+                        "Failed to get clients from Hyprland {}. Rules will not be matched. ",
                         err
                     );
-                    self.sound_map.remove(sfx_path);
+                    Vec::new()
+                });
+                if self.config.is_ignored(HyprClient::find(&clients, data)) {
+                    detail!(
+                        self,
+                        debug,
+                        "Bell from window {data} with an ignored class, staying quiet"
+                    );
+                    return;
+                }
+                let first = self
+                    .first_bell
+                    .advance(self.config.first_bell_timeout(), Instant::now());
+                if first && let Some(sfx_path) = self.config.first_bell_sound.clone() {
+                    detail!(self, debug, "First bell of the session");
+                    let volume =
+                        self.escalate(self.config.volume * self.config.ext_volume(&sfx_path));
+                    self.play_sound_after(
+                        Duration::ZERO,
+                        Vec::new(),
+                        None,
+                        &sfx_path,
+                        volume,
+                        None,
+                    );
+                    return;
+                }
+                if !clients.is_empty() && clients.iter().all(|x| x.address.get(2..) != Some(data)) {
+                    detail!(
+                        self,
+                        debug,
+                        "Bell from unknown window {data}, matching orphan rules"
+                    );
+                }
+                Self::fetch_rule_state(&self.config, &mut self.rule_state, &self.socket_path);
+                // Checked before resolving, so a quiet bell doesn't count towards `nth` or
+                // `once_per_window`.
+                if self
+                    .config
+                    .rules
+                    .iter()
+                    .any(|x| x.window_cooldown_ms.is_some())
+                    && self.is_cooling(
+                        self.config.matching_rule(&clients, data, &self.rule_state),
+                        data,
+                        Instant::now(),
+                    )
+                {
+                    detail!(
+                        self,
+                        debug,
+                        "Rule is cooling down for window {data}, staying quiet"
+                    );
+                    return;
+                }
+                let resolved = self
+                    .config
+                    .resolve_sound(&clients, data, &mut self.rule_state);
+                detail!(self, trace, "resolved = {resolved:?}");
+                if self.emit_json {
+                    let value = emit::bell_json(
+                        data,
+                        HyprClient::find(&clients, data),
+                        resolved.rule.map(|i| &self.config.rules[i]),
+                        &resolved,
+                    );
+                    if let Err(err) = emit::write_line(&mut io::stdout().lock(), &value) {
+                        warn!("Failed to emit JSON: {}", err);
+                    }
+                }
+                #[cfg(feature = "mqtt")]
+                if let Some(ref publisher) = self.mqtt {
+                    let client = HyprClient::find(&clients, data);
+                    let rule = resolved.rule.map(|i| &self.config.rules[i]);
+                    let value = emit::bell_json(data, client, rule, &resolved);
+                    publisher.publish(
+                        publisher.topic(client, resolved.rule, rule),
+                        value.to_string().into_bytes(),
+                    );
+                }
+                if resolved
+                    .rule
+                    .is_some_and(|i| self.config.rules[i].terminal_bell == Some(true))
+                {
+                    Self::ring_terminal_bell();
+                }
+
+                let volume = resolved.volume * self.fatigue(resolved.rule, Instant::now());
+                let delay = resolved
+                    .rule
+                    .map(|i| self.config.rules[i].delay(&mut self.rng))
+                    .unwrap_or_default();
+                let devices = self.config.devices(
+                    resolved.rule,
+                    HyprClient::find(&clients, data),
+                    self.rule_state.monitors.as_deref(),
+                );
+                let cooldown = resolved
+                    .rule
+                    .filter(|i| self.config.rules[*i].window_cooldown_ms.is_some())
+                    .map(|i| (i, data.to_string()));
+
+                // Missing sfx_path and command = no sound
+                if let Some(command) = resolved.command {
+                    let volume = self.escalate(volume);
+                    let volume = config::floor_volume(volume, resolved.volume_floor);
+                    let request = PlayRequest {
+                        devices,
+                        ..self.play_request(
+                            PathBuf::from(&command),
+                            Sound::Command(command),
+                            volume,
+                        )
+                    };
+                    self.enqueue_after(delay, request, cooldown);
+                } else if let Some(sfx_path) = resolved.sound {
+                    // Composed before escalation, which clamps the result.
+                    let volume = self.escalate(volume * self.config.ext_volume(&sfx_path));
+                    let floor = resolved.volume_floor;
+                    self.play_sound_after(delay, devices, cooldown, &sfx_path, volume, floor);
+                }
+            }
+            // changefloatingmode>>WINDOWADDRESS,FLOATING and fullscreen>>0/1, where the latter
+            // is about the focused window.
+            "changefloatingmode" | "fullscreen" => {
+                let (address, state) = match ev_type {
+                    "fullscreen" => (self.focused.as_deref().unwrap_or_default(), data),
+                    _ => data.rsplit_once(',').unwrap_or(("", data)),
+                };
+                let now = Instant::now();
+                self.window_states.observe(
+                    (ev_type.to_string(), address.to_string()),
+                    state.to_string(),
+                    now,
+                );
+                self.settle_window_states(now);
+            }
+            // activewindowv2>>WINDOWADDRESS
+            "activewindowv2" => self.focused = Some(data.to_string()),
+            // A new monitor may bring the speakers of a device that failed to open.
+            "monitoradded" | "monitoraddedv2" => self.audio_worker.reset(),
+            "closewindow" => {
+                self.window_states
+                    .forget_where(|(_, address)| address == data);
+                if self.muted.remove(data) {
+                    self.remember_muted();
+                }
+            }
+            _ if rule_state_event => {}
+            _ => {
+                detail!(self, debug, "Unhandled event type: {ev_type}");
+            }
+        }
+    }
+
+    /// Keep `state` up to date with the event `ev_type` with `data`, for rules that look at more
+    /// than `hyprctl clients`. Returns whether the event is one of those that rules follow.
+    fn update_rule_state(
+        config: &Config,
+        state: &mut RuleState,
+        socket_path: &Path,
+        ev_type: &str,
+        data: &str,
+    ) -> bool {
+        match ev_type {
+            // The cached active window is fetched again on the next bell.
+            "activewindow" | "activewindowv2" => state.active_window = None,
+            // The cached monitors are fetched again on the next bell. Reloading the config may
+            // change their resolution or scale.
+            "monitoradded" | "monitoraddedv2" | "monitorremoved" | "monitorremovedv2"
+            | "configreloaded" => state.monitors = None,
+            // openwindow>>WINDOWADDRESS,WORKSPACENAME,WINDOWCLASS,WINDOWTITLE
+            "openwindow" => {
+                if !config.tracks_geometry() {
+                    return true;
+                }
+                let address = data.split(',').next().unwrap_or(data);
+                match HyprClient::get_clients(socket_path) {
+                    Ok(clients) => match HyprClient::find(&clients, address) {
+                        Some(client) => {
+                            trace!("initial geometry of {address}: {:?}", client.geometry());
+                            state
+                                .initial_geometry
+                                .insert(address.to_string(), client.geometry());
+                        }
+                        None => debug!("Opened window {address} not found"),
+                    },
+                    Err(err) => warn!("Failed to get clients from Hyprland {}. ", err),
+                }
+            }
+            "urgent" => state.mark_urgent(data, config.urgent_ttl(), Instant::now()),
+            "closewindow" => state.forget_window(data),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Fetch the focused window and the monitors into `state` when rules look at them and they're
+    /// not cached yet.
+    fn fetch_rule_state(config: &Config, state: &mut RuleState, socket_path: &Path) {
+        if config.uses_active_window() && state.active_window.is_none() {
+            state.active_window = HyprActiveWindow::get(socket_path)
+                .inspect_err(|err| warn!("Failed to get the active window: {}", err))
+                .ok();
+        }
+        if config.uses_monitors() && state.monitors.is_none() {
+            state.monitors = HyprMonitor::get(socket_path)
+                .inspect_err(|err| warn!("Failed to get monitors: {}", err))
+                .ok();
+        }
+    }
+
+    /// Assemble the status reported by the `health` control command.
+    fn health(&self) -> Health {
+        Health {
+            hyprland: UnixStream::connect(&self.socket_path).is_ok(),
+            socket2: self.socket2_connected.load(Ordering::Relaxed),
+            last_event: self.last_event.map(|x| x.elapsed()),
+            audio: self.audio_stream_handle.is_some() && self.audio_worker.is_running(),
+            sounds_loaded: self.sound_map.len(),
+            sounds_total: self.config.sound_paths().collect::<HashSet<_>>().len(),
+            rules: self.config.rules.len(),
+        }
+    }
+
+    fn handle_control(&mut self, line: &str) -> Result<String, AppError> {
+        debug!("Control command: {}", line);
+        match ControlCommand::parse(line)? {
+            ControlCommand::Stats => Ok(self
+                .stats
+                .lock()
+                .unwrap_or_else(|x| x.into_inner())
+                .to_string()),
+            ControlCommand::Sounds => Ok(sound::format_sound_list(&self.sound_map)),
+            ControlCommand::Health => Ok(self.health().to_string()),
+            ControlCommand::ReloadSounds => {
+                Ok(format!("reloaded {} sounds\n", self.reload_sounds()))
+            }
+            ControlCommand::ReloadFile(name) => self.reload_file(&name),
+            ControlCommand::Play(path, volume) => self.play_on_demand(&path, volume),
+            ControlCommand::Enable => Ok(self.set_enabled(true)),
+            ControlCommand::Disable => Ok(self.set_enabled(false)),
+            ControlCommand::MuteActive => self.mute_active(true),
+            ControlCommand::UnmuteActive => self.mute_active(false),
+            ControlCommand::DumpState => {
+                Ok(format!("{}\n", self.snapshot(Instant::now()).to_json()))
+            }
+            ControlCommand::RestoreState(path) => {
+                let snapshot = Snapshot::from_json(&fs::read_to_string(path)?)?;
+                self.restore(snapshot, Instant::now());
+                Ok("state restored\n".to_string())
+            }
+        }
+    }
+
+    /// Play the sound at `sfx_path` for the `play` control command, at `volume` or the global
+    /// `volume`. Sounds that are not preloaded are loaded for this time only. Returns the reply to
+    /// the control command.
+    fn play_on_demand(&mut self, sfx_path: &Path, volume: Option<f32>) -> Result<String, AppError> {
+        if !self.enabled {
+            return Ok("sounds are disabled\n".to_string());
+        }
+        let sound = match self.sound_map.get(sfx_path) {
+            Some(sound) => sound.clone(),
+            None => {
+                debug!("Loading {} on demand", sfx_path.to_string_lossy());
+                Self::load_sound(sfx_path, self.config.disk_cache)?
+            }
+        };
+        let volume = volume.unwrap_or(self.config.volume) * self.config.ext_volume(sfx_path);
+        self.enqueue(sfx_path.to_path_buf(), sound, volume);
+        Ok(format!("playing {}\n", sfx_path.to_string_lossy()))
+    }
+
+    /// Read the rule file `name` in `conf.d` again and load the sounds that are new, keeping the
+    /// rest of the config. Returns the reply to the control command.
+    fn reload_file(&mut self, name: &str) -> Result<String, AppError> {
+        let count = self.config.reload_file(name)?;
+        for warning in self.config.validate() {
+            warn!("{}", warning);
+        }
+        // Rules may have moved, so what's remembered by their index no longer applies.
+        self.rule_state.counters.clear();
+        self.rule_state.fired.clear();
+        self.fatigue.clear();
+        self.window_cooldowns.clear();
+        self.handled_events = self.config.handled_events();
+
+        let new_sounds = self
+            .config
+            .sound_paths()
+            .filter(|x| !self.sound_map.contains_key(*x))
+            .cloned()
+            .collect::<HashSet<_>>();
+        let mut loaded = 0;
+        for sfx_path in new_sounds {
+            match Self::load_sound(&sfx_path, self.config.disk_cache) {
+                Ok(sound) => {
+                    self.sound_map.insert(sfx_path, sound);
+                    loaded += 1;
                 }
+                Err(err) => warn!(
+                    "Failed to read or decode source {}: {}",
+                    sfx_path.to_string_lossy(),
+                    err
+                ),
             }
         }
+        self.audio_worker.reset();
+        info!("Reloaded {count} rules from {name}, and {loaded} new sounds");
+        Ok(format!(
+            "reloaded {count} rules from {name}, and {loaded} new sounds\n"
+        ))
+    }
+
+    /// Write a BEL to stdout for rules with `terminal_bell`, if it's a terminal.
+    fn ring_terminal_bell() {
+        let mut stdout = io::stdout().lock();
+        let is_terminal = stdout.is_terminal();
+        match util::ring_terminal_bell(&mut stdout, is_terminal) {
+            Ok(true) => trace!("rang the terminal bell"),
+            Ok(false) => debug!("stdout is not a terminal, skipping the terminal bell"),
+            Err(err) => warn!("Failed to ring the terminal bell: {}", err),
+        }
+    }
+
+    /// What the app remembers between bells, for `dump-state`. Sounds waiting for their delay
+    /// are not included.
+    pub fn snapshot(&self, now: Instant) -> Snapshot {
+        let mut fired = self.rule_state.fired.keys().cloned().collect::<Vec<_>>();
+        fired.sort();
+        Snapshot {
+            escalation: self.escalation.snapshot(now),
+            fatigue: self
+                .fatigue
+                .iter()
+                .map(|(i, x)| (*i, x.snapshot(now)))
+                .collect(),
+            window_cooldowns: self
+                .window_cooldowns
+                .iter()
+                .filter_map(|(i, x)| Some((*i, x.snapshot(now)?)))
+                .collect(),
+            first_bell_ms_ago: self.first_bell.snapshot(now),
+            counters: self.rule_state.counters.clone(),
+            fired,
+            urgent_ms_ago: self
+                .rule_state
+                .urgent
+                .iter()
+                .map(|(address, x)| (address.clone(), snapshot::ms_ago(*x, now)))
+                .collect(),
+        }
+    }
+
+    /// Replace what the app remembers between bells with `snapshot`, for `restore-state`. Rules
+    /// that don't exist in the config are skipped.
+    pub fn restore(&mut self, mut snapshot: Snapshot, now: Instant) {
+        snapshot.retain_rules(self.config.rules.len());
+        self.escalation = Escalation::restore(&snapshot.escalation, now);
+        self.fatigue = snapshot
+            .fatigue
+            .iter()
+            .map(|(i, x)| (*i, FatigueState::restore(x, now)))
+            .collect();
+        self.window_cooldowns = snapshot
+            .window_cooldowns
+            .iter()
+            .map(|(i, x)| (*i, WindowCooldown::restore(x, now)))
+            .collect();
+        self.first_bell = FirstBell::restore(snapshot.first_bell_ms_ago, now);
+        self.rule_state.counters = snapshot.counters;
+        self.rule_state.fired.clear();
+        for (i, key) in snapshot.fired {
+            self.rule_state.fire(i, key);
+        }
+        self.rule_state.urgent = snapshot
+            .urgent_ms_ago
+            .into_iter()
+            .filter_map(|(address, x)| Some((address, snapshot::instant_ago(x, now)?)))
+            .collect();
+        info!("State restored");
+    }
+
+    /// Mute or unmute bells from the focused window, for `mute-active` and `unmute-active`.
+    /// Returns the reply to the control command.
+    fn mute_active(&mut self, mute: bool) -> Result<String, AppError> {
+        let window = HyprActiveWindow::get(&self.socket_path)?;
+        let Some(address) = window.address.strip_prefix("0x") else {
+            return Ok("no window is focused\n".to_string());
+        };
+        let changed = if mute {
+            self.muted.insert(address.to_string())
+        } else {
+            self.muted.remove(address)
+        };
+        let window = format!("{} ({address})", window.class);
+        if !changed {
+            let status = if mute { "already muted" } else { "not muted" };
+            return Ok(format!("{window} is {status}\n"));
+        }
+        let status = if mute { "muted" } else { "unmuted" };
+        info!("Window {window} is {status}");
+        self.remember_muted();
+        Ok(format!("{status} {window}\n"))
+    }
+
+    /// Write the muted windows to their state file, with `persist_mutes`.
+    fn remember_muted(&self) {
+        if !self.config.persist_mutes {
+            return;
+        }
+        let Some(path) = self.state_dir.as_deref().map(state::muted_path) else {
+            warn!("Neither XDG_STATE_HOME nor HOME is set to remember muted windows");
+            return;
+        };
+        if let Err(err) = state::write_muted(&path, &self.muted) {
+            warn!("Failed to write {}: {}", path.to_string_lossy(), err);
+        }
+    }
+
+    /// Enable or disable sounds and remember it in the state file. Returns the reply to the
+    /// control command.
+    fn set_enabled(&mut self, enabled: bool) -> String {
+        self.enabled = enabled;
+        let status = if enabled { "enabled" } else { "disabled" };
+        info!("Sounds are {status}");
+        let Some(path) = self.state_dir.as_deref().map(state::enabled_path) else {
+            return format!(
+                "{status}, but neither XDG_STATE_HOME nor HOME is set to remember it\n"
+            );
+        };
+        match state::write_enabled(&path, enabled) {
+            Ok(()) => format!("{status}\n"),
+            Err(err) => {
+                warn!("Failed to write {}: {}", path.to_string_lossy(), err);
+                format!("{status}, but failed to remember it: {err}\n")
+            }
+        }
+    }
+
+    /// Whether a bell from `address` at `now` matched by the rule at index `rule` stays quiet
+    /// because of the rule's `window_cooldown_ms`.
+    fn is_cooling(&self, rule: Option<usize>, address: &str, now: Instant) -> bool {
+        let Some(i) = rule else {
+            return false;
+        };
+        let (Some(cooldown_ms), Some(cooldown)) = (
+            self.config.rules[i].window_cooldown_ms,
+            self.window_cooldowns.get(&i),
+        ) else {
+            return false;
+        };
+        cooldown.is_cooling(address, Duration::from_millis(cooldown_ms), now)
+    }
+
+    /// Register a bell at `now` matched by the rule at index `rule`, and return the volume factor
+    /// of the rule's `fatigue`, or 1.0 if it has none.
+    fn fatigue(&mut self, rule: Option<usize>, now: Instant) -> f32 {
+        let Some(i) = rule else {
+            return 1.0;
+        };
+        let Some(ref config) = self.config.rules[i].fatigue else {
+            return 1.0;
+        };
+        let factor = self.fatigue.entry(i).or_default().advance(config, now);
+        trace!("fatigue factor of rule #{i} = {factor}");
+        factor
+    }
+
+    /// Apply escalation to the volume of a bell, if it's enabled. The result is clamped to 1.0,
+    /// which is reported the first time it happens.
+    fn escalate(&mut self, volume: f32) -> f32 {
+        let factor = match self.config.escalate {
+            Some(ref escalate) => {
+                let factor = self.escalation.advance(escalate, Instant::now());
+                trace!("escalation factor = {factor}");
+                factor
+            }
+            None => 1.0,
+        };
+        let (composed, clamped) = escalation::compose_volume(volume, factor);
+        if clamped && !self.clamp_reported {
+            warn!(
+                "Volume {volume} escalated by a factor of {factor} would clip, so it's clamped to \
+                1.0. Lower `volume` to leave room for escalation. This is only reported once."
+            );
+            self.clamp_reported = true;
+        }
+        composed
+    }
+
+    /// Hand the sound at `sfx_path` over to the audio worker, with the `ext_volume` of its
+    /// extension applied, and `floor` after that. Unknown paths are ignored, as the reason is
+    /// already logged when loading sounds.
+    fn play_sound(&mut self, sfx_path: &PathBuf, volume: f32, floor: Option<f32>) {
+        let volume = volume * self.config.ext_volume(sfx_path);
+        self.play_sound_after(Duration::ZERO, Vec::new(), None, sfx_path, volume, floor);
+    }
+
+    /// Like [`App::play_sound`], on `devices` instead of the default output and after `delay`,
+    /// with the `ext_volume` already applied to `volume`. The `window_cooldown_ms` of `cooldown`
+    /// starts when it plays.
+    fn play_sound_after(
+        &mut self,
+        delay: Duration,
+        devices: Vec<String>,
+        cooldown: Option<CooldownKey>,
+        sfx_path: &PathBuf,
+        volume: f32,
+        floor: Option<f32>,
+    ) {
+        if let Some(sound) = self.sound_map.get(sfx_path) {
+            let volume = config::floor_volume(volume, floor);
+            let request = PlayRequest {
+                devices,
+                ..self.play_request(sfx_path.clone(), sound.clone(), volume)
+            };
+            self.enqueue_after(delay, request, cooldown);
+        }
+    }
+
+    /// Hand `request` to the audio worker after `delay`, or right away without one. Silent
+    /// requests are dropped, so they're never decoded. The `window_cooldown_ms` of `cooldown`
+    /// starts when it's handed over.
+    fn enqueue_after(
+        &mut self,
+        delay: Duration,
+        request: PlayRequest,
+        cooldown: Option<CooldownKey>,
+    ) {
+        if request.volume == 0.0 {
+            trace!("volume is 0, skipping {}", request.path.to_string_lossy());
+            return;
+        }
+        let now = Instant::now();
+        if delay.is_zero() {
+            self.play_now(request, cooldown, now);
+            return;
+        }
+        trace!("playing {} in {delay:?}", request.path.to_string_lossy());
+        self.delayed
+            .add(now + delay, DelayedSound { request, cooldown });
+    }
+
+    /// Hand `request` to the audio worker, and start the cooldown of `cooldown` at `now` if it's
+    /// accepted.
+    fn play_now(&mut self, request: PlayRequest, cooldown: Option<CooldownKey>, now: Instant) {
+        if self.audio_worker.play(request)
+            && let Some((i, address)) = cooldown
+        {
+            self.window_cooldowns
+                .entry(i)
+                .or_default()
+                .record(&address, now);
+        }
+    }
+
+    /// Hand `sound` to the audio worker. `path` is only used in logs.
+    fn enqueue(&self, path: PathBuf, sound: Sound, volume: f32) {
+        self.audio_worker
+            .play(self.play_request(path, sound, volume));
+    }
+
+    /// A request to play `sound` on the default output with the playback settings of the config.
+    fn play_request(&self, path: PathBuf, sound: Sound, volume: f32) -> PlayRequest {
+        PlayRequest {
+            path,
+            sound,
+            volume,
+            sample_rate: self.config.sample_rate.map(NonZeroU32::get),
+            interrupt: self.config.interrupt,
+            crossfade: self.config.crossfade(),
+            prelude: self.config.prelude(),
+            downmix_mono: self.config.downmix_mono == Some(true),
+            retries: self.config.play_retries,
+            devices: Vec::new(),
+        }
+    }
+}
+
+#[allow(unused)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reload_sound_map() {
+        let dir = env::temp_dir().join(format!("onionbell-app-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let a = dir.join("a.wav");
+        let b = dir.join("b.wav");
+        fs::write(&a, b"old a").unwrap();
+        fs::write(&b, b"old b").unwrap();
+        let config = Config::from_source(format!(
+            "sound = {:?}\n[[rule]]\nsounds = [{:?}, \"builtin:ding\"]",
+            a, b
+        ))
+        .unwrap();
+        let mut sound_map = App::load_sounds(&config);
+        assert_eq!(sound_map[&a], Sound::Encoded(b"old a".to_vec()));
+
+        // Replaced files are picked up, and ones that became unreadable keep their old data.
+        fs::write(&a, b"new a").unwrap();
+        fs::remove_file(&b).unwrap();
+        assert_eq!(App::reload_sound_map(&config, &mut sound_map), 2);
+        assert_eq!(sound_map.len(), 3);
+        assert_eq!(sound_map[&a], Sound::Encoded(b"new a".to_vec()));
+        assert_eq!(sound_map[&b], Sound::Encoded(b"old b".to_vec()));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_max_preload_bytes() {
+        let dir = env::temp_dir().join(format!("onionbell-preload-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let [a, b, c, d] = ["a", "b", "c", "d"].map(|x| dir.join(format!("{x}.wav")));
+        fs::write(&a, [0; 10]).unwrap();
+        fs::write(&b, [0; 10]).unwrap();
+        fs::write(&c, [0; 10]).unwrap();
+        fs::write(&d, [0; 1]).unwrap();
+        let config = Config::from_source(format!(
+            "sound = {:?}\nmax_preload_bytes = 25\n[[rule]]\nsounds = [{:?}, {:?}, \"builtin:ding\", {:?}]",
+            a, b, c, d
+        ))
+        .unwrap();
+        let sound_map = App::load_sounds(&config);
+        assert_eq!(sound_map[&a], Sound::Encoded(vec![0; 10]));
+        assert_eq!(sound_map[&b], Sound::Encoded(vec![0; 10]));
+
+        // Preloading stops at the limit, even for small sounds after it, except builtin ones.
+        assert_eq!(sound_map[&c], Sound::Lazy(c.clone()));
+        assert_eq!(sound_map[&d], Sound::Lazy(d.clone()));
+        assert!(matches!(
+            sound_map[Path::new("builtin:ding")],
+            Sound::Encoded(_)
+        ));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_validate_sound() {
+        let dir = env::temp_dir().join(format!("onionbell-validate-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let valid = dir.join("valid.ogg");
+        let corrupt = dir.join("corrupt.ogg");
+        let data = builtin::get("ding").unwrap();
+        fs::write(&valid, data).unwrap();
+        fs::write(&corrupt, &data[..16]).unwrap();
+
+        assert!(App::validate_sound(&valid).is_ok());
+        assert!(App::validate_sound(&corrupt).is_err());
+        assert!(App::validate_sound(&dir.join("missing.ogg")).is_err());
+        assert!(App::validate_sound(Path::new("builtin:ding")).is_ok());
+        assert!(App::validate_sound(Path::new("builtin:nope")).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_instance_signature() {
+        let args = Args {
+            instance: Some("abc_123".to_string()),
+            instance_file: Some(PathBuf::from("/nonexistent")),
+            ..Default::default()
+        };
+        assert_eq!(App::instance_signature(&args).unwrap(), "abc_123");
+        assert_eq!(
+            App::hyprland_socket_paths(Path::new("/run/user/1000"), "abc_123"),
+            (
+                PathBuf::from("/run/user/1000/hypr/abc_123/.socket.sock"),
+                PathBuf::from("/run/user/1000/hypr/abc_123/.socket2.sock")
+            )
+        );
+
+        let file = env::temp_dir().join(format!("onionbell-instance-{}", std::process::id()));
+        fs::write(&file, "def_456\n").unwrap();
+        let args = Args {
+            instance_file: Some(file.clone()),
+            ..Default::default()
+        };
+        assert_eq!(App::instance_signature(&args).unwrap(), "def_456");
+        let _ = fs::remove_file(&file);
+
+        for signature in ["", "..", "../../etc"] {
+            let args = Args {
+                instance: Some(signature.to_string()),
+                ..Default::default()
+            };
+            assert!(App::instance_signature(&args).is_err());
+        }
+    }
+
+    #[test]
+    fn test_tail_events() {
+        let mut socket =
+            io::Cursor::new("workspace>>2\nbell>>558e91924520\nactivewindow>>kitty,日本語\n");
+        let mut out = Vec::new();
+        App::tail_events(&mut socket, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "workspace>>2\nbell>>558e91924520\nactivewindow>>kitty,日本語\n"
+        );
+    }
+
+    #[test]
+    fn test_long_event() {
+        let long = format!("activewindow>>kitty,{}\n", "x".repeat(100));
+        let mut socket = io::Cursor::new(format!("{long}bell>>558e91924520\n{long}"));
+        assert_eq!(
+            App::get_event(&mut socket, 32).unwrap(),
+            "bell>>558e91924520"
+        );
+        // The rest of the stream is discarded without growing the buffer.
+        assert!(App::get_event(&mut socket, 32).is_err());
+
+        let mut socket = io::Cursor::new(long.clone());
+        assert_eq!(App::get_event(&mut socket, 0).unwrap(), long.trim_end());
+    }
+
+    #[test]
+    fn test_sound_is_directory() {
+        let dir = env::temp_dir();
+        let Err(err) = App::load_sound(&dir, false) else {
+            unreachable!()
+        };
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Expected a sound file, found a directory: {}",
+                dir.to_string_lossy()
+            )
+        );
+        assert!(App::validate_sound(&dir).is_err());
+    }
+
+    #[test]
+    fn test_env_config() {
+        let env_config = Some("sound = \"builtin:knock\"\nvolume = 0.5".to_string());
+        let config = App::load_config_from(None, env_config.clone()).unwrap();
+        assert_eq!(config.sound, Some(PathBuf::from("builtin:knock")));
+        assert_eq!(config.volume, 0.5);
+
+        // --config takes precedence.
+        let path =
+            env::temp_dir().join(format!("onionbell-env-config-{}.toml", std::process::id()));
+        fs::write(&path, "sound = \"builtin:ding\"").unwrap();
+        let config = App::load_config_from(Some(&path), env_config).unwrap();
+        assert_eq!(config.sound, Some(PathBuf::from("builtin:ding")));
+        let _ = fs::remove_file(&path);
+
+        // A broken config is an error rather than falling back to the file.
+        assert!(App::load_config_from(None, Some("volume = 5.0".to_string())).is_err());
+    }
+
+    /// Records play requests instead of playing them.
+    struct RecordingSink(Arc<Mutex<Vec<PlayRequest>>>);
+
+    impl AudioSink for RecordingSink {
+        fn play(&self, request: PlayRequest) -> bool {
+            self.0.lock().unwrap().push(request);
+            true
+        }
+
+        fn reset(&self) {}
+
+        fn is_running(&self) -> bool {
+            true
+        }
+    }
+
+    /// Serve `clients` on a fake Hyprland request socket at `path`. The first of them is the
+    /// focused window.
+    fn fake_hyprland(path: &Path, clients: &'static str) {
+        let _ = fs::remove_file(path);
+        let listener = std::os::unix::net::UnixListener::bind(path).unwrap();
+        let active = serde_json::from_str::<serde_json::Value>(clients)
+            .ok()
+            .and_then(|x| x.get(0).cloned())
+            .unwrap_or_else(|| serde_json::json!({}))
+            .to_string();
+        // Hyprland reports every field of windows, which the tests leave out.
+        let clients = match serde_json::from_str::<Vec<serde_json::Value>>(clients) {
+            Ok(x) => serde_json::Value::from_iter(x.into_iter().map(HyprClient::with_defaults))
+                .to_string(),
+            Err(_) => clients.to_string(),
+        };
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = [0; 64];
+                let len = stream.read(&mut request).unwrap_or(0);
+                let reply = if request[..len].starts_with(b"-j/activewindow") {
+                    active.as_bytes()
+                } else {
+                    clients.as_bytes()
+                };
+                let _ = stream.write_all(reply);
+            }
+        });
+    }
+
+    /// The whole app without hardware: Hyprland is faked on a socket serving `clients`, and sounds
+    /// are recorded instead of played. Events go through [`App::handle_event`], like the ones read
+    /// from socket2.
+    struct Harness {
+        app: App,
+        played: Arc<Mutex<Vec<PlayRequest>>>,
+        socket_path: PathBuf,
+    }
+
+    impl Harness {
+        fn new(name: &str, config: &str, clients: &'static str) -> Harness {
+            let socket_path =
+                env::temp_dir().join(format!("onionbell-hypr-{name}-{}.sock", std::process::id()));
+            fake_hyprland(&socket_path, clients);
+            let (app, played) = test_app(Config::from_source(config).unwrap(), socket_path.clone());
+            Harness {
+                app,
+                played,
+                socket_path,
+            }
+        }
+
+        fn event(&mut self, event: &str) {
+            self.app.handle_event(event);
+        }
+
+        /// The paths and volumes of the sounds played since the last call.
+        fn take_played(&self) -> Vec<(String, f32)> {
+            self.played
+                .lock()
+                .unwrap()
+                .drain(..)
+                .map(|x| (x.path.to_string_lossy().into_owned(), x.volume))
+                .collect()
+        }
+    }
+
+    impl Drop for Harness {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.socket_path);
+        }
+    }
+
+    fn test_app(config: Config, socket_path: PathBuf) -> (App, Arc<Mutex<Vec<PlayRequest>>>) {
+        let played = Arc::new(Mutex::new(Vec::new()));
+        let app = App {
+            socket_path,
+            socket2_path: PathBuf::new(),
+            sound_map: App::load_sounds(&config),
+            log_limiter: RateLimiter::new(config.log_throttle()),
+            detailed_logs: true,
+            handled_events: config.handled_events(),
+            config,
+            audio_stream_handle: None,
+            audio_sink: Sink::new().0,
+            enabled: true,
+            state_dir: None,
+            muted: HashSet::new(),
+            escalation: Escalation::default(),
+            fatigue: HashMap::new(),
+            window_cooldowns: HashMap::new(),
+            first_bell: FirstBell::default(),
+            clamp_reported: false,
+            rule_state: RuleState::default(),
+            stats: Arc::new(Mutex::new(Stats::default())),
+            audio_worker: Box::new(RecordingSink(played.clone())),
+            rng: make_rng(Some(0)),
+            recorder: None,
+            emit_json: false,
+            #[cfg(feature = "mqtt")]
+            mqtt: None,
+            socket2_connected: Arc::new(AtomicBool::new(false)),
+            last_event: None,
+            started: Instant::now(),
+            window_states: StateDebouncer::default(),
+            focused: None,
+            delayed: Schedule::default(),
+        };
+        (app, played)
+    }
+
+    #[test]
+    fn test_bell_plays_sound() {
+        let mut harness = Harness::new(
+            "bell",
+            r#"
+            sound = "builtin:chime"
+
+            [[rule]]
+            class_regex = "^kitty$"
+            sound = "builtin:ding"
+            volume = 0.5
+            "#,
+            r#"[{ "address": "0x558e91924520", "class": "kitty", "workspace": { "id": 1, "name": "1" } }]"#,
+        );
+        harness.event("bell>>558e91924520");
+        harness.event("bell>>deadbeef");
+        harness.event("workspace>>2");
+        assert_eq!(
+            harness.take_played(),
+            [
+                ("builtin:ding".to_string(), 0.5),
+                ("builtin:chime".to_string(), 1.0)
+            ]
+        );
+
+        // Nothing is played while disabled.
+        harness.app.enabled = false;
+        harness.event("bell>>558e91924520");
+        assert!(harness.take_played().is_empty());
+    }
+
+    #[test]
+    fn test_play_on_demand() {
+        let config = Config::from_source("sound = \"builtin:chime\"\nvolume = 0.8").unwrap();
+        let (mut app, played) = test_app(config, PathBuf::from("/nonexistent"));
+        assert_eq!(
+            app.handle_control("play builtin:chime").unwrap(),
+            "playing builtin:chime\n"
+        );
+        // Loaded on demand, without keeping it.
+        assert_eq!(
+            app.handle_control("play builtin:knock 0.4").unwrap(),
+            "playing builtin:knock\n"
+        );
+        assert!(!app.sound_map.contains_key(Path::new("builtin:knock")));
+        assert!(app.handle_control("play /nonexistent/a.wav").is_err());
+        assert!(app.handle_control("play builtin:nothing").is_err());
+
+        app.enabled = false;
+        assert_eq!(
+            app.handle_control("play builtin:chime").unwrap(),
+            "sounds are disabled\n"
+        );
+        assert_eq!(
+            played
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|x| (x.path.clone(), x.volume))
+                .collect::<Vec<_>>(),
+            [
+                (PathBuf::from("builtin:chime"), 0.8),
+                (PathBuf::from("builtin:knock"), 0.4)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pipeline() {
+        let mut harness = Harness::new(
+            "pipeline",
+            r#"
+            sound = "builtin:chime"
+            volume = 0.8
+
+            [[rule]]
+            class_regex = "^foot$"
+            title_regex = "^htop$"
+            sound = "/tmp/onionbell-test-missing.ogg"
+            "#,
+            r#"[
+                { "address": "0x558e9243ab50", "class": "foot", "title": "htop" },
+                { "address": "0x558e9243cd70", "class": "firefox", "title": "Inbox" }
+            ]"#,
+        );
+
+        // A sound that failed to load plays nothing, rather than the global sound.
+        harness.event("bell>>558e9243ab50");
+        assert!(harness.take_played().is_empty());
+
+        // Other events don't play anything.
+        harness.event("activewindow>>foot,htop");
+        assert!(harness.take_played().is_empty());
+        harness.event("bell>>558e9243cd70");
+        assert_eq!(harness.take_played(), [("builtin:chime".to_string(), 0.8)]);
+    }
+
+    #[test]
+    fn test_debounced_event_sound() {
+        let config = r#"
+            debounce_ms = 100
+
+            [[event_sound]]
+            event = "fullscreen"
+            data = "1"
+            sound = "builtin:knock"
+            "#;
+        let mut harness = Harness::new("debounce", config, r#"[]"#);
+        harness.event("activewindowv2>>558e91924520");
+        harness.event("fullscreen>>1");
+        harness.event("fullscreen>>0");
+        harness.event("fullscreen>>1");
+        assert!(harness.take_played().is_empty());
+
+        // It plays once the state has been stable for long enough.
+        harness
+            .app
+            .handle_timers(Instant::now() + Duration::from_millis(200));
+        assert_eq!(harness.take_played(), [("builtin:knock".to_string(), 1.0)]);
+
+        // Closing the window forgets its states.
+        let key = ("fullscreen".to_string(), "558e91924520".to_string());
+        assert_eq!(
+            harness.app.window_states.stable_state(&key),
+            Some(&"1".to_string())
+        );
+        harness.event("closewindow>>558e91924520");
+        assert_eq!(harness.app.window_states.stable_state(&key), None);
+    }
+
+    #[test]
+    fn test_events_allowlist() {
+        let config = r#"
+            sound = "builtin:chime"
+            events = ["bell", "screencast"]
+
+            [[event_sound]]
+            event = "submap"
+            sound = "builtin:knock"
+
+            [[event_sound]]
+            event = "screencast"
+            sound = "builtin:ding"
+            "#;
+        let mut harness = Harness::new("events", config, r#"[]"#);
+        harness.event("submap>>resize");
+        assert!(harness.take_played().is_empty());
+        harness.event("screencast>>1,0");
+        harness.event("bell>>558e91924520");
+        assert_eq!(
+            harness.take_played(),
+            [
+                ("builtin:ding".to_string(), 1.0),
+                ("builtin:chime".to_string(), 1.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_enabled() {
+        let config = r#"
+            sound = "builtin:chime"
+
+            [[event_sound]]
+            event = "submap"
+            sound = "builtin:knock"
+            "#;
+        let mut harness = Harness::new("enabled", config, r#"[]"#);
+        let state_dir = env::temp_dir().join(format!("onionbell-state-{}", std::process::id()));
+        fs::create_dir_all(&state_dir).unwrap();
+        harness.app.state_dir = Some(state_dir.clone());
+
+        assert_eq!(harness.app.handle_control("disable").unwrap(), "disabled\n");
+        assert!(!state::read_enabled(&state::enabled_path(&state_dir)));
+        harness.event("bell>>558e91924520");
+        harness.event("submap>>resize");
+        assert_eq!(
+            harness.app.handle_control("play builtin:chime").unwrap(),
+            "sounds are disabled\n"
+        );
+        assert!(harness.take_played().is_empty());
+
+        assert_eq!(harness.app.handle_control("enable").unwrap(), "enabled\n");
+        assert!(state::read_enabled(&state::enabled_path(&state_dir)));
+        harness.event("bell>>558e91924520");
+        harness.event("submap>>resize");
+        assert_eq!(
+            harness.take_played(),
+            [
+                ("builtin:chime".to_string(), 1.0),
+                ("builtin:knock".to_string(), 1.0)
+            ]
+        );
+        let _ = fs::remove_dir_all(&state_dir);
+    }
+
+    #[test]
+    fn test_health() {
+        let harness = Harness::new("health", "sound = \"builtin:chime\"", r#"[]"#);
+        let health = harness.app.health();
+        assert!(health.hyprland);
+        assert!(!health.socket2);
+        // Tests play no sounds on an output stream.
+        assert!(!health.audio);
+        assert!(!health.ok());
+
+        harness.app.socket2_connected.store(true, Ordering::Relaxed);
+        assert!(harness.app.health().socket2);
+    }
+
+    #[test]
+    fn test_log_throttle() {
+        let config = r#"
+            sound = "builtin:chime"
+            log_throttle_ms = 60000
+            "#;
+        let mut harness = Harness::new("log-throttle", config, r#"[]"#);
+        let max_level = log::max_level();
+        harness.event("bell>>558e91924520");
+        assert!(harness.app.detailed_logs);
+        harness.event("bell>>558e91924520");
+        assert!(!harness.app.detailed_logs);
+        assert_eq!(log::max_level(), max_level);
+        // Throttled events are still handled.
+        assert_eq!(harness.take_played().len(), 2);
+    }
+
+    #[test]
+    fn test_ext_volume_escalation() {
+        let config = r#"
+            sound = "/tmp/onionbell-ext.wav"
+            volume = 0.8
+
+            [ext_volume]
+            wav = 0.5
+
+            [escalate]
+            step = 1.0
+            cap = 3.0
+            window_ms = 60000
+            "#;
+        let mut harness = Harness::new("ext-volume", config, r#"[]"#);
+        harness.app.sound_map.insert(
+            PathBuf::from("/tmp/onionbell-ext.wav"),
+            Sound::Beeps(
+                Config::from_source("[[rule]]\nbeeps = {}").unwrap().rules[0]
+                    .beeps
+                    .clone()
+                    .unwrap(),
+            ),
+        );
+        for _ in 0..3 {
+            harness.event("bell>>558e91924520");
+        }
+        // The factor is composed before escalation clamps the volume.
+        let played = harness.take_played();
+        let volumes = played.iter().map(|(_, x)| *x).collect::<Vec<_>>();
+        assert_eq!(volumes, [0.4, 0.8, 1.0]);
+        assert!(harness.app.clamp_reported);
+    }
+
+    #[test]
+    fn test_state_round_trip() {
+        let config = r#"
+            sound = "builtin:chime"
+            volume = 0.5
+
+            [escalate]
+            step = 0.5
+            cap = 2.0
+            window_ms = 60000
+
+            [[rule]]
+            class_regex = "^kitty$"
+            once_per_window = true
+            sound = "builtin:ding"
+            "#;
+        let clients = r#"[{ "address": "0x558e91924520", "class": "kitty" }]"#;
+        let mut old = Harness::new("state-old", config, clients);
+        old.event("bell>>558e91924520");
+        old.event("bell>>558e91924520");
+        assert_eq!(
+            old.take_played(),
+            [
+                ("builtin:ding".to_string(), 1.0),
+                ("builtin:chime".to_string(), 0.75)
+            ]
+        );
+        let path =
+            env::temp_dir().join(format!("onionbell-test-state-{}.json", std::process::id()));
+        fs::write(&path, old.app.handle_control("dump-state").unwrap()).unwrap();
+
+        // The new process carries on where the old one stopped, with the rule already fired and
+        // the volume escalated.
+        let mut new = Harness::new("state-new", config, clients);
+        assert_eq!(
+            new.app
+                .handle_control(&format!("restore-state {}", path.to_string_lossy()))
+                .unwrap(),
+            "state restored\n"
+        );
+        new.event("bell>>558e91924520");
+        assert_eq!(new.take_played(), [("builtin:chime".to_string(), 1.0)]);
+        assert_eq!(new.app.rule_state.counters, old.app.rule_state.counters);
+        let _ = fs::remove_file(&path);
+
+        // Without the rule, what's remembered for it is dropped.
+        let (mut app, _) = test_app(
+            Config::from_source("sound = \"builtin:chime\"").unwrap(),
+            PathBuf::from("/nonexistent"),
+        );
+        app.restore(old.app.snapshot(Instant::now()), Instant::now());
+        assert!(app.rule_state.counters.is_empty() && app.rule_state.fired.is_empty());
+        assert!(
+            app.handle_control("restore-state /nonexistent/state.json")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_ignore_classes() {
+        let mut harness = Harness::new(
+            "ignore",
+            r#"
+            sound = "builtin:chime"
+            first_bell_sound = "builtin:knock"
+            ignore_classes = ["^Slack$"]
+
+            [[rule]]
+            class_regex = "^Slack$"
+            force = true
+            sound = "builtin:ding"
+
+            [[rule]]
+            orphan = true
+            sound = "builtin:ding"
+            "#,
+            r#"[
+                { "address": "0x558e91924520", "class": "kitty" },
+                { "address": "0x558e9243ab50", "class": "Slack" }
+            ]"#,
+        );
+
+        // Ignored before anything else, even a forced rule or the first bell sound.
+        harness.event("bell>>558e9243ab50");
+        assert!(harness.take_played().is_empty());
+        harness.event("bell>>558e91924520");
+        assert_eq!(harness.take_played(), [("builtin:knock".to_string(), 1.0)]);
+        harness.event("bell>>558e9243ab50");
+        assert!(harness.take_played().is_empty());
+
+        // The class of an orphan is unknown, so it's never ignored.
+        harness.event("bell>>deadbeef");
+        assert_eq!(harness.take_played(), [("builtin:ding".to_string(), 1.0)]);
+    }
+
+    #[test]
+    fn test_mute_active() {
+        let mut harness = Harness::new(
+            "mute",
+            "sound = \"builtin:chime\"",
+            r#"[
+                { "address": "0x558e91924520", "class": "kitty" },
+                { "address": "0x558e9243ab50", "class": "foot" }
+            ]"#,
+        );
+        assert_eq!(
+            harness.app.handle_control("mute-active").unwrap(),
+            "muted kitty (558e91924520)\n"
+        );
+        assert_eq!(
+            harness.app.handle_control("mute-active").unwrap(),
+            "kitty (558e91924520) is already muted\n"
+        );
+        assert_eq!(
+            harness.app.muted,
+            HashSet::from(["558e91924520".to_string()])
+        );
+
+        // Only the muted window stays quiet.
+        harness.event("bell>>558e91924520");
+        harness.event("bell>>558e9243ab50");
+        assert_eq!(harness.take_played(), [("builtin:chime".to_string(), 1.0)]);
+
+        assert_eq!(
+            harness.app.handle_control("unmute-active").unwrap(),
+            "unmuted kitty (558e91924520)\n"
+        );
+        harness.event("bell>>558e91924520");
+        assert_eq!(harness.take_played(), [("builtin:chime".to_string(), 1.0)]);
+        assert_eq!(
+            harness.app.handle_control("unmute-active").unwrap(),
+            "kitty (558e91924520) is not muted\n"
+        );
+
+        // A new window reusing the address of a closed one is not muted.
+        harness.app.handle_control("mute-active").unwrap();
+        harness.event("closewindow>>558e91924520");
+        assert!(harness.app.muted.is_empty());
+
+        let mut unfocused = Harness::new("mute-unfocused", "", "[]");
+        assert_eq!(
+            unfocused.app.handle_control("mute-active").unwrap(),
+            "no window is focused\n"
+        );
+    }
+
+    #[test]
+    fn test_persist_mutes() {
+        let mut harness = Harness::new(
+            "persist-mutes",
+            "sound = \"builtin:chime\"\npersist_mutes = true",
+            r#"[{ "address": "0x558e91924520", "class": "kitty" }]"#,
+        );
+        let state_dir = env::temp_dir().join(format!("onionbell-mutes-{}", std::process::id()));
+        let path = state::muted_path(&state_dir);
+        let stale = HashSet::from(["558e91924520".to_string(), "558e9243ab50".to_string()]);
+        state::write_muted(&path, &stale).unwrap();
+        harness.app.state_dir = Some(state_dir.clone());
+
+        // The window closed while onionbell was down is forgotten, also in the file.
+        harness.app.restore_muted();
+        let open = HashSet::from(["558e91924520".to_string()]);
+        assert_eq!(harness.app.muted, open);
+        assert_eq!(state::read_muted(&path), open);
+        harness.event("bell>>558e91924520");
+        harness.event("bell>>558e9243ab50");
+        assert_eq!(harness.take_played(), [("builtin:chime".to_string(), 1.0)]);
+        let _ = fs::remove_dir_all(&state_dir);
+    }
+
+    #[test]
+    fn test_silent_volume() {
+        let mut harness = Harness::new(
+            "silent",
+            r#"
+            sound = "builtin:chime"
+
+            [[rule]]
+            class_regex = "^kitty$"
+            sound = "builtin:ding"
+            volume = 0.0
+
+            [[rule]]
+            class_regex = "^foot$"
+            sound_cmd = "cat /tmp/bell.wav"
+            volume = 0.0
+            delay_ms = 10
+            "#,
+            r#"[{ "address": "0x558e91924520", "class": "kitty" }, { "address": "0x558e9243ab50", "class": "foot" }]"#,
+        );
+        harness.event("bell>>558e91924520");
+        harness.event("bell>>558e9243ab50");
+        assert!(harness.take_played().is_empty());
+        assert!(harness.app.delayed.is_empty());
+    }
+
+    #[test]
+    fn test_window_cooldown() {
+        let mut harness = Harness::new(
+            "cooldown",
+            r#"
+            sound = "builtin:chime"
+
+            [[rule]]
+            class_regex = "^kitty$"
+            sound = "builtin:ding"
+            window_cooldown_ms = 60000
+            "#,
+            r#"[{ "address": "0x558e91924520", "class": "kitty" }, { "address": "0x558e9243ab50", "class": "kitty" }]"#,
+        );
+        harness.event("bell>>558e91924520");
+        harness.event("bell>>558e91924520");
+        assert_eq!(harness.take_played().len(), 1);
+        harness.event("bell>>558e9243ab50");
+        harness.event("bell>>558e9243ab50");
+        assert_eq!(harness.take_played().len(), 1);
+        harness.event("bell>>558e91924520");
+        assert_eq!(harness.take_played().len(), 1);
+    }
+
+    #[test]
+    fn test_window_cooldown_state() {
+        let config = r#"
+            [[rule]]
+            class_regex = "^kitty$"
+            sounds = ["builtin:ding", "builtin:knock"]
+            window_cooldown_ms = 60000
+            "#;
+        let clients = r#"[{ "address": "0x558e91924520", "class": "kitty" }, { "address": "0x558e9243ab50", "class": "kitty" }]"#;
+        let mut harness = Harness::new("cooldown-state", config, clients);
+
+        // A quiet bell doesn't count towards the rotation of `sounds`.
+        harness.event("bell>>558e91924520");
+        harness.event("bell>>558e91924520");
+        harness.event("bell>>558e9243ab50");
+        assert_eq!(
+            harness.take_played(),
+            [
+                ("builtin:ding".to_string(), 1.0),
+                ("builtin:knock".to_string(), 1.0)
+            ]
+        );
+
+        // A delayed sound starts the cooldown when it plays.
+        let config = r#"
+            [[rule]]
+            class_regex = "^kitty$"
+            sound = "builtin:ding"
+            delay_ms = 1000
+            window_cooldown_ms = 60000
+            "#;
+        let mut harness = Harness::new("cooldown-delay", config, clients);
+        harness.event("bell>>558e91924520");
+        let now = Instant::now();
+        assert!(!harness.app.is_cooling(Some(0), "558e91924520", now));
+        harness.app.play_delayed(now + Duration::from_secs(2));
+        assert_eq!(harness.take_played().len(), 1);
+        assert!(
+            harness
+                .app
+                .is_cooling(Some(0), "558e91924520", now + Duration::from_secs(2))
+        );
+    }
+
+    #[test]
+    fn test_idle_exit() {
+        let (mut app, _) = test_app(Config::default(), PathBuf::new());
+        let start = app.started;
+        assert_eq!(app.idle_deadline(), None);
+        assert!(!app.is_idle(start + Duration::from_secs(86400)));
+
+        let config = Config::from_source(r#"idle_exit_ms = "10s""#).unwrap();
+        let (mut app, _) = test_app(config, PathBuf::new());
+        let start = app.started;
+        assert_eq!(app.next_deadline(), Some(start + Duration::from_secs(10)));
+        assert!(!app.is_idle(start + Duration::from_millis(9999)));
+        assert!(app.is_idle(start + Duration::from_secs(10)));
+
+        // Events push it back.
+        app.last_event = Some(start + Duration::from_secs(5));
+        assert!(!app.is_idle(start + Duration::from_secs(10)));
+        assert!(app.is_idle(start + Duration::from_secs(15)));
+
+        // So do delayed sounds that are still to be played.
+        let request = app.play_request(
+            PathBuf::from("true"),
+            Sound::Command("true".to_string()),
+            1.0,
+        );
+        let cooldown = None;
+        app.delayed.add(
+            start + Duration::from_secs(20),
+            DelayedSound { request, cooldown },
+        );
+        assert!(!app.is_idle(start + Duration::from_secs(15)));
+        assert_eq!(app.next_deadline(), Some(start + Duration::from_secs(20)));
+    }
+
+    #[test]
+    fn test_delay() {
+        let mut harness = Harness::new(
+            "delay",
+            r#"
+            [[rule]]
+            class_regex = "^kitty$"
+            sound = "builtin:ding"
+            delay_ms = 60000
+            "#,
+            r#"[{ "address": "0x558e91924520", "class": "kitty", "workspace": { "id": 1, "name": "1" } }]"#,
+        );
+        let start = Instant::now();
+        harness.event("bell>>558e91924520");
+        harness.event("bell>>558e91924520");
+        assert!(harness.take_played().is_empty());
+        let deadline = harness.app.next_deadline().unwrap();
+        assert!(deadline >= start + Duration::from_secs(60));
+
+        harness.app.handle_timers(start);
+        assert!(harness.take_played().is_empty());
+        harness.app.handle_timers(deadline + Duration::from_secs(1));
+        assert_eq!(harness.take_played().len(), 2);
+        assert_eq!(harness.app.next_deadline(), None);
+
+        // Sounds disabled in the meantime are dropped when they're due.
+        harness.event("bell>>558e91924520");
+        harness.app.enabled = false;
+        harness
+            .app
+            .handle_timers(deadline + Duration::from_secs(120));
+        assert!(harness.take_played().is_empty());
+        assert!(harness.app.delayed.is_empty());
+    }
+
+    #[test]
+    fn test_first_bell_sound() {
+        let config = Config::from_source(
+            r#"
+            sound = "builtin:chime"
+            volume = 0.8
+            first_bell_sound = "builtin:knock"
+            "#,
+        )
+        .unwrap();
+        // No rules, so Hyprland isn't needed to pick the sound.
+        let (mut app, played) = test_app(config, PathBuf::from("/nonexistent"));
+        app.dispatch_event("bell>>558e91924520");
+        app.dispatch_event("bell>>558e91924520");
+        let played = played.lock().unwrap();
+        assert_eq!(
+            played
+                .iter()
+                .map(|x| (x.path.clone(), x.volume))
+                .collect::<Vec<_>>(),
+            [
+                (PathBuf::from("builtin:knock"), 0.8),
+                (PathBuf::from("builtin:chime"), 0.8)
+            ]
+        );
     }
 }
@@ -0,0 +1,70 @@
+use log::{debug, warn};
+use rodio::cpal::traits::{DeviceTrait, HostTrait};
+use rodio::cpal::{self, HostId};
+use rodio::{OutputStream, OutputStreamBuilder, StreamError};
+
+use crate::error::AppError;
+
+/// Open the output stream on the default device of the audio host named `backend`, e.g. `alsa`.
+/// If it's unavailable, or `backend` is `None`, the default host is used instead.
+pub fn open_stream(backend: Option<&str>) -> Result<OutputStream, AppError> {
+    if let Some(backend) = backend {
+        match open_backend_stream(backend) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => warn!("{err}, falling back to the default audio backend"),
+        }
+    }
+    Ok(OutputStreamBuilder::open_default_stream()?)
+}
+
+fn open_backend_stream(backend: &str) -> Result<OutputStream, AppError> {
+    let id = host_id(backend, &cpal::available_hosts())?;
+    let host =
+        cpal::host_from_id(id).map_err(|_| AppError::UnavailableBackend(backend.to_string()))?;
+    let device = host.default_output_device().ok_or(StreamError::NoDevice)?;
+    debug!("Using audio backend {}", id.name());
+    Ok(OutputStreamBuilder::from_device(device)?.open_stream_or_fallback()?)
+}
+
+/// Open the output stream on the device called `name` of the audio host named `backend`, or of
+/// the default host if `backend` is `None`.
+pub fn open_device_stream(backend: Option<&str>, name: &str) -> Result<OutputStream, AppError> {
+    let host = match backend {
+        Some(backend) => cpal::host_from_id(host_id(backend, &cpal::available_hosts())?)
+            .map_err(|_| AppError::UnavailableBackend(backend.to_string()))?,
+        None => cpal::default_host(),
+    };
+    let unavailable = || AppError::UnavailableDevice(name.to_string());
+    let device = host
+        .output_devices()
+        .map_err(|_| unavailable())?
+        .find(|x| x.name().is_ok_and(|x| x == name))
+        .ok_or_else(unavailable)?;
+    Ok(OutputStreamBuilder::from_device(device)?.open_stream_or_fallback()?)
+}
+
+/// Find the host named `name` among `available` hosts, ignoring case.
+pub fn host_id(name: &str, available: &[HostId]) -> Result<HostId, AppError> {
+    available
+        .iter()
+        .find(|x| x.name().eq_ignore_ascii_case(name))
+        .copied()
+        .ok_or_else(|| AppError::UnavailableBackend(name.to_string()))
+}
+
+#[allow(unused)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_host_id() {
+        assert_eq!(host_id("alsa", &[HostId::Alsa]).unwrap(), HostId::Alsa);
+        assert_eq!(host_id("ALSA", &[HostId::Alsa]).unwrap(), HostId::Alsa);
+        assert!(host_id("alsa", &[]).is_err());
+
+        let Err(err) = host_id("pulse", &[HostId::Alsa]) else {
+            unreachable!()
+        };
+        assert_eq!(err.to_string(), "Audio backend pulse is not available");
+    }
+}
@@ -0,0 +1,53 @@
+use std::path::Path;
+
+/// Prefix of `sound` paths that refer to a sound bundled into the binary, e.g. `builtin:chime`.
+pub const PREFIX: &str = "builtin:";
+
+/// Sounds bundled into the binary, so a fresh install can make sound without any audio file.
+/// They're tiny mono Vorbis files to keep the binary small.
+pub const SOUNDS: [(&str, &[u8]); 3] = [
+    ("chime", include_bytes!("../assets/sounds/chime.ogg")),
+    ("ding", include_bytes!("../assets/sounds/ding.ogg")),
+    ("knock", include_bytes!("../assets/sounds/knock.ogg")),
+];
+
+/// The name of the bundled sound `path` refers to, or `None` if it's a regular path.
+pub fn name(path: &Path) -> Option<&str> {
+    path.to_str()?.strip_prefix(PREFIX)
+}
+
+/// Data of the bundled sound called `name`.
+pub fn get(name: &str) -> Option<&'static [u8]> {
+    SOUNDS
+        .iter()
+        .find(|(x, _)| *x == name)
+        .map(|(_, data)| *data)
+}
+
+#[allow(unused)]
+mod test {
+    use std::io;
+
+    use rodio::{Decoder, Source};
+
+    use super::*;
+
+    #[test]
+    fn test_name() {
+        assert_eq!(name(Path::new("builtin:chime")), Some("chime"));
+        assert_eq!(name(Path::new("builtin:")), Some(""));
+        assert_eq!(name(Path::new("/tmp/builtin:chime")), None);
+        assert_eq!(name(Path::new("chime")), None);
+    }
+
+    #[test]
+    fn test_decode() {
+        assert!(get("nope").is_none());
+        for (sound, _) in SOUNDS {
+            let data = get(name(Path::new(&format!("{PREFIX}{sound}"))).unwrap()).unwrap();
+            let audio = Decoder::try_from(io::Cursor::new(data.to_vec())).unwrap();
+            assert_eq!(audio.channels(), 1);
+            assert!(audio.count() > 0, "{sound} is empty");
+        }
+    }
+}
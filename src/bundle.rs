@@ -0,0 +1,118 @@
+#[cfg(feature = "bundle")]
+use std::collections::HashMap;
+#[cfg(feature = "bundle")]
+use std::fs::File;
+#[cfg(feature = "bundle")]
+use std::io::Read;
+use std::path::Path;
+#[cfg(feature = "bundle")]
+use std::path::PathBuf;
+
+#[cfg(feature = "bundle")]
+use zip::ZipArchive;
+#[cfg(feature = "bundle")]
+use zip::result::ZipError;
+
+use crate::error::AppError;
+
+/// Prefix of `sound` paths that refer to an entry of a zip archive, e.g.
+/// `bundle:sounds.zip#bell.ogg`.
+pub const PREFIX: &str = "bundle:";
+
+/// The archive and the name of the entry `path` refers to, or `None` if it's a regular path. The
+/// entry name follows the last `#`.
+pub fn split(path: &Path) -> Option<(&Path, &str)> {
+    let (archive, entry) = path.to_str()?.strip_prefix(PREFIX)?.rsplit_once('#')?;
+    Some((Path::new(archive), entry))
+}
+
+/// The archives opened so far, so the central directory of every archive is read once however
+/// many of its entries are used.
+#[derive(Debug, Default)]
+pub struct Bundles {
+    #[cfg(feature = "bundle")]
+    archives: HashMap<PathBuf, ZipArchive<File>>,
+}
+
+impl Bundles {
+    /// Read `entry` of the zip archive at `archive`.
+    #[cfg(feature = "bundle")]
+    pub fn read(&mut self, archive: &Path, entry: &str) -> Result<Vec<u8>, AppError> {
+        let invalid = |err: ZipError| AppError::InvalidZip(err.to_string());
+        if !self.archives.contains_key(archive) {
+            let opened = ZipArchive::new(File::open(archive)?).map_err(invalid)?;
+            self.archives.insert(archive.to_path_buf(), opened);
+        }
+        let mut file = match self.archives.get_mut(archive).unwrap().by_name(entry) {
+            Ok(file) => file,
+            Err(ZipError::FileNotFound) => {
+                return Err(AppError::MissingBundleEntry(
+                    archive.to_path_buf(),
+                    entry.to_string(),
+                ));
+            }
+            Err(err) => return Err(invalid(err)),
+        };
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    #[cfg(not(feature = "bundle"))]
+    pub fn read(&mut self, archive: &Path, _entry: &str) -> Result<Vec<u8>, AppError> {
+        Err(AppError::BundleUnsupported(archive.to_path_buf()))
+    }
+}
+
+#[allow(unused)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_split() {
+        assert_eq!(
+            split(Path::new("bundle:sounds.zip#bell.ogg")),
+            Some((Path::new("sounds.zip"), "bell.ogg"))
+        );
+        assert_eq!(
+            split(Path::new("bundle:/tmp/a#b.zip#sfx/bell.ogg")),
+            Some((Path::new("/tmp/a#b.zip"), "sfx/bell.ogg"))
+        );
+        assert_eq!(split(Path::new("bundle:sounds.zip")), None);
+        assert_eq!(split(Path::new("/tmp/bundle:sounds.zip#bell.ogg")), None);
+    }
+
+    #[cfg(feature = "bundle")]
+    #[test]
+    fn test_read() {
+        use std::io::Write;
+        use zip::ZipWriter;
+        use zip::write::SimpleFileOptions;
+
+        let ding = crate::builtin::get("ding").unwrap();
+        // A zip archive with `ding.ogg` stored as is, and a deflated copy.
+        let mut writer = ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        let stored =
+            SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        writer.start_file("ding.ogg", stored).unwrap();
+        writer.write_all(ding).unwrap();
+        let deflated =
+            SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+        writer.start_file("sfx/ding.ogg", deflated).unwrap();
+        writer.write_all(ding).unwrap();
+        let zip = writer.finish().unwrap().into_inner();
+
+        let path =
+            std::env::temp_dir().join(format!("onionbell-test-bundle-{}.zip", std::process::id()));
+        std::fs::write(&path, &zip).unwrap();
+        let mut bundles = Bundles::default();
+        assert_eq!(bundles.read(&path, "ding.ogg").unwrap(), ding);
+        // The archive is only opened once.
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(bundles.read(&path, "sfx/ding.ogg").unwrap(), ding);
+        assert!(matches!(
+            bundles.read(&path, "chime.ogg"),
+            Err(AppError::MissingBundleEntry(..))
+        ));
+    }
+}
@@ -0,0 +1,176 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::sound::DecodedSound;
+use crate::util;
+
+/// Identifies files written by [`write`], in case the format changes in the future.
+const MAGIC: &[u8; 8] = b"OBPCM\0\0\x01";
+
+/// Directory of the decoded sample cache, which is `$XDG_CACHE_HOME/onionbell` or
+/// `~/.cache/onionbell`.
+pub fn cache_dir() -> Option<PathBuf> {
+    util::xdg_dir("XDG_CACHE_HOME", ".cache").map(|x| x.join("onionbell"))
+}
+
+/// Name of the cache file of the sound at `path`. The modification time isn't part of the key but
+/// stored in the file instead, so a changed sound overwrites its stale entry rather than leaving
+/// it behind. The path is hashed with 64-bit FNV-1a, which unlike the hasher of the standard
+/// library gives the same key in every build of onionbell.
+pub fn cache_key(path: &Path) -> String {
+    let hash = path
+        .as_os_str()
+        .as_bytes()
+        .iter()
+        .fold(0xcbf29ce484222325u64, |hash, &byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+    format!("{hash:016x}.pcm")
+}
+
+fn mtime_nanos(mtime: SystemTime) -> u128 {
+    mtime
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_nanos())
+        .unwrap_or_default()
+}
+
+/// Read the cached samples of a sound whose file was last modified at `mtime`. Returns `None` if
+/// the entry is missing, unreadable or stale.
+pub fn read(cache_file: &Path, mtime: SystemTime) -> Option<DecodedSound> {
+    read_from(BufReader::new(File::open(cache_file).ok()?), mtime).ok()
+}
+
+fn read_from(mut reader: impl Read, mtime: SystemTime) -> io::Result<DecodedSound> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid("not a cache file"));
+    }
+    let mut buf = [0u8; 16];
+    reader.read_exact(&mut buf)?;
+    if u128::from_le_bytes(buf) != mtime_nanos(mtime) {
+        return Err(invalid("stale cache file"));
+    }
+    let mut buf = [0u8; 2];
+    reader.read_exact(&mut buf)?;
+    let channels = u16::from_le_bytes(buf);
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    let sample_rate = u32::from_le_bytes(buf);
+    if channels == 0 || sample_rate == 0 {
+        return Err(invalid("invalid audio format"));
+    }
+
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    if data.len() % 4 != 0 {
+        return Err(invalid("truncated cache file"));
+    }
+    let samples = data
+        .chunks_exact(4)
+        .map(|x| f32::from_le_bytes([x[0], x[1], x[2], x[3]]))
+        .collect();
+    Ok(DecodedSound {
+        channels,
+        sample_rate,
+        samples,
+    })
+}
+
+/// Write the samples of a sound whose file was last modified at `mtime` into the cache. They're
+/// written to a temporary file that replaces `cache_file` once it's complete, so another
+/// onionbell starting at the same time never reads a partial entry.
+pub fn write(cache_file: &Path, mtime: SystemTime, sound: &DecodedSound) -> io::Result<()> {
+    if let Some(dir) = cache_file.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut temp_file = cache_file.as_os_str().to_owned();
+    temp_file.push(format!(".{}.tmp", std::process::id()));
+    let result = File::create(&temp_file).and_then(|file| {
+        let mut writer = BufWriter::new(file);
+        write_to(&mut writer, mtime, sound)?;
+        writer.flush()
+    });
+    match result.and_then(|()| fs::rename(&temp_file, cache_file)) {
+        Ok(()) => Ok(()),
+        Err(err) => {
+            let _ = fs::remove_file(&temp_file);
+            Err(err)
+        }
+    }
+}
+
+fn write_to(mut writer: impl Write, mtime: SystemTime, sound: &DecodedSound) -> io::Result<()> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&mtime_nanos(mtime).to_le_bytes())?;
+    writer.write_all(&sound.channels.to_le_bytes())?;
+    writer.write_all(&sound.sample_rate.to_le_bytes())?;
+    for sample in &sound.samples {
+        writer.write_all(&sample.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+#[allow(unused)]
+mod test {
+    use std::env;
+    use std::time::Duration;
+
+    use super::*;
+
+    fn sound() -> DecodedSound {
+        DecodedSound {
+            channels: 2,
+            sample_rate: 44100,
+            samples: vec![0.0, 0.5, -0.5, 1.0],
+        }
+    }
+
+    #[test]
+    fn test_cache_key() {
+        let key = cache_key(Path::new("/tmp/a.wav"));
+        assert_eq!(key, cache_key(Path::new("/tmp/a.wav")));
+        assert_ne!(key, cache_key(Path::new("/tmp/b.wav")));
+        assert!(key.ends_with(".pcm"));
+        // The key is stable across builds, so the cache survives upgrades.
+        assert_eq!(cache_key(Path::new("")), "cbf29ce484222325.pcm");
+        assert_eq!(cache_key(Path::new("a")), "af63dc4c8601ec8c.pcm");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mtime = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut buf = Vec::new();
+        write_to(&mut buf, mtime, &sound()).unwrap();
+        assert_eq!(read_from(buf.as_slice(), mtime).unwrap(), sound());
+    }
+
+    #[test]
+    fn test_invalidation() {
+        let mtime = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut buf = Vec::new();
+        write_to(&mut buf, mtime, &sound()).unwrap();
+        assert!(read_from(buf.as_slice(), mtime + Duration::from_nanos(1)).is_err());
+        assert!(read_from(&buf[..buf.len() - 1], mtime).is_err());
+        assert!(read_from(&b"garbage"[..], mtime).is_err());
+    }
+
+    #[test]
+    fn test_file() {
+        let dir = env::temp_dir().join(format!("onionbell-cache-test-{}", std::process::id()));
+        let cache_file = dir.join(cache_key(Path::new("/tmp/a.wav")));
+        let mtime = SystemTime::now();
+        assert!(read(&cache_file, mtime).is_none());
+        write(&cache_file, mtime, &sound()).unwrap();
+        assert_eq!(read(&cache_file, mtime), Some(sound()));
+        // Only the entry is left behind, not the temporary file it was written to.
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,299 @@
+use std::path::PathBuf;
+
+use crate::completions::Shell;
+use crate::error::AppError;
+use crate::replay::ReplaySpeed;
+
+/// Command line arguments of onionbell.
+#[derive(Debug, Default)]
+pub struct Args {
+    /// Path to the config file, overriding the default lookup. The special value `-` means the
+    /// config is read from stdin.
+    pub config: Option<PathBuf>,
+
+    /// A command to send to the running instance through the control socket instead of starting
+    /// a new one. It consumes all the remaining arguments.
+    pub ctl: Option<String>,
+
+    /// Signature of the Hyprland instance to connect to, overriding
+    /// `$HYPRLAND_INSTANCE_SIGNATURE`.
+    pub instance: Option<String>,
+
+    /// A file containing the signature of the Hyprland instance to connect to, for when neither
+    /// the environment variable nor `instance` is available.
+    pub instance_file: Option<PathBuf>,
+
+    /// Seed of the random number generator, overriding the `seed` config key.
+    pub seed: Option<u64>,
+
+    /// Play the sound of every rule in turn and exit.
+    pub audition: bool,
+
+    /// Print the config as onionbell sees it, with defaults filled in, as TOML and exit.
+    pub dump_effective: bool,
+
+    /// Decode every sound referenced by the config, report which ones fail and exit.
+    pub validate_sounds: bool,
+
+    /// Run every check on the config, print a report and exit. It's given as the `lint`
+    /// subcommand, which has to be the first argument.
+    pub lint: bool,
+
+    /// Print which rule every current window would match, and what it would play, as a table and
+    /// exit.
+    pub explain: bool,
+
+    /// Print every event received from Hyprland instead of handling it.
+    pub tail: bool,
+
+    /// Print a JSON object in a line for every handled bell, besides playing its sound.
+    pub emit_json: bool,
+
+    /// A file to append every received event to.
+    pub record: Option<PathBuf>,
+
+    /// A file of recorded events to replay through rule matching instead of listening to
+    /// Hyprland.
+    pub replay: Option<PathBuf>,
+
+    /// How fast to replay events with `replay`.
+    pub replay_speed: ReplaySpeed,
+
+    /// Print the completion script for a shell and exit.
+    pub completions: Option<Shell>,
+}
+
+impl Args {
+    /// Parse arguments, excluding the program name.
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Args, AppError> {
+        let mut result = Args::default();
+        let mut args = args.into_iter().peekable();
+        if args.next_if(|x| x == "lint").is_some() {
+            result.lint = true;
+        }
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--config" | "-c" => {
+                    let Some(path) = args.next() else {
+                        return Err(AppError::InvalidArgument(format!("{arg} requires a path")));
+                    };
+                    result.config = Some(path.into());
+                }
+                "--instance" => {
+                    let Some(signature) = args.next() else {
+                        return Err(AppError::InvalidArgument(format!(
+                            "{arg} requires a signature"
+                        )));
+                    };
+                    result.instance = Some(signature);
+                }
+                "--instance-file" => {
+                    let Some(path) = args.next() else {
+                        return Err(AppError::InvalidArgument(format!("{arg} requires a path")));
+                    };
+                    result.instance_file = Some(path.into());
+                }
+                "--seed" => {
+                    let seed = args.next().and_then(|x| x.parse().ok());
+                    let Some(seed) = seed else {
+                        return Err(AppError::InvalidArgument(format!(
+                            "{arg} requires an unsigned integer"
+                        )));
+                    };
+                    result.seed = Some(seed);
+                }
+                "--audition" => result.audition = true,
+                "--dump-effective" => result.dump_effective = true,
+                "--validate-sounds" => result.validate_sounds = true,
+                "--explain" => result.explain = true,
+                "--tail" => result.tail = true,
+                "--emit-json" => result.emit_json = true,
+                "--record" | "--replay" => {
+                    let Some(path) = args.next() else {
+                        return Err(AppError::InvalidArgument(format!("{arg} requires a path")));
+                    };
+                    if arg == "--record" {
+                        result.record = Some(path.into());
+                    } else {
+                        result.replay = Some(path.into());
+                    }
+                }
+                "--replay-speed" => {
+                    let Some(speed) = args.next() else {
+                        return Err(AppError::InvalidArgument(format!("{arg} requires a speed")));
+                    };
+                    result.replay_speed = ReplaySpeed::parse(&speed)?;
+                }
+                "--completions" => {
+                    let Some(shell) = args.next() else {
+                        return Err(AppError::InvalidArgument(format!("{arg} requires a shell")));
+                    };
+                    result.completions = Some(Shell::parse(&shell)?);
+                }
+                "--ctl" => {
+                    let command = args.by_ref().collect::<Vec<_>>().join(" ");
+                    if command.is_empty() {
+                        return Err(AppError::InvalidArgument(format!(
+                            "{arg} requires a command"
+                        )));
+                    }
+                    result.ctl = Some(command);
+                }
+                _ => {
+                    return Err(AppError::InvalidArgument(format!("unknown argument {arg}")));
+                }
+            }
+        }
+        Ok(result)
+    }
+}
+
+#[allow(unused)]
+mod test {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, AppError> {
+        Args::parse(args.iter().map(|x| x.to_string()))
+    }
+
+    #[test]
+    fn test_config() {
+        assert!(parse(&[]).unwrap().config.is_none());
+        assert_eq!(
+            parse(&["--config", "-"]).unwrap().config,
+            Some(PathBuf::from("-"))
+        );
+        assert_eq!(
+            parse(&["-c", "/tmp/foo.toml"]).unwrap().config,
+            Some(PathBuf::from("/tmp/foo.toml"))
+        );
+        assert!(parse(&["--config"]).is_err());
+        assert!(parse(&["--foo"]).is_err());
+    }
+
+    #[test]
+    fn test_ctl() {
+        assert!(parse(&[]).unwrap().ctl.is_none());
+        assert_eq!(
+            parse(&["--ctl", "stats"]).unwrap().ctl.as_deref(),
+            Some("stats")
+        );
+        assert_eq!(
+            parse(&["-c", "-", "--ctl", "play", "/tmp/a.wav"])
+                .unwrap()
+                .ctl
+                .as_deref(),
+            Some("play /tmp/a.wav")
+        );
+        assert!(parse(&["--ctl"]).is_err());
+    }
+
+    #[test]
+    fn test_seed() {
+        assert!(parse(&[]).unwrap().seed.is_none());
+        assert_eq!(parse(&["--seed", "42"]).unwrap().seed, Some(42));
+        assert!(parse(&["--seed"]).is_err());
+        assert!(parse(&["--seed", "-1"]).is_err());
+    }
+
+    #[test]
+    fn test_record_replay() {
+        let args = parse(&["--record", "/tmp/events.log"]).unwrap();
+        assert_eq!(args.record, Some(PathBuf::from("/tmp/events.log")));
+        assert!(args.replay.is_none());
+        let args = parse(&["--replay", "/tmp/events.log"]).unwrap();
+        assert_eq!(args.replay, Some(PathBuf::from("/tmp/events.log")));
+        assert!(args.record.is_none());
+        assert!(parse(&["--record"]).is_err());
+    }
+
+    #[test]
+    fn test_emit_json() {
+        assert!(!parse(&[]).unwrap().emit_json);
+        assert!(parse(&["--emit-json"]).unwrap().emit_json);
+    }
+
+    #[test]
+    fn test_lint() {
+        assert!(!parse(&[]).unwrap().lint);
+        assert!(parse(&["lint"]).unwrap().lint);
+        assert!(parse(&["lint", "-c", "/tmp/foo.toml"]).unwrap().lint);
+        assert!(parse(&["--tail", "lint"]).is_err());
+        assert!(parse(&["lint", "lint"]).is_err());
+    }
+
+    #[test]
+    fn test_explain() {
+        assert!(!parse(&[]).unwrap().explain);
+        assert!(parse(&["--explain"]).unwrap().explain);
+    }
+
+    #[test]
+    fn test_audition() {
+        assert!(!parse(&[]).unwrap().audition);
+        assert!(parse(&["--audition"]).unwrap().audition);
+    }
+
+    #[test]
+    fn test_dump_effective() {
+        assert!(!parse(&[]).unwrap().dump_effective);
+        let args = parse(&["--dump-effective", "-c", "/tmp/a.toml"]).unwrap();
+        assert!(args.dump_effective);
+        assert_eq!(args.config, Some(PathBuf::from("/tmp/a.toml")));
+    }
+
+    #[test]
+    fn test_validate_sounds() {
+        assert!(!parse(&[]).unwrap().validate_sounds);
+        assert!(parse(&["--validate-sounds"]).unwrap().validate_sounds);
+    }
+
+    #[test]
+    fn test_instance() {
+        let args = parse(&[]).unwrap();
+        assert!(args.instance.is_none() && args.instance_file.is_none());
+        assert_eq!(
+            parse(&["--instance", "abc_123"])
+                .unwrap()
+                .instance
+                .as_deref(),
+            Some("abc_123")
+        );
+        assert_eq!(
+            parse(&["--instance-file", "/tmp/sig"])
+                .unwrap()
+                .instance_file,
+            Some(PathBuf::from("/tmp/sig"))
+        );
+        assert!(parse(&["--instance"]).is_err());
+        assert!(parse(&["--instance-file"]).is_err());
+    }
+
+    #[test]
+    fn test_tail() {
+        assert!(!parse(&[]).unwrap().tail);
+        assert!(parse(&["--tail"]).unwrap().tail);
+    }
+
+    #[test]
+    fn test_replay_speed() {
+        assert_eq!(parse(&[]).unwrap().replay_speed, ReplaySpeed::Max);
+        assert_eq!(
+            parse(&["--replay-speed", "realtime"]).unwrap().replay_speed,
+            ReplaySpeed::RealTime
+        );
+        assert!(parse(&["--replay-speed"]).is_err());
+        assert!(parse(&["--replay-speed", "slow"]).is_err());
+    }
+
+    #[test]
+    fn test_completions() {
+        assert!(parse(&[]).unwrap().completions.is_none());
+        assert_eq!(
+            parse(&["--completions", "zsh"]).unwrap().completions,
+            Some(Shell::Zsh)
+        );
+        assert!(parse(&["--completions"]).is_err());
+        assert!(parse(&["--completions", "tcsh"]).is_err());
+    }
+}
@@ -0,0 +1,302 @@
+use crate::error::AppError;
+
+/// A shell onionbell can print a completion script for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl Shell {
+    pub const ALL: [Shell; 3] = [Shell::Bash, Shell::Zsh, Shell::Fish];
+
+    pub fn parse(s: &str) -> Result<Shell, AppError> {
+        match s {
+            "bash" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            _ => Err(AppError::InvalidArgument(format!(
+                "shell must be bash, zsh or fish, not {s}"
+            ))),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Shell::Bash => "bash",
+            Shell::Zsh => "zsh",
+            Shell::Fish => "fish",
+        }
+    }
+}
+
+/// What follows a flag on the command line.
+#[derive(Debug)]
+enum Value {
+    None,
+    Path,
+    Word,
+    Choice(&'static [&'static str]),
+}
+
+#[derive(Debug)]
+struct Flag {
+    long: &'static str,
+    short: Option<char>,
+    value: Value,
+    help: &'static str,
+}
+
+const fn flag(long: &'static str, value: Value, help: &'static str) -> Flag {
+    Flag {
+        long,
+        short: None,
+        value,
+        help,
+    }
+}
+
+/// Every flag [`crate::cli::Args::parse`] accepts. The parser is hand-written rather than built
+/// with clap, so there is no command definition to generate the scripts from and this table is
+/// kept in sync with it by tests, both ways. Help texts are quoted as is in the scripts, so they must not
+/// contain quotes or brackets.
+const FLAGS: &[Flag] = &[
+    Flag {
+        long: "config",
+        short: Some('c'),
+        value: Value::Path,
+        help: "Path to the config file, or - for stdin",
+    },
+    flag(
+        "instance",
+        Value::Word,
+        "Signature of the Hyprland instance",
+    ),
+    flag(
+        "instance-file",
+        Value::Path,
+        "File containing the signature of the Hyprland instance",
+    ),
+    flag("seed", Value::Word, "Seed of the random number generator"),
+    flag(
+        "audition",
+        Value::None,
+        "Play the sound of every rule and exit",
+    ),
+    flag(
+        "dump-effective",
+        Value::None,
+        "Print the config with defaults filled in and exit",
+    ),
+    flag(
+        "validate-sounds",
+        Value::None,
+        "Decode every sound of the config and exit",
+    ),
+    flag(
+        "explain",
+        Value::None,
+        "Print the rule every window would match and exit",
+    ),
+    flag(
+        "tail",
+        Value::None,
+        "Print every event received from Hyprland",
+    ),
+    flag(
+        "emit-json",
+        Value::None,
+        "Print a JSON object for every bell",
+    ),
+    flag(
+        "record",
+        Value::Path,
+        "File to append every received event to",
+    ),
+    flag("replay", Value::Path, "File of recorded events to replay"),
+    flag(
+        "replay-speed",
+        Value::Choice(&["max", "realtime"]),
+        "How fast to replay events",
+    ),
+    flag("ctl", Value::Word, "Send a command to the running instance"),
+    flag(
+        "completions",
+        Value::Choice(&["bash", "zsh", "fish"]),
+        "Print a completion script for a shell and exit",
+    ),
+];
+
+/// Subcommands, given as the first argument only.
+const SUBCOMMANDS: &[(&str, &str)] = &[("lint", "Check the config and print a report")];
+
+/// The completion script of onionbell for `shell`.
+pub fn generate(shell: Shell) -> String {
+    match shell {
+        Shell::Bash => bash(),
+        Shell::Zsh => zsh(),
+        Shell::Fish => fish(),
+    }
+}
+
+/// Every spelling of `flag`, e.g. `-c` and `--config`.
+fn spellings(flag: &Flag) -> Vec<String> {
+    let mut spellings = Vec::new();
+    if let Some(short) = flag.short {
+        spellings.push(format!("-{short}"));
+    }
+    spellings.push(format!("--{}", flag.long));
+    spellings
+}
+
+fn bash() -> String {
+    let mut words: Vec<String> = SUBCOMMANDS.iter().map(|x| x.0.to_string()).collect();
+    words.extend(FLAGS.iter().flat_map(spellings));
+
+    let mut cases = String::new();
+    let with = |value: fn(&Value) -> bool| {
+        FLAGS
+            .iter()
+            .filter(|x| value(&x.value))
+            .flat_map(spellings)
+            .collect::<Vec<_>>()
+            .join("|")
+    };
+    cases += &format!(
+        "        {})\n            COMPREPLY=($(compgen -f -- \"$cur\"))\n            return ;;\n",
+        with(|x| matches!(x, Value::Path))
+    );
+    cases += &format!(
+        "        {})\n            return ;;\n",
+        with(|x| matches!(x, Value::Word))
+    );
+    for flag in FLAGS {
+        if let Value::Choice(choices) = flag.value {
+            cases += &format!(
+                "        {})\n            \
+                COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))\n            \
+                return ;;\n",
+                spellings(flag).join("|"),
+                choices.join(" ")
+            );
+        }
+    }
+
+    format!(
+        "_onionbell() {{\n    \
+        local cur=\"${{COMP_WORDS[COMP_CWORD]}}\"\n    \
+        local prev=\"${{COMP_WORDS[COMP_CWORD-1]}}\"\n    \
+        case \"$prev\" in\n{cases}    esac\n    \
+        COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))\n}}\n\
+        complete -F _onionbell onionbell\n",
+        words.join(" ")
+    )
+}
+
+fn zsh() -> String {
+    let mut script = String::from("#compdef onionbell\n\n_arguments \\\n");
+    for flag in FLAGS {
+        let names = spellings(flag);
+        let names = if names.len() > 1 {
+            format!("'({})'{{{}}}'", names.join(" "), names.join(","))
+        } else {
+            format!("'{}", names[0])
+        };
+        let value = match flag.value {
+            Value::None => String::new(),
+            Value::Path => format!(":{}:_files", flag.long),
+            Value::Word => format!(":{}: ", flag.long),
+            Value::Choice(choices) => format!(":{}:({})", flag.long, choices.join(" ")),
+        };
+        script += &format!("  {names}[{}]{value}' \\\n", flag.help);
+    }
+    let subcommands: Vec<_> = SUBCOMMANDS.iter().map(|x| x.0).collect();
+    script += &format!("  '1::command:({})'\n", subcommands.join(" "));
+    script
+}
+
+fn fish() -> String {
+    let mut script = String::from("complete -c onionbell -f\n");
+    for (name, help) in SUBCOMMANDS {
+        script +=
+            &format!("complete -c onionbell -n __fish_use_subcommand -a {name} -d '{help}'\n");
+    }
+    for flag in FLAGS {
+        let mut line = String::from("complete -c onionbell");
+        if let Some(short) = flag.short {
+            line += &format!(" -s {short}");
+        }
+        line += &format!(" -l {}", flag.long);
+        match flag.value {
+            Value::None => {}
+            Value::Path => line += " -r -F",
+            Value::Word => line += " -x",
+            Value::Choice(choices) => line += &format!(" -x -a '{}'", choices.join(" ")),
+        }
+        script += &format!("{line} -d '{}'\n", flag.help);
+    }
+    script
+}
+
+#[allow(unused)]
+mod test {
+    use super::*;
+    use crate::cli::Args;
+
+    #[test]
+    fn test_generate() {
+        for shell in Shell::ALL {
+            let script = generate(shell);
+            assert!(!script.is_empty(), "{}", shell.name());
+            for flag in FLAGS {
+                assert!(script.contains(flag.long), "{} {}", shell.name(), flag.long);
+            }
+            assert!(script.contains("lint"));
+        }
+    }
+
+    #[test]
+    fn test_flags_parse() {
+        // Every flag offered for completion is one the parser accepts.
+        for flag in FLAGS {
+            for spelling in spellings(flag) {
+                let mut args = vec![spelling.clone()];
+                match flag.value {
+                    Value::None => {}
+                    Value::Path => args.push("/tmp/a".to_string()),
+                    Value::Word => args.push("1".to_string()),
+                    Value::Choice(choices) => args.push(choices[0].to_string()),
+                }
+                assert!(Args::parse(args).is_ok(), "{spelling}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_parser_flags() {
+        // Every flag the parser matches on is offered for completion, so a flag added to the
+        // parser but not here fails.
+        let source = include_str!("cli.rs");
+        let parse = &source[source.find("fn parse").unwrap()..source.find("mod test").unwrap()];
+        let spellings = FLAGS.iter().flat_map(spellings).collect::<Vec<_>>();
+        let literals = parse
+            .split('"')
+            .skip(1)
+            .step_by(2)
+            .filter(|x| x.starts_with('-'))
+            .collect::<Vec<_>>();
+        assert!(literals.contains(&"--config"));
+        for literal in literals {
+            assert!(spellings.iter().any(|x| x == literal), "{literal}");
+        }
+    }
+
+    #[test]
+    fn test_shell() {
+        for shell in Shell::ALL {
+            assert_eq!(Shell::parse(shell.name()).unwrap(), shell);
+        }
+        assert!(Shell::parse("tcsh").is_err());
+    }
+}
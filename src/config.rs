@@ -1,144 +1,3843 @@
 mod serde_helpers;
 
+use crate::bundle;
+use crate::debounce::DEBOUNCED_EVENTS;
 use crate::error::AppError;
+use crate::hypr::{
+    Geometry, HyprActiveWindow, HyprClient, HyprMonitor, HyprWorkspace, WindowState,
+};
+use crate::util::{reader_to_string, shell_quote};
 
-use self::serde_helpers::{default_volume, validate_volume};
-use regex::Regex;
-use serde::Deserialize;
-use std::path::PathBuf;
+use self::serde_helpers::{
+    db_to_volume, default_beeps_count, default_beeps_freq, default_beeps_gap, default_beeps_ms,
+    default_escalate_cap, default_escalate_step, default_escalate_window_ms, default_fatigue_decay,
+    default_fatigue_min, default_fatigue_recovery, default_fatigue_window_ms,
+    default_max_event_bytes, default_mqtt_client_id, default_mqtt_port, default_mqtt_topic,
+    default_play_retries, default_urgent_ttl_ms, default_volume, deserialize_ms,
+    deserialize_optional_ms, serialize_optional_regexes, validate_beeps_count, validate_db,
+    validate_escalate_cap, validate_escalate_step, validate_fraction, validate_optional_db,
+    validate_optional_volume, validate_volume, validate_volume_map,
+};
+use log::trace;
+use rand::Rng;
+use regex::{Regex, RegexSet};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::{self, Read};
+use std::num::{NonZeroU32, NonZeroU64};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// The range of `sample_rate` that output devices support, outside of which it's warned about.
+const MIN_SAMPLE_RATE: u32 = 8000;
+const MAX_SAMPLE_RATE: u32 = 384000;
+
+/// The most beeps that a `beeps` pattern may have.
+pub const MAX_BEEPS: u32 = 100;
+
+/// The default `max_event_bytes`.
+pub const DEFAULT_MAX_EVENT_BYTES: usize = 64 * 1024;
 
 /// The config of onionbell contains a `sound` key and several rules.
 /// Read each field's documentation for more information.
-#[derive(Debug, Deserialize, Default)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Config {
     /// `sound` is an optional key, represents path to an audio file that will be played when
     /// the `bell` event is triggered. When this key is not present, no sound will play at all.
+    /// Paths like `builtin:chime` refer to sounds bundled into onionbell.
+    pub sound: Option<PathBuf>,
+
+    /// The volume of the sound, ranges from 0.0 to 1.0.
+    /// The default value is 1.0.
+    #[serde(default = "default_volume", deserialize_with = "validate_volume")]
+    pub volume: f32,
+
+    /// Decibels added to the `volume_db` of every rule, like a master fader, e.g. `-6.0` to make
+    /// them all quieter. Volumes are clamped to 0 dB after adding it. Rules that set `volume`
+    /// instead of `volume_db` are not affected. The default value is 0.0.
+    #[serde(default, deserialize_with = "validate_db")]
+    pub volume_offset_db: f32,
+
+    /// Makes repeated bells louder and louder. When this key is not present, every bell plays at
+    /// its configured volume.
+    pub escalate: Option<Escalate>,
+
+    /// Publishes every handled bell to an MQTT broker, e.g. for home automation. Only available
+    /// when onionbell is built with the `mqtt` feature.
+    pub mqtt: Option<Mqtt>,
+
+    /// A sound played instead of the one chosen by rules for the first bell after onionbell
+    /// starts, at the global `volume`. With `first_bell_timeout_ms`, a bell after that long a
+    /// silence counts as a first bell again.
+    pub first_bell_sound: Option<PathBuf>,
+
+    /// How many milliseconds without bells make the next bell play `first_bell_sound` again. When
+    /// this key is not present, only the first bell after onionbell starts does.
+    #[serde(default, deserialize_with = "deserialize_optional_ms")]
+    pub first_bell_timeout_ms: Option<u64>,
+
+    /// How many milliseconds without events from Hyprland make onionbell exit, e.g. to be started
+    /// again on demand by a service manager. When this key is not present, it runs until Hyprland
+    /// goes away.
+    #[serde(default, deserialize_with = "deserialize_optional_ms")]
+    pub idle_exit_ms: Option<u64>,
+
+    /// The audio host to play through, e.g. `alsa`, which is matched against the hosts supported
+    /// by the audio library, ignoring case. When the host is not available, or this key is not
+    /// present, the default host is used. PulseAudio and PipeWire are reached through their ALSA
+    /// plugins, so there are no separate hosts for them.
+    pub backend: Option<String>,
+
+    /// Output devices to play bells on by the name of the monitor of the source window, e.g.
+    /// `DP-1`, for monitors with speakers of their own. Device names are the ones of the audio
+    /// `backend`. Bells from other monitors, and ones whose device fails to open, play on the
+    /// default output.
+    #[serde(default)]
+    pub monitor_devices: BTreeMap<String, String>,
+
+    /// Resample every sound to this sample rate before handing it to the output device. This
+    /// avoids artifacts of resamplers on some devices, at the cost of some CPU time on every bell,
+    /// and it's best set to the native rate of the device so it's not resampled twice. When this
+    /// key is not present, sounds are played at their own sample rate.
+    pub sample_rate: Option<NonZeroU32>,
+
+    /// Whether to decode sound files at startup and cache the decoded samples on disk, in
+    /// `$XDG_CACHE_HOME/onionbell`. Later startups load the cached samples instead of decoding
+    /// again, unless the file has been modified since. The default value is `false`.
+    #[serde(default)]
+    pub disk_cache: bool,
+
+    /// The most memory, in bytes, that preloaded sounds may take. Sounds are preloaded in the
+    /// order they appear in the config until the next one would go over the limit, and that one
+    /// and every later one are read from disk every time they're played instead. Builtin sounds
+    /// are always preloaded. When this key is not present, every sound is preloaded.
+    pub max_preload_bytes: Option<usize>,
+
+    /// How long, in milliseconds, a window state like floating or fullscreen has to stay the same
+    /// before its change counts, so states toggling during animations don't cause flapping. The
+    /// `event_sound`s of `changefloatingmode` and `fullscreen` play once the state settles. The
+    /// default value is 0, which counts every change immediately.
+    #[serde(default, deserialize_with = "deserialize_ms")]
+    pub debounce_ms: u64,
+
+    /// Log the handling of at most one event per this many milliseconds at the debug and trace
+    /// levels, so logs stay readable and cheap during bell storms. Events in between are still
+    /// handled, and counted in the next log. The default value is 0, which logs every event.
+    #[serde(default, deserialize_with = "deserialize_ms")]
+    pub log_throttle_ms: u64,
+
+    /// The longest event line, in bytes, read from Hyprland's event socket. The rest of a longer
+    /// line is discarded with a warning, so a runaway line can't take up unbounded memory. The
+    /// default value is 65536, and 0 disables the limit.
+    #[serde(default = "default_max_event_bytes")]
+    pub max_event_bytes: usize,
+
+    /// The most rules checked for a bell, as a safety cap for very large configs. Once it's
+    /// reached, matching stops and the global `sound` plays. Configs with more rules are warned
+    /// about when they're loaded. When this key is not present, every rule may be checked.
+    pub max_rules_evaluated: Option<usize>,
+
+    /// Whether a new sound stops the one that's still playing, instead of playing on top of it.
+    /// The default value is `false`.
+    #[serde(default)]
+    pub interrupt: bool,
+
+    /// With `interrupt`, how long, in milliseconds, the interrupted sound fades out while the new
+    /// one fades in. The default value is 0, which cuts the interrupted sound off.
+    #[serde(default, deserialize_with = "deserialize_ms")]
+    pub crossfade_ms: u64,
+
+    /// Milliseconds of silence played before every sound, for output devices that clip the start
+    /// of a sound while waking up. It comes before the fade-in of `crossfade_ms`. The default
+    /// value is 0.
+    #[serde(default, deserialize_with = "deserialize_ms")]
+    pub prelude_ms: u64,
+
+    /// Whether to mix every channel of a sound down to mono, for a single speaker that would
+    /// only play one channel of stereo sounds otherwise. The default value is `false`.
+    pub downmix_mono: Option<bool>,
+
+    /// How many times to try reading a sound again when it fails with an I/O error, e.g. a sound
+    /// on a network share that's briefly unavailable. Sounds that fail to decode are not tried
+    /// again, and neither are `sound_cmd`s. The default value is 2.
+    #[serde(default = "default_play_retries")]
+    pub play_retries: u32,
+
+    /// How many milliseconds a window counts as `urgent` for rules after it requested attention.
+    /// The default value is 10000.
+    #[serde(default = "default_urgent_ttl_ms", deserialize_with = "deserialize_ms")]
+    pub urgent_ttl_ms: u64,
+
+    /// Seed of the random number generator, making random choices reproducible. When this key is
+    /// not present, the generator is seeded from system entropy. `--seed` takes precedence.
+    pub seed: Option<u64>,
+
+    /// Volume factors by file extension, e.g. `wav = 0.7`, for evening out formats that are
+    /// louder than others. The factor of a sound file's extension, ignoring case, multiplies the
+    /// volume it's played at, whichever rule chose it. Other files play unchanged. Factors are
+    /// between 0.0 and 1.0.
+    #[serde(default, deserialize_with = "validate_volume_map")]
+    pub ext_volume: BTreeMap<String, f32>,
+
+    /// Named sets of window classes, which rules refer to with `group` instead of repeating a
+    /// `class_regex`.
+    #[serde(default)]
+    pub groups: BTreeMap<String, Vec<String>>,
+
+    /// Rules to match before using the global `sound` key as the audio file to play.
+    ///
+    /// Rules are checked in order, and the first match will be used. Rules with `force` set are
+    /// checked before the others.
+    #[serde(default, alias = "rule")]
+    pub rules: Vec<Rule>,
+
+    /// The `conf.d` directory next to the config file, whose files hold more rules. It's `None`
+    /// when the config wasn't read from a file.
+    #[serde(skip)]
+    pub conf_d: Option<PathBuf>,
+
+    /// Sound and volume overrides by the workspace of the source window, applied after rules.
+    /// The first matching override wins.
+    #[serde(default, alias = "workspace_override")]
+    pub workspace_overrides: Vec<WorkspaceOverride>,
+
+    /// Sounds to play on other Hyprland events, like `submap` or `screencast`, without matching
+    /// any window.
+    #[serde(default, alias = "event_sound")]
+    pub event_sounds: Vec<EventSound>,
+
+    /// The types of Hyprland events to handle, e.g. `["bell"]`. Other events are dropped right
+    /// away, except the ones that keys like `urgent` depend on. When this key is not present,
+    /// `bell` and the events of `event_sound`s are handled, see [`Config::handled_events`].
+    pub events: Option<Vec<String>>,
+
+    /// Regular expressions matched against the `class` property of the source window of a bell.
+    /// Bells from a window whose class matches any of them are dropped before rules are checked,
+    /// and play nothing at all.
+    #[serde(
+        deserialize_with = "serde_regex::deserialize",
+        serialize_with = "serialize_optional_regexes"
+    )]
+    #[serde(default)]
+    pub ignore_classes: Option<Vec<Regex>>,
+
+    /// Whether windows muted with the `mute-active` control command stay muted when onionbell
+    /// restarts, as long as they're open. The default value is `false`.
+    #[serde(default)]
+    pub persist_mutes: bool,
+}
+
+/// The same as an empty config file, with the defaults of the keys that have one.
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            sound: Default::default(),
+            volume: default_volume(),
+            volume_offset_db: Default::default(),
+            escalate: Default::default(),
+            mqtt: Default::default(),
+            first_bell_sound: Default::default(),
+            first_bell_timeout_ms: Default::default(),
+            idle_exit_ms: Default::default(),
+            backend: Default::default(),
+            monitor_devices: Default::default(),
+            sample_rate: Default::default(),
+            disk_cache: Default::default(),
+            max_preload_bytes: Default::default(),
+            debounce_ms: Default::default(),
+            log_throttle_ms: Default::default(),
+            max_event_bytes: default_max_event_bytes(),
+            max_rules_evaluated: Default::default(),
+            interrupt: Default::default(),
+            crossfade_ms: Default::default(),
+            prelude_ms: Default::default(),
+            downmix_mono: Default::default(),
+            play_retries: default_play_retries(),
+            urgent_ttl_ms: default_urgent_ttl_ms(),
+            seed: Default::default(),
+            ext_volume: Default::default(),
+            groups: Default::default(),
+            rules: Default::default(),
+            conf_d: Default::default(),
+            workspace_overrides: Default::default(),
+            event_sounds: Default::default(),
+            events: Default::default(),
+            ignore_classes: Default::default(),
+            persist_mutes: Default::default(),
+        }
+    }
+}
+
+/// A sound played whenever Hyprland sends an event of a certain type, e.g. `submap`.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct EventSound {
+    /// The event type, which is the part before `>>` in Hyprland's socket2 events.
+    pub event: String,
+
+    /// When present, only events whose data (the part after `>>`) equals this play the sound,
+    /// e.g. the name of the submap for `submap` events, which is empty for the default submap.
+    pub data: Option<String>,
+
+    /// Path to the audio file to play.
+    pub sound: PathBuf,
+
+    /// The volume of the sound, ranges from 0.0 to 1.0.
+    /// The default value is 1.0.
+    #[serde(default = "default_volume", deserialize_with = "validate_volume")]
+    pub volume: f32,
+}
+
+/// A rule that matches against properties of the window who sends the `bell` event (we'll call it
+/// the *source window* afterwards).
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
+pub struct Rule {
+    /// An optional name of the rule, only used in logs.
+    pub name: Option<String>,
+
+    /// `sound` is an optional key, represents path to an audio file that will be played when the
+    /// `bell` event is triggered and the current rule matches. When this key is not present, no
+    /// sound will play at all when the rule matches the source window, even if the global `sound`
+    /// key is present, unless `inherit_sound` is set.
     pub sound: Option<PathBuf>,
 
+    /// When set to `true` and this rule has no `sound` of its own, the global `sound` is played
+    /// instead of nothing when the rule matches. The rule's `volume` still applies. A rule with its
+    /// own `sound` always plays that sound regardless of this key.
+    pub inherit_sound: Option<bool>,
+
+    /// A list of audio files to pick from every time the rule matches, used instead of `sound`
+    /// when it's not empty. Each rule counts how many times it has matched, and by default the
+    /// list is played in turn, starting over after the last one.
+    pub sounds: Option<Vec<PathBuf>>,
+
+    /// Play the last entry of `sounds` on every `nth` match of the rule instead (the `nth`, the
+    /// `2 * nth`, ...), and take turns through the other entries on the remaining matches. With
+    /// a single entry in `sounds`, that one is always played.
+    pub nth: Option<NonZeroU64>,
+
+    /// How to pick from `sounds`, see [`Select`]. The default value is `"cycle"`.
+    pub select: Option<Select>,
+
+    /// A shell command whose standard output is decoded and played instead of `sound` or
+    /// `sounds`, e.g. a text-to-speech script. `{title}`, `{class}`, `{address}` and `{workspace}`
+    /// are replaced with the properties of the source window, quoted for the shell. The command is
+    /// killed if it takes longer than 5 seconds.
+    pub sound_cmd: Option<String>,
+
+    /// A pattern of synthesized beeps played instead of `sound` or `sounds`, so the rule needs no
+    /// audio file at all, see [`Beeps`].
+    pub beeps: Option<Beeps>,
+
     /// The volume of the sound, ranges from 0.0 to 1.0.
     /// The default value is 1.0.
     #[serde(default = "default_volume", deserialize_with = "validate_volume")]
     pub volume: f32,
 
-    /// Rules to match before using the global `sound` key as the audio file to play.
-    ///
-    /// Rules are checked in order, and the first match will be used.
-    #[serde(default, alias = "rule")]
-    pub rules: Vec<Rule>,
-}
+    /// The volume of the sound in decibels, where 0.0 is full volume and `-6.0` is about half,
+    /// used instead of `volume`. The global `volume_offset_db` is added to it.
+    #[serde(default, deserialize_with = "validate_optional_db")]
+    pub volume_db: Option<f32>,
+
+    /// Makes the rule's sound quieter and quieter when it fires in quick succession, to reduce
+    /// alert fatigue. When this key is not present, the volume doesn't change.
+    pub fatigue: Option<Fatigue>,
+
+    /// The lowest volume the rule's sound plays at, ranging from 0.0 to 1.0, e.g. for alerts that
+    /// must stay audible. It applies after everything else that changes the volume, like
+    /// workspace overrides and `ext_volume`.
+    #[serde(default, deserialize_with = "validate_optional_volume")]
+    pub volume_floor: Option<f32>,
+
+    /// A JACK port to play the sound on, written as `client:port`, e.g. `system:playback_1`.
+    /// The audio library onionbell uses can't connect to chosen JACK ports, so the sound still
+    /// plays on the default output, and a warning is logged when the config is loaded.
+    pub jack_port: Option<String>,
+
+    /// Output devices to play the sound on all at once, e.g. speakers and a headset, instead of
+    /// the default output or the one in `monitor_devices`. Device names are the ones of the audio
+    /// `backend`. Devices that fail to open are skipped, and when none of them opens, the sound
+    /// plays on the default output.
+    pub devices: Option<Vec<String>>,
+
+    /// When set to `true`, a BEL character is written to onionbell's standard output when the
+    /// rule matches, in addition to the sound, as a cue without audio, e.g. over SSH. Nothing is
+    /// written when the output isn't a terminal.
+    pub terminal_bell: Option<bool>,
+
+    /// After the rule plays for a window, more bells from the same window that the rule matches
+    /// stay quiet for this many milliseconds. A bell from another window plays right away and
+    /// starts the cooldown over for that window.
+    #[serde(default, deserialize_with = "deserialize_optional_ms")]
+    pub window_cooldown_ms: Option<u64>,
+
+    /// How many milliseconds to wait before playing the sound of the rule, e.g. to desync it from
+    /// visual cues. Sounds disabled in the meantime are not played.
+    #[serde(default, deserialize_with = "deserialize_optional_ms")]
+    pub delay_ms: Option<u64>,
+
+    /// Up to how many milliseconds to wait on top of `delay_ms`, picked at random for every bell
+    /// for a more natural feel.
+    #[serde(default, deserialize_with = "deserialize_optional_ms")]
+    pub delay_range_ms: Option<u64>,
+
+    /// When set to `true`, the rule only matches bells whose source window can't be found, e.g.
+    /// because it closed right after ringing, and the other predicates are not checked. When set
+    /// to `false`, the rule only matches bells from known windows, like any other rule.
+    pub orphan: Option<bool>,
+
+    /// When set to `true`, the rule is checked before all rules without it, so it wins even when
+    /// an earlier rule, like a broad silence rule, matches as well. Forced rules are checked among
+    /// themselves in order.
+    pub force: Option<bool>,
+
+    /// When set to `true`, the rule fires at most once for each window, e.g. for a welcome sound.
+    /// Later bells from the same window skip the rule as if it didn't match, until the window is
+    /// closed.
+    pub once_per_window: Option<bool>,
+
+    /// What tells windows apart for `once_per_window`, see [`WindowKey`]. The default value is
+    /// `"address"`.
+    pub window_key: Option<WindowKey>,
+
+    /// When set to `true` and the source window is in a group, the rest of the rule is matched
+    /// against the group leader (the first window of the group) instead. Windows that are not
+    /// grouped are matched as usual.
+    pub match_group_leader: Option<bool>,
+
+    /// The workspace that the source window lives in.
+    pub workspace: Option<WorkspaceRule>,
+
+    /// Whether the source window is on a special workspace, like a scratchpad, whose name starts
+    /// with `special:`.
+    pub special_workspace: Option<bool>,
+
+    /// The name of the special workspace the source window is on, without the `special:` prefix,
+    /// e.g. `magic`. Windows on regular workspaces never match.
+    pub special_workspace_name: Option<String>,
+
+    /// Whether the source window is floating.
+    pub floating: Option<bool>,
+
+    /// A regular expression to match with the `class` property of the source window.
+    #[serde(with = "serde_regex")]
+    #[serde(default)]
+    pub class_regex: Option<Regex>,
+
+    /// A regular expression to match with the `title` property of the source window.
+    #[serde(with = "serde_regex")]
+    #[serde(default)]
+    pub title_regex: Option<Regex>,
+
+    /// Regular expressions that all have to match the `title` property of the source window. This
+    /// is checked in addition to `title_regex`.
+    #[serde(
+        deserialize_with = "serde_regex::deserialize",
+        serialize_with = "serialize_optional_regexes"
+    )]
+    #[serde(default)]
+    pub title_regex_all: Option<Vec<Regex>>,
+
+    /// A regular expression to match with the `class` and `title` properties of the source window
+    /// joined by a `|`, e.g. `kitty|tmux a`. Remember to escape the `|` in the pattern.
+    #[serde(with = "serde_regex")]
+    #[serde(default)]
+    pub combined_regex: Option<Regex>,
+
+    /// Text that the `xdg_description` property of the source window must contain, for simple
+    /// matches without a regular expression. The comparison is case-sensitive.
+    pub xdg_description_contains: Option<String>,
+
+    /// A JSON pointer into the source window as reported by `hyprctl clients -j`, e.g. `/tags`
+    /// or `/workspace/name`, for matching fields that onionbell doesn't know about. The field
+    /// must exist, and equal `field_value` and match `field_regex` when they are present.
+    pub field: Option<String>,
+
+    /// The value that the field at `field` must equal, of any type, e.g. `true` or `["urgent"]`.
+    pub field_value: Option<serde_json::Value>,
+
+    /// A regular expression to match with the field at `field`. Strings are matched as they are,
+    /// and other values as JSON, e.g. `["urgent"]`.
+    #[serde(with = "serde_regex")]
+    #[serde(default)]
+    pub field_regex: Option<Regex>,
+
+    /// The name of an entry in `groups`. The class of the source window must equal one of the
+    /// classes in the group.
+    pub group: Option<String>,
+
+    /// The classes of `group`, resolved when the config is loaded.
+    #[serde(skip)]
+    pub group_classes: Option<RegexSet>,
+
+    /// A regular expression to match with the executable name of the source window's process, as
+    /// found in `/proc/<pid>/comm`. Useful for apps whose `class` is generic or changes. Windows
+    /// whose process can't be looked up never match.
+    #[serde(with = "serde_regex")]
+    #[serde(default)]
+    pub exe_regex: Option<Regex>,
+
+    /// Whether the source window is an XWayland window.
+    pub xwayland: Option<bool>,
+
+    /// Whether the source window is mapped.
+    pub mapped: Option<bool>,
+
+    /// Whether the source window is hidden, e.g. minimized or an inactive tab in a group.
+    pub hidden: Option<bool>,
+
+    /// Whether the source window is swallowing another window, like a terminal that launched a
+    /// GUI program.
+    pub swallowing: Option<bool>,
+
+    /// The minimum aspect ratio (width divided by height) of the source window, e.g. `0.8` together
+    /// with `max_aspect = 1.25` for square-ish popups. Windows with a height of 0 never match.
+    pub min_aspect: Option<f32>,
+
+    /// The maximum aspect ratio (width divided by height) of the source window. Windows with a
+    /// height of 0 never match.
+    pub max_aspect: Option<f32>,
+
+    /// The minimum opacity of the source window, from 0.0 to 1.0. Only some Hyprland versions
+    /// report the opacity of windows, and windows without it never match.
+    pub min_opacity: Option<f32>,
+
+    /// The maximum opacity of the source window, from 0.0 to 1.0, e.g. `0.9` for
+    /// semi-transparent overlays. Windows whose opacity isn't reported never match.
+    pub max_opacity: Option<f32>,
+
+    /// The minimum number of windows whose `class` matches `class_regex`, including the source
+    /// window. When `class_regex` is not present, all windows are counted.
+    pub min_window_count: Option<usize>,
+
+    /// The maximum number of windows whose `class` matches `class_regex`, including the source
+    /// window. When `class_regex` is not present, all windows are counted.
+    pub max_window_count: Option<usize>,
+
+    /// The minimum number of windows in the group of the source window, the source window
+    /// included. A window that is not in a group counts as a group of 1.
+    pub min_group_size: Option<usize>,
+
+    /// The maximum number of windows in the group of the source window, the source window
+    /// included. A window that is not in a group counts as a group of 1.
+    pub max_group_size: Option<usize>,
+
+    /// Whether the source window is the only window on its workspace. Special workspaces, like
+    /// scratchpads, count as workspaces of their own, separate from the regular workspace on the
+    /// same monitor.
+    pub solo_on_workspace: Option<bool>,
+
+    /// A regular expression to match with the `class` of the focused window, which may be another
+    /// window than the source window, e.g. to ring only while a terminal is focused. When the
+    /// focused window can't be fetched, the rule doesn't match.
+    #[serde(with = "serde_regex")]
+    #[serde(default)]
+    pub active_class_regex: Option<Regex>,
+
+    /// The horizontal resolution of the monitor of the source window in physical pixels, e.g.
+    /// 3840 for a 4K monitor. When the monitors can't be fetched, the rule doesn't match.
+    pub monitor_width: Option<i32>,
+
+    /// The vertical resolution of the monitor of the source window in physical pixels.
+    pub monitor_height: Option<i32>,
+
+    /// The scale of the monitor of the source window, compared to two decimal places.
+    pub monitor_scale: Option<f32>,
+
+    /// Whether the source window requested attention within the last `urgent_ttl_ms`, as told by
+    /// Hyprland's `urgent` event.
+    pub urgent: Option<bool>,
+
+    /// Whether the source window has moved since it opened, a hint that the user interacted with
+    /// it. Windows that opened before onionbell started never match.
+    pub moved: Option<bool>,
+
+    /// Whether the source window has been resized since it opened. Windows that opened before
+    /// onionbell started never match.
+    pub resized: Option<bool>,
+
+    /// The name of the file in `conf.d` that the rule was read from, or `None` for the rules of
+    /// the config itself.
+    #[serde(skip)]
+    pub file: Option<String>,
+
+    /// Whether `volume` was given besides `volume_db`, which takes precedence.
+    #[serde(skip)]
+    pub volume_overridden: bool,
+}
+
+/// A rule file in `conf.d`, see [`Config::merge_file`].
+#[derive(Debug, Deserialize)]
+struct RuleFile {
+    #[serde(default, alias = "rule")]
+    rules: Vec<Rule>,
+}
+
+/// How many windows rules with `once_per_window` remember having fired for. Windows remembered
+/// by a stable key are never forgotten when they close, so the ones that fired longest ago are
+/// forgotten once there are more.
+pub const MAX_FIRED_WINDOWS: usize = 4096;
+
+/// What rules remember between bells, kept by the caller of [`Config::resolve_sound`].
+#[derive(Debug, Default)]
+pub struct RuleState {
+    /// How many times each rule has matched, keyed by the index of the rule.
+    pub counters: HashMap<usize, u64>,
+
+    /// Windows that rules with `once_per_window` have fired for, as pairs of the index of the rule
+    /// and the key of the window, see [`Rule::once_key`], with the order in which they fired. See
+    /// [`RuleState::fire`].
+    pub fired: HashMap<(usize, String), u64>,
+
+    /// How many times rules with `once_per_window` have fired, which orders `fired`.
+    pub fired_count: u64,
+
+    /// The focused window, fetched by the caller for rules with `active_class_regex`. It should
+    /// be reset when the focus changes.
+    pub active_window: Option<HyprActiveWindow>,
+
+    /// The monitors, fetched by the caller for rules with `monitor_width`, `monitor_height` or
+    /// `monitor_scale`. It should be reset when monitors change.
+    pub monitors: Option<Vec<HyprMonitor>>,
+
+    /// The geometry of each window when it opened, keyed by its address. Only recorded when a rule
+    /// has `moved` or `resized`.
+    pub initial_geometry: HashMap<String, Geometry>,
+
+    /// When each window last requested attention, keyed by its address.
+    pub urgent: HashMap<String, Instant>,
+}
+
+impl RuleState {
+    /// Forget about the window at `address`, e.g. when it's closed, so rules with
+    /// `once_per_window` fire again for a new window that reuses the address. Windows remembered
+    /// by a stable key are kept, as a new window may be the same one again.
+    pub fn forget_window(&mut self, address: &str) {
+        self.fired.retain(|(_, x), _| x != address);
+        self.initial_geometry.remove(address);
+        self.urgent.remove(address);
+    }
+
+    /// Remember that rule #`rule` fired for the window with the key `key`. The window that it fired
+    /// for longest ago is forgotten when there are more than [`MAX_FIRED_WINDOWS`].
+    pub fn fire(&mut self, rule: usize, key: String) {
+        self.fired.insert((rule, key), self.fired_count);
+        self.fired_count += 1;
+        if self.fired.len() > MAX_FIRED_WINDOWS
+            && let Some(oldest) = self
+                .fired
+                .iter()
+                .min_by_key(|(_, x)| **x)
+                .map(|(key, _)| key.clone())
+        {
+            trace!("forgetting that rule #{} fired for {}", oldest.0, oldest.1);
+            self.fired.remove(&oldest);
+        }
+    }
+
+    /// Remember that the window at `address` requested attention at `now`. Windows that
+    /// requested it longer than `ttl` ago are forgotten on the way.
+    pub fn mark_urgent(&mut self, address: &str, ttl: Duration, now: Instant) {
+        self.urgent
+            .retain(|_, since| now.saturating_duration_since(*since) < ttl);
+        self.urgent.insert(address.to_string(), now);
+    }
+
+    /// Whether the window at `address` requested attention less than `ttl` before `now`.
+    pub fn is_urgent(&self, address: &str, ttl: Duration, now: Instant) -> bool {
+        self.urgent
+            .get(address)
+            .is_some_and(|since| now.saturating_duration_since(*since) < ttl)
+    }
+
+    /// What is known about the window at `address` at `now`, for matching rules against it.
+    pub fn window_state(
+        &self,
+        address: &str,
+        urgent_ttl: Duration,
+        now: Instant,
+    ) -> WindowState<'_> {
+        WindowState {
+            initial_geometry: self.initial_geometry.get(address).copied(),
+            urgent: self.is_urgent(address, urgent_ttl, now),
+            monitors: self.monitors.as_deref(),
+            active_window: self.active_window.as_ref(),
+        }
+    }
+}
+
+impl Rule {
+    /// Every audio file the rule may play.
+    pub fn all_sounds(&self) -> impl Iterator<Item = &PathBuf> {
+        self.sound.iter().chain(self.sounds.iter().flatten())
+    }
+
+    /// The key `once_per_window` remembers the window at `address` by, see `window_key`. An
+    /// orphan window is always remembered by its address.
+    pub fn once_key(&self, client: Option<&HyprClient>, address: &str) -> String {
+        match (self.window_key, client) {
+            (Some(WindowKey::Stable), Some(client)) => client.window_key(),
+            _ => address.to_string(),
+        }
+    }
+
+    /// The audio file to play when the rule matches for the `count + 1`-th time for the window at
+    /// `address`. See `sounds`, `nth` and `select` for how it's picked.
+    pub fn pick_sound(&self, count: u64, address: &str) -> Option<&PathBuf> {
+        match self.select {
+            Some(Select::Hash) => self.hash_sound(address),
+            Some(Select::Cycle) | None => self.select_sound(count),
+        }
+    }
+
+    /// The entry of `sounds` for the window at `address` with `select = "hash"`, which is the same
+    /// for as long as the window exists.
+    pub fn hash_sound(&self, address: &str) -> Option<&PathBuf> {
+        let sounds = match self.sounds {
+            Some(ref sounds) if !sounds.is_empty() => sounds,
+            _ => return self.sound.as_ref(),
+        };
+        let mut hasher = DefaultHasher::new();
+        address.hash(&mut hasher);
+        sounds.get((hasher.finish() % sounds.len() as u64) as usize)
+    }
+
+    /// The audio file to play when the rule matches for the `count + 1`-th time. See `sounds` and
+    /// `nth` for how it's picked.
+    pub fn select_sound(&self, count: u64) -> Option<&PathBuf> {
+        let sounds = match self.sounds {
+            Some(ref sounds) if !sounds.is_empty() => sounds,
+            _ => return self.sound.as_ref(),
+        };
+        let len = sounds.len() as u64;
+        let index = match self.nth {
+            Some(nth) if len > 1 => {
+                let nth = nth.get();
+                if (count + 1).is_multiple_of(nth) {
+                    len - 1
+                } else {
+                    // Skip the matches that played the last entry.
+                    (count - count / nth) % (len - 1)
+                }
+            }
+            _ => count % len,
+        };
+        sounds.get(index as usize)
+    }
+
+    /// Match against a window described by a JSON object with Hyprland's field names, as in
+    /// `hyprctl clients -j`. Missing fields take their default values. Predicates looking at other
+    /// windows only see this one, and `state` is what is known about it besides its fields.
+    pub fn matches_value(&self, value: &serde_json::Value, state: &WindowState) -> bool {
+        match HyprClient::from_partial_value(value.clone()) {
+            Ok(client) => client.matches(std::slice::from_ref(&client), self, state),
+            Err(err) => {
+                trace!("not a window: {err}");
+                false
+            }
+        }
+    }
+
+    /// Match `active_class_regex` of the rule against the focused window, which is `None` if it's
+    /// unknown.
+    pub fn matches_active(&self, active: Option<&HyprActiveWindow>) -> bool {
+        match self.active_class_regex {
+            Some(ref regex) => active.is_some_and(|x| regex.is_match(&x.class)),
+            None => true,
+        }
+    }
+
+    /// Match the monitor predicates of the rule against the monitor of the source window, which
+    /// is `None` if it's unknown.
+    pub fn matches_monitor(&self, monitor: Option<&HyprMonitor>) -> bool {
+        if self.monitor_width.is_none()
+            && self.monitor_height.is_none()
+            && self.monitor_scale.is_none()
+        {
+            return true;
+        }
+        monitor.is_some_and(|monitor| {
+            self.monitor_width.is_none_or(|x| x == monitor.width)
+                && self.monitor_height.is_none_or(|x| x == monitor.height)
+                && self
+                    .monitor_scale
+                    .is_none_or(|x| (x - monitor.scale).abs() < 0.005)
+        })
+    }
+
+    /// How long to wait before playing the sound of the rule, with the random part of
+    /// `delay_range_ms` picked from `rng`.
+    pub fn delay(&self, rng: &mut impl Rng) -> Duration {
+        let jitter = match self.delay_range_ms {
+            Some(range) => rng.random_range(0..=range),
+            None => 0,
+        };
+        Duration::from_millis(self.delay_ms.unwrap_or(0).saturating_add(jitter))
+    }
+
+    /// Match `urgent` of the rule against whether the window requested attention recently.
+    pub fn matches_urgent(&self, urgent: bool) -> bool {
+        self.urgent.is_none_or(|x| x == urgent)
+    }
+
+    /// Match `moved` and `resized` of the rule against the `current` geometry of a window and its
+    /// `initial` one, which is `None` if the window opened before onionbell started.
+    pub fn matches_geometry(&self, current: Option<Geometry>, initial: Option<Geometry>) -> bool {
+        if self.moved.is_none() && self.resized.is_none() {
+            return true;
+        }
+        let (Some(current), Some(initial)) = (current, initial) else {
+            trace!("initial geometry unknown");
+            return false;
+        };
+        self.moved.is_none_or(|x| (current.at != initial.at) == x)
+            && self
+                .resized
+                .is_none_or(|x| (current.size != initial.size) == x)
+    }
+
+    /// Describe the rule in one line for logs, e.g.
+    /// `kitty: class_regex = "^kitty$", floating = true -> /tmp/kitty.wav at volume 0.5`.
+    pub fn describe(&self) -> String {
+        fn regex(x: &Regex) -> String {
+            format!("{:?}", x.as_str())
+        }
+
+        let mut predicates = Vec::new();
+        let mut push = |name: &str, value: Option<String>| {
+            if let Some(value) = value {
+                predicates.push(format!("{name} = {value}"));
+            }
+        };
+        let flag = |x: Option<bool>| x.map(|x| x.to_string());
+        let number = |x: Option<f32>| x.map(|x| x.to_string());
+        push("force", flag(self.force));
+        push("once_per_window", flag(self.once_per_window));
+        push(
+            "window_key",
+            self.window_key.map(|x| match x {
+                WindowKey::Address => "\"address\"".to_string(),
+                WindowKey::Stable => "\"stable\"".to_string(),
+            }),
+        );
+        push("orphan", flag(self.orphan));
+        push("match_group_leader", flag(self.match_group_leader));
+        push(
+            "workspace",
+            self.workspace.as_ref().map(|x| match x {
+                WorkspaceRule::Id(id) => id.to_string(),
+                WorkspaceRule::Name(name) => format!("{name:?}"),
+            }),
+        );
+        push("special_workspace", flag(self.special_workspace));
+        push(
+            "special_workspace_name",
+            self.special_workspace_name
+                .as_ref()
+                .map(|x| format!("{x:?}")),
+        );
+        push("floating", flag(self.floating));
+        push("group", self.group.as_ref().map(|x| format!("{x:?}")));
+        push("class_regex", self.class_regex.as_ref().map(regex));
+        push("title_regex", self.title_regex.as_ref().map(regex));
+        push(
+            "title_regex_all",
+            self.title_regex_all
+                .as_ref()
+                .map(|x| format!("[{}]", x.iter().map(regex).collect::<Vec<_>>().join(", "))),
+        );
+        push("combined_regex", self.combined_regex.as_ref().map(regex));
+        push(
+            "xdg_description_contains",
+            self.xdg_description_contains
+                .as_ref()
+                .map(|x| format!("{x:?}")),
+        );
+        push("field", self.field.as_ref().map(|x| format!("{x:?}")));
+        push(
+            "field_value",
+            self.field_value.as_ref().map(|x| x.to_string()),
+        );
+        push("field_regex", self.field_regex.as_ref().map(regex));
+        push("exe_regex", self.exe_regex.as_ref().map(regex));
+        push("xwayland", flag(self.xwayland));
+        push("mapped", flag(self.mapped));
+        push("hidden", flag(self.hidden));
+        push("swallowing", flag(self.swallowing));
+        push("min_aspect", number(self.min_aspect));
+        push("max_aspect", number(self.max_aspect));
+        push("min_opacity", number(self.min_opacity));
+        push("max_opacity", number(self.max_opacity));
+        push(
+            "min_window_count",
+            self.min_window_count.map(|x| x.to_string()),
+        );
+        push(
+            "max_window_count",
+            self.max_window_count.map(|x| x.to_string()),
+        );
+        push("min_group_size", self.min_group_size.map(|x| x.to_string()));
+        push("max_group_size", self.max_group_size.map(|x| x.to_string()));
+        push("solo_on_workspace", flag(self.solo_on_workspace));
+        push(
+            "active_class_regex",
+            self.active_class_regex.as_ref().map(regex),
+        );
+        push("monitor_width", self.monitor_width.map(|x| x.to_string()));
+        push("monitor_height", self.monitor_height.map(|x| x.to_string()));
+        push("monitor_scale", number(self.monitor_scale));
+        push("urgent", flag(self.urgent));
+        push("moved", flag(self.moved));
+        push("resized", flag(self.resized));
+        let predicates = if predicates.is_empty() {
+            "any window".to_string()
+        } else {
+            predicates.join(", ")
+        };
+
+        let sounds = self
+            .all_sounds()
+            .map(|x| x.to_string_lossy())
+            .collect::<Vec<_>>();
+        let sound = if let Some(ref sound_cmd) = self.sound_cmd {
+            format!("`{sound_cmd}`")
+        } else if let Some(ref beeps) = self.beeps {
+            beeps.path().to_string_lossy().into_owned()
+        } else if sounds.len() > 1 {
+            format!("[{}]", sounds.join(", "))
+        } else if let Some(sound) = sounds.first() {
+            sound.to_string()
+        } else if self.inherit_sound == Some(true) {
+            "the global sound".to_string()
+        } else {
+            return format!("{}{predicates} -> silence", self.name_prefix());
+        };
+        format!(
+            "{}{predicates} -> {sound} at volume {}",
+            self.name_prefix(),
+            self.volume
+        )
+    }
+
+    /// Compile the classes of `group`, and set `volume` from `volume_db` shifted by
+    /// `volume_offset_db`. `i` is the index of the rule. Referring to a group that's not in
+    /// `groups` is an error.
+    fn resolve(
+        &mut self,
+        i: usize,
+        groups: &BTreeMap<String, Vec<String>>,
+        volume_offset_db: f32,
+    ) -> Result<(), AppError> {
+        if let Some(ref group) = self.group {
+            let Some(classes) = groups.get(group) else {
+                return Err(AppError::UndefinedGroup(i, group.clone()));
+            };
+            let patterns = classes.iter().map(|x| format!("^{}$", regex::escape(x)));
+            self.group_classes =
+                Some(RegexSet::new(patterns).expect("escaped classes are valid regexes"));
+        }
+        if let Some(volume_db) = self.volume_db {
+            let volume = db_to_volume(volume_db, volume_offset_db);
+            // Printed configs have both, with `volume` already resolved from `volume_db`.
+            self.volume_overridden = self.volume != default_volume() && self.volume != volume;
+            self.volume = volume;
+        }
+        Ok(())
+    }
+
+    /// Whether `value`, the field at `field` of the source window, matches `field_value` and
+    /// `field_regex`. A missing field never matches.
+    pub fn matches_field(&self, value: Option<&serde_json::Value>) -> bool {
+        let Some(value) = value else {
+            trace!("field not found");
+            return false;
+        };
+        self.field_value.as_ref().is_none_or(|x| x == value)
+            && self.field_regex.as_ref().is_none_or(|x| match value {
+                serde_json::Value::String(value) => x.is_match(value),
+                value => x.is_match(&value.to_string()),
+            })
+    }
+
+    fn name_prefix(&self) -> String {
+        self.name
+            .as_ref()
+            .map(|x| format!("{x}: "))
+            .unwrap_or_default()
+    }
+
+    /// The regexes of the rule with the names of their keys.
+    pub fn regexes(&self) -> Vec<(&'static str, &Regex)> {
+        let single = [
+            ("class_regex", &self.class_regex),
+            ("title_regex", &self.title_regex),
+            ("combined_regex", &self.combined_regex),
+            ("field_regex", &self.field_regex),
+            ("exe_regex", &self.exe_regex),
+            ("active_class_regex", &self.active_class_regex),
+        ];
+        let all = self
+            .title_regex_all
+            .iter()
+            .flatten()
+            .map(|x| ("title_regex_all", x));
+        single
+            .into_iter()
+            .filter_map(|(key, x)| Some((key, x.as_ref()?)))
+            .chain(all)
+            .collect()
+    }
+
+    /// Whether the rule has no conditions at all, so it matches every window.
+    pub fn is_catch_all(&self) -> bool {
+        self.same_predicates(&Rule::default())
+    }
+
+    /// Whether `self` and `other` check exactly the same conditions, regardless of what they play.
+    pub fn same_predicates(&self, other: &Rule) -> bool {
+        fn regex(x: &Option<Regex>) -> Option<&str> {
+            x.as_ref().map(Regex::as_str)
+        }
+        fn regexes(x: &Option<Vec<Regex>>) -> Option<Vec<&str>> {
+            x.as_ref().map(|x| x.iter().map(Regex::as_str).collect())
+        }
+
+        self.orphan == other.orphan
+            && self.match_group_leader == other.match_group_leader
+            && self.workspace == other.workspace
+            && self.special_workspace == other.special_workspace
+            && self.special_workspace_name == other.special_workspace_name
+            && self.floating == other.floating
+            && regex(&self.class_regex) == regex(&other.class_regex)
+            && self.group == other.group
+            && regex(&self.title_regex) == regex(&other.title_regex)
+            && regexes(&self.title_regex_all) == regexes(&other.title_regex_all)
+            && regex(&self.combined_regex) == regex(&other.combined_regex)
+            && self.xdg_description_contains == other.xdg_description_contains
+            && self.field == other.field
+            && self.field_value == other.field_value
+            && regex(&self.field_regex) == regex(&other.field_regex)
+            && regex(&self.exe_regex) == regex(&other.exe_regex)
+            && self.xwayland == other.xwayland
+            && self.mapped == other.mapped
+            && self.hidden == other.hidden
+            && self.swallowing == other.swallowing
+            && self.min_aspect == other.min_aspect
+            && self.max_aspect == other.max_aspect
+            && self.min_opacity == other.min_opacity
+            && self.max_opacity == other.max_opacity
+            && self.min_window_count == other.min_window_count
+            && self.max_window_count == other.max_window_count
+            && self.min_group_size == other.min_group_size
+            && self.max_group_size == other.max_group_size
+            && self.solo_on_workspace == other.solo_on_workspace
+            && regex(&self.active_class_regex) == regex(&other.active_class_regex)
+            && self.monitor_width == other.monitor_width
+            && self.monitor_height == other.monitor_height
+            && self.monitor_scale == other.monitor_scale
+            && self.urgent == other.urgent
+            && self.moved == other.moved
+            && self.resized == other.resized
+    }
+}
+
+/// The `escalate` table. Each bell arriving within `window_ms` of the previous one raises the
+/// volume factor by `step`, up to `cap`. Every full `window_ms` of silence lowers it by one `step`
+/// again, until it's back to 1.0.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct Escalate {
+    /// How much the volume factor grows with each repeated bell. The default value is 0.1.
+    #[serde(
+        default = "default_escalate_step",
+        deserialize_with = "validate_escalate_step"
+    )]
+    pub step: f32,
+
+    /// The maximum volume factor, at least 1.0. The default value is 1.5.
+    #[serde(
+        default = "default_escalate_cap",
+        deserialize_with = "validate_escalate_cap"
+    )]
+    pub cap: f32,
+
+    /// Bells closer than this many milliseconds count as repeated. The default value is 2000.
+    #[serde(
+        default = "default_escalate_window_ms",
+        deserialize_with = "deserialize_ms"
+    )]
+    pub window_ms: u64,
+}
+
+/// The `fatigue` table of a rule. Each bell arriving within `window_ms` of the previous one that
+/// the rule matched lowers the volume factor by `decay`, down to `min`. Every full `window_ms` of
+/// silence raises it by `recovery` again, until it's back to 1.0.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct Fatigue {
+    /// How much the volume factor drops with each repeated bell, between 0.0 and 1.0. The
+    /// default value is 0.1.
+    #[serde(
+        default = "default_fatigue_decay",
+        deserialize_with = "validate_fraction"
+    )]
+    pub decay: f32,
+
+    /// How much the volume factor recovers with each full `window_ms` of silence. The default
+    /// value is 0.1. Like `decay` and `min`, it's between 0.0 and 1.0.
+    #[serde(
+        default = "default_fatigue_recovery",
+        deserialize_with = "validate_fraction"
+    )]
+    pub recovery: f32,
+
+    /// The minimum volume factor, between 0.0 and 1.0. The default value is 0.3.
+    #[serde(
+        default = "default_fatigue_min",
+        deserialize_with = "validate_fraction"
+    )]
+    pub min: f32,
+
+    /// Bells closer than this many milliseconds count as repeated. The default value is 2000.
+    #[serde(
+        default = "default_fatigue_window_ms",
+        deserialize_with = "deserialize_ms"
+    )]
+    pub window_ms: u64,
+}
+
+/// The `beeps` table of a rule: `count` sine beeps at `freq` Hz, each lasting `ms` milliseconds,
+/// with `gap` milliseconds of silence in between.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct Beeps {
+    /// How many beeps to play, at most [`MAX_BEEPS`]. The default value is 1.
+    #[serde(
+        default = "default_beeps_count",
+        deserialize_with = "validate_beeps_count"
+    )]
+    pub count: u32,
+
+    /// The frequency of the beeps in Hz. The default value is 880.
+    #[serde(default = "default_beeps_freq")]
+    pub freq: f32,
+
+    /// How long each beep lasts in milliseconds. The default value is 120.
+    #[serde(default = "default_beeps_ms", deserialize_with = "deserialize_ms")]
+    pub ms: u64,
+
+    /// How long the silence between two beeps lasts in milliseconds. The default value is 80.
+    #[serde(default = "default_beeps_gap", deserialize_with = "deserialize_ms")]
+    pub gap: u64,
+}
+
+impl Beeps {
+    /// The key of the pattern among loaded sounds, which stands for it in logs, e.g.
+    /// `beeps:3x880hz,120ms,80ms`.
+    pub fn path(&self) -> PathBuf {
+        PathBuf::from(format!(
+            "beeps:{}x{}hz,{}ms,{}ms",
+            self.count, self.freq, self.ms, self.gap
+        ))
+    }
+
+    /// How long the whole pattern lasts, or [`Duration::MAX`] if that's too long to tell.
+    pub fn duration(&self) -> Duration {
+        let count = self.count as u64;
+        count
+            .checked_mul(self.ms)
+            .zip(count.saturating_sub(1).checked_mul(self.gap))
+            .and_then(|(beeps, gaps)| beeps.checked_add(gaps))
+            .map_or(Duration::MAX, Duration::from_millis)
+    }
+}
+
+/// The `mqtt` table. Each handled bell is published to `topic` with the JSON object printed by
+/// `--emit-json` as the payload.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct Mqtt {
+    /// Host name or address of the broker.
+    pub host: String,
+
+    /// The default value is 1883.
+    #[serde(default = "default_mqtt_port")]
+    pub port: u16,
+
+    /// The topic to publish to. `{class}`, `{address}`, `{workspace}` and `{rule}` are replaced
+    /// with the properties of the source window and the name or index of the matched rule. The
+    /// default value is `onionbell/bell`.
+    #[serde(default = "default_mqtt_topic")]
+    pub topic: String,
+
+    /// The default value is `onionbell`.
+    #[serde(default = "default_mqtt_client_id")]
+    pub client_id: String,
+
+    pub username: Option<String>,
+
+    /// Only sent along with `username`. It's left out when the config is printed.
+    #[serde(skip_serializing)]
+    pub password: Option<String>,
+}
+
+/// The sound chosen for a `bell` event.
+#[derive(Debug, PartialEq)]
+pub struct ResolvedSound {
+    /// The audio file to play, or `None` for silence.
+    pub sound: Option<PathBuf>,
+
+    /// The command whose output to play instead of `sound`, with the placeholders of `sound_cmd`
+    /// replaced.
+    pub command: Option<String>,
+
+    /// The volume to play the sound at.
+    pub volume: f32,
+
+    /// The `volume_floor` of the rule that matched.
+    pub volume_floor: Option<f32>,
+
+    /// Index of the rule that matched, or `None` if the global `sound` is used as fallback.
+    pub rule: Option<usize>,
+}
+
+/// Raise `volume`, which is the final volume after every other adjustment, to `floor`, which is
+/// the `volume_floor` of a rule.
+pub fn floor_volume(volume: f32, floor: Option<f32>) -> f32 {
+    floor.map_or(volume, |x| volume.max(x))
+}
+
+/// Split a JACK port name into its client and port, e.g. `system:playback_1` into `system` and
+/// `playback_1`. Port names may contain colons, but client names can't.
+pub fn split_jack_port(name: &str) -> Option<(&str, &str)> {
+    name.split_once(':')
+        .filter(|(client, port)| !client.is_empty() && !port.is_empty())
+}
+
+/// Replace the placeholders in `sound_cmd` with the properties of `client`, or with empty strings
+/// for orphan bells.
+pub fn expand_sound_cmd(sound_cmd: &str, client: Option<&HyprClient>) -> String {
+    let (title, class, address, workspace) = match client {
+        Some(x) => (&*x.title, &*x.class, &*x.address, &*x.workspace.name),
+        None => ("", "", "", ""),
+    };
+    sound_cmd
+        .replace("{title}", &shell_quote(title))
+        .replace("{class}", &shell_quote(class))
+        .replace("{address}", &shell_quote(address))
+        .replace("{workspace}", &shell_quote(workspace))
+}
+
+/// The type of `workspace` key in the rule.
+/// This key is an untagged enum. When `workspace` is a number, it will be matched against the
+/// `workspace.id` property of the source window. When it is a string, `workspace.name` will be
+/// checked instead.
+///
+/// Special workspaces (scratchpads) have negative ids and names like `special:magic`, and both
+/// forms can be matched as well. Their names are usually the more stable choice.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[serde(untagged)]
+pub enum WorkspaceRule {
+    /// `id` of the workspace.
+    Id(i32),
+
+    /// `name` of the workspace.
+    Name(String),
+}
+
+impl WorkspaceRule {
+    pub fn matches(&self, workspace: &HyprWorkspace) -> bool {
+        match self {
+            WorkspaceRule::Id(id) => &workspace.id == id,
+            WorkspaceRule::Name(name) => &workspace.name == name,
+        }
+    }
+}
+
+/// How a rule picks from its `sounds`.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Select {
+    /// Play the list in turn, see `nth`.
+    Cycle,
+
+    /// Pick by a hash of the address of the source window, so every window keeps its own sound
+    /// while different windows tend to get different ones.
+    Hash,
+}
+
+/// What a rule with `once_per_window` tells windows apart by.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum WindowKey {
+    /// The address of the window, so a window opened again is a new one.
+    Address,
+
+    /// The class, title and workspace of the window, see [`HyprClient::window_key`], so a window
+    /// opened again with the same ones, e.g. after restarting the application, is the same one.
+    Stable,
+}
+
+/// Overrides the sound or volume of bells from windows on a workspace, whichever rule matched.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct WorkspaceOverride {
+    /// The workspace of the source window, matched like the `workspace` key of rules.
+    pub workspace: WorkspaceRule,
+
+    /// Audio file to play instead of the one chosen by rules or the global `sound`. Bells that
+    /// would be silent stay silent.
+    pub sound: Option<PathBuf>,
+
+    /// Volume to play at instead of the one of the matched rule or the global `volume`.
+    #[serde(default, deserialize_with = "validate_optional_volume")]
+    pub volume: Option<f32>,
+}
+
+impl Config {
+    pub fn from_source<S: AsRef<str>>(source: S) -> Result<Config, AppError> {
+        let mut config: Config = toml::from_str(source.as_ref())?;
+        config.prepare_rules()?;
+        Ok(config)
+    }
+
+    /// Serialize the config back to TOML, with every key that has a value, including defaults.
+    pub fn to_toml(&self) -> Result<String, AppError> {
+        toml::to_string(self).map_err(AppError::from)
+    }
+
+    /// Parse a config written in JSON, with the same keys as in TOML.
+    pub fn from_json<S: AsRef<str>>(source: S) -> Result<Config, AppError> {
+        let mut config: Config = serde_json::from_str(source.as_ref())?;
+        config.prepare_rules()?;
+        Ok(config)
+    }
+
+    /// Resolve the group and `volume_db` of every rule, see [`Rule::resolve`].
+    fn prepare_rules(&mut self) -> Result<(), AppError> {
+        for (i, rule) in self.rules.iter_mut().enumerate() {
+            rule.resolve(i, &self.groups, self.volume_offset_db)?;
+        }
+        Ok(())
+    }
+
+    /// Merge the rules of every `.toml` file in `dir`, in the order of their names, after the
+    /// rules of the config itself. The directory is remembered for [`Config::reload_file`], and
+    /// it's fine if it doesn't exist.
+    pub fn load_conf_d(&mut self, dir: &Path) -> Result<(), AppError> {
+        self.conf_d = Some(dir.to_path_buf());
+        let entries = match fs::read_dir(dir) {
+            Ok(x) => x,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+        let mut names = entries
+            .filter_map(|x| x.ok())
+            .map(|x| x.file_name().to_string_lossy().into_owned())
+            .filter(|x| x.ends_with(".toml"))
+            .collect::<Vec<_>>();
+        names.sort();
+        for name in names {
+            trace!("merging {name}");
+            self.reload_file(&name)?;
+        }
+        Ok(())
+    }
+
+    /// Read the rule file `name` in `conf_d` again, and merge it with [`Config::merge_file`].
+    /// A file that no longer exists has no rules anymore. Returns how many rules it has.
+    pub fn reload_file(&mut self, name: &str) -> Result<usize, AppError> {
+        let Some(ref dir) = self.conf_d else {
+            return Err(AppError::NoConfDir);
+        };
+        if name.contains('/') || name == ".." {
+            return Err(AppError::InvalidArgument(format!(
+                "invalid rule file name {name:?}"
+            )));
+        }
+        let source = match File::open(dir.join(name)) {
+            Ok(file) => reader_to_string(file)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err.into()),
+        };
+        self.merge_file(name, &source)
+    }
+
+    /// Replace the rules that came from the rule file `name` with the ones in `source`, which
+    /// only has `[[rule]]` tables. They take the place of the old rules, or go among the rules of
+    /// the other files in the order of the file names if the file is new. The rules are left
+    /// unchanged if `source` is invalid. Returns how many rules the file has.
+    pub fn merge_file(&mut self, name: &str, source: &str) -> Result<usize, AppError> {
+        let RuleFile { mut rules } = toml::from_str(source)?;
+        let position = self
+            .rules
+            .iter()
+            .position(|x| x.file.as_deref().is_some_and(|x| x >= name))
+            .unwrap_or(self.rules.len());
+        for (i, rule) in rules.iter_mut().enumerate() {
+            rule.file = Some(name.to_string());
+            rule.resolve(position + i, &self.groups, self.volume_offset_db)?;
+        }
+        let count = rules.len();
+        self.rules.retain(|x| x.file.as_deref() != Some(name));
+        self.rules.splice(position..position, rules);
+        Ok(count)
+    }
+
+    pub fn debounce(&self) -> Duration {
+        Duration::from_millis(self.debounce_ms)
+    }
+
+    pub fn first_bell_timeout(&self) -> Option<Duration> {
+        self.first_bell_timeout_ms.map(Duration::from_millis)
+    }
+
+    pub fn idle_exit(&self) -> Option<Duration> {
+        self.idle_exit_ms.map(Duration::from_millis)
+    }
+
+    pub fn log_throttle(&self) -> Duration {
+        Duration::from_millis(self.log_throttle_ms)
+    }
+
+    pub fn crossfade(&self) -> Duration {
+        Duration::from_millis(self.crossfade_ms)
+    }
+
+    pub fn prelude(&self) -> Duration {
+        Duration::from_millis(self.prelude_ms)
+    }
+
+    pub fn urgent_ttl(&self) -> Duration {
+        Duration::from_millis(self.urgent_ttl_ms)
+    }
+
+    /// Decide what to play for a bell sent by the window at `address`. `clients` may be empty if
+    /// they failed to be fetched, in which case no rule will match. The counter of the matched
+    /// rule in `state` is advanced, and rules with `once_per_window` that already fired for the
+    /// window are skipped. Finally, the first `workspace_override` matching the workspace of the
+    /// window is applied.
+    pub fn resolve_sound(
+        &self,
+        clients: &[HyprClient],
+        address: &str,
+        state: &mut RuleState,
+    ) -> ResolvedSound {
+        let mut resolved = self.resolve_rules(clients, address, state);
+        let workspace_override = HyprClient::find(clients, address).and_then(|client| {
+            self.workspace_overrides
+                .iter()
+                .find(|x| x.workspace.matches(&client.workspace))
+        });
+        if let Some(workspace_override) = workspace_override {
+            trace!("workspace override: {workspace_override:?}");
+            if (resolved.sound.is_some() || resolved.command.is_some())
+                && workspace_override.sound.is_some()
+            {
+                resolved.sound = workspace_override.sound.clone();
+                resolved.command = None;
+            }
+            if let Some(volume) = workspace_override.volume {
+                resolved.volume = volume;
+            }
+        }
+        resolved
+    }
+
+    /// The index of the rule that a bell from the window at `address` would play, without
+    /// changing `state` like [`Config::resolve_sound`] does, or `None` for the global sound.
+    pub fn matching_rule(
+        &self,
+        clients: &[HyprClient],
+        address: &str,
+        state: &RuleState,
+    ) -> Option<usize> {
+        // Forced rules are checked first, then the rest in order.
+        let is_forced = |rule: &Rule| rule.force == Some(true);
+        let forced = self.rules.iter().enumerate().filter(|(_, x)| is_forced(x));
+        let others = self.rules.iter().enumerate().filter(|(_, x)| !is_forced(x));
+        let window = state.window_state(address, self.urgent_ttl(), Instant::now());
+        for (evaluated, (i, rule)) in forced.chain(others).enumerate() {
+            if self.max_rules_evaluated.is_some_and(|max| evaluated >= max) {
+                trace!("No rule matched within the first {evaluated} rules checked");
+                break;
+            }
+            if HyprClient::match_rule(clients, address, rule, &window) {
+                if rule.once_per_window == Some(true)
+                    && state.fired.contains_key(&(
+                        i,
+                        rule.once_key(HyprClient::find(clients, address), address),
+                    ))
+                {
+                    trace!("rule #{i} already fired for {address}");
+                    continue;
+                }
+                return Some(i);
+            }
+        }
+        None
+    }
+
+    fn resolve_rules(
+        &self,
+        clients: &[HyprClient],
+        address: &str,
+        state: &mut RuleState,
+    ) -> ResolvedSound {
+        let Some(i) = self.matching_rule(clients, address, state) else {
+            return ResolvedSound {
+                sound: self.sound.clone(),
+                command: None,
+                volume: self.volume,
+                volume_floor: None,
+                rule: None,
+            };
+        };
+        let rule = &self.rules[i];
+        if rule.once_per_window == Some(true) {
+            state.fire(
+                i,
+                rule.once_key(HyprClient::find(clients, address), address),
+            );
+        }
+        let count = state.counters.entry(i).or_default();
+        let selected = rule.pick_sound(*count, address);
+        *count += 1;
+        if let Some(ref sound_cmd) = rule.sound_cmd {
+            return ResolvedSound {
+                sound: None,
+                command: Some(expand_sound_cmd(
+                    sound_cmd,
+                    HyprClient::find(clients, address),
+                )),
+                volume: rule.volume,
+                volume_floor: rule.volume_floor,
+                rule: Some(i),
+            };
+        }
+        let sound = match (&rule.beeps, selected, rule.inherit_sound) {
+            (Some(beeps), _, _) => Some(beeps.path()),
+            (None, Some(sound), _) => Some(sound.clone()),
+            (None, None, Some(true)) => self.sound.clone(),
+            (None, None, _) => None,
+        };
+        ResolvedSound {
+            sound,
+            command: None,
+            volume: rule.volume,
+            volume_floor: rule.volume_floor,
+            rule: Some(i),
+        }
+    }
+
+    /// The `ext_volume` factor of the extension of `path`, or 1.0 if there's none.
+    pub fn ext_volume(&self, path: &Path) -> f32 {
+        let Some(extension) = path.extension() else {
+            return 1.0;
+        };
+        self.ext_volume
+            .iter()
+            .find(|(x, _)| extension.eq_ignore_ascii_case(x.as_str()))
+            .map_or(1.0, |(_, volume)| *volume)
+    }
+
+    /// Whether any rule has `active_class_regex`, so the focused window has to be fetched.
+    pub fn uses_active_window(&self) -> bool {
+        self.rules.iter().any(|x| x.active_class_regex.is_some())
+    }
+
+    /// Whether bells from `client` are dropped because of `ignore_classes`. Orphan bells, whose
+    /// window is unknown, never are.
+    pub fn is_ignored(&self, client: Option<&HyprClient>) -> bool {
+        let (Some(classes), Some(client)) = (&self.ignore_classes, client) else {
+            return false;
+        };
+        classes.iter().any(|x| x.is_match(&client.class))
+    }
+
+    /// The types of events to handle: `events` if present, otherwise `bell` and the events of
+    /// `event_sound`s. `closewindow` to forget closed windows, and the events that the keys in use
+    /// depend on, are always handled, so `events` can't leave stale state behind.
+    pub fn handled_events(&self) -> HashSet<String> {
+        let mut events = vec!["closewindow"];
+        if self.debounce_ms > 0 {
+            // `fullscreen` is about the focused window, which `activewindowv2` reports.
+            events.extend(DEBOUNCED_EVENTS);
+            events.push("activewindowv2");
+        }
+        if self.uses_active_window() {
+            events.extend(["activewindow", "activewindowv2"]);
+        }
+        if self.uses_monitors() {
+            events.extend([
+                "monitoradded",
+                "monitoraddedv2",
+                "monitorremoved",
+                "monitorremovedv2",
+                "configreloaded",
+            ]);
+        }
+        if self.tracks_geometry() {
+            events.push("openwindow");
+        }
+        if self.rules.iter().any(|x| x.urgent.is_some()) {
+            events.push("urgent");
+        }
+        let events = events.into_iter().map(String::from);
+        match self.events {
+            Some(ref picked) => events.chain(picked.iter().cloned()).collect(),
+            None => events
+                .chain(["bell".to_string()])
+                .chain(self.event_sounds.iter().map(|x| x.event.clone()))
+                .collect(),
+        }
+    }
+
+    /// Whether any rule has a monitor predicate, or `monitor_devices` is set, so the monitors have
+    /// to be fetched.
+    pub fn uses_monitors(&self) -> bool {
+        !self.monitor_devices.is_empty()
+            || self.rules.iter().any(|x| {
+                x.monitor_width.is_some() || x.monitor_height.is_some() || x.monitor_scale.is_some()
+            })
+    }
+
+    /// The output device in `monitor_devices` for the monitor of `client` among `monitors`, or
+    /// `None` for the default output.
+    pub fn monitor_device(
+        &self,
+        client: Option<&HyprClient>,
+        monitors: Option<&[HyprMonitor]>,
+    ) -> Option<&str> {
+        if self.monitor_devices.is_empty() {
+            return None;
+        }
+        let monitor = HyprMonitor::find(monitors?, client?.monitor)?;
+        trace!("monitor = {}", monitor.name);
+        self.monitor_devices.get(&monitor.name).map(String::as_str)
+    }
+
+    /// The output devices to play a bell from `client` on, when it matched the rule at index
+    /// `rule`: the `devices` of the rule, or else the device in `monitor_devices` for the monitor
+    /// of the window. No devices means the default output.
+    pub fn devices(
+        &self,
+        rule: Option<usize>,
+        client: Option<&HyprClient>,
+        monitors: Option<&[HyprMonitor]>,
+    ) -> Vec<String> {
+        if let Some(devices) = rule.and_then(|i| self.rules[i].devices.as_ref()) {
+            return devices.clone();
+        }
+        self.monitor_device(client, monitors)
+            .map(str::to_string)
+            .into_iter()
+            .collect()
+    }
+
+    /// Whether any rule has `moved` or `resized`, so the geometry of windows has to be recorded
+    /// when they open.
+    pub fn tracks_geometry(&self) -> bool {
+        self.rules
+            .iter()
+            .any(|x| x.moved.is_some() || x.resized.is_some())
+    }
+
+    /// Every audio file referenced by the config, possibly with duplicates.
+    pub fn sound_paths(&self) -> impl Iterator<Item = &PathBuf> {
+        self.sound
+            .iter()
+            .chain(self.first_bell_sound.iter())
+            .chain(self.rules.iter().flat_map(|x| x.all_sounds()))
+            .chain(
+                self.workspace_overrides
+                    .iter()
+                    .filter_map(|x| x.sound.as_ref()),
+            )
+            .chain(self.event_sounds.iter().map(|x| &x.sound))
+    }
+
+    /// The sound to play for an event of type `ev_type` with `data`. The first matching
+    /// `event_sound` wins.
+    pub fn event_sound(&self, ev_type: &str, data: &str) -> Option<&EventSound> {
+        self.event_sounds
+            .iter()
+            .find(|x| x.event == ev_type && x.data.as_ref().is_none_or(|x| x == data))
+    }
+
+    /// Rules that have sounds of their own, with their indices, in the order they're checked.
+    pub fn auditions(&self) -> impl Iterator<Item = (usize, &Rule)> {
+        self.rules
+            .iter()
+            .enumerate()
+            .filter(|(_, rule)| rule.all_sounds().next().is_some() || rule.beeps.is_some())
+    }
+
+    /// Check the config for likely mistakes, returning a warning message for each.
+    pub fn validate(&self) -> Vec<String> {
+        let mut warnings = self.duplicate_rules();
+        warnings.extend(self.key_warnings());
+        warnings
+    }
+
+    /// Rules that have the same conditions as another one, so only one of them ever matches.
+    pub fn duplicate_rules(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        for (i, rule) in self.rules.iter().enumerate() {
+            if let Some(j) = self.rules[..i].iter().position(|x| x.same_predicates(rule)) {
+                // A forced rule shadows an unforced one, wherever it is.
+                warnings.push(match (self.rules[j].force == Some(true), rule.force == Some(true)) {
+                    (false, true) => format!(
+                        "Rule #{j} has the same conditions as forced rule #{i}, so it will never \
+                        match"
+                    ),
+                    _ => format!(
+                        "Rule #{i} has the same conditions as rule #{j}, so it will never match"
+                    ),
+                });
+            }
+        }
+        warnings
+    }
+
+    /// Keys that are ignored or contradict each other.
+    pub fn key_warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if !cfg!(feature = "bundle")
+            && let Some(sfx_path) = self.sound_paths().find(|x| bundle::split(x).is_some())
+        {
+            warnings.push(format!(
+                "{} is in a bundle, but onionbell was built without the `bundle` feature, so it \
+                is not played",
+                sfx_path.to_string_lossy()
+            ));
+        }
+        if self.mqtt.is_some() && !cfg!(feature = "mqtt") {
+            warnings.push(
+                "`mqtt` is set, but onionbell was built without the `mqtt` feature, so bells are \
+                not published"
+                    .to_string(),
+            );
+        }
+        if let Some(ref mqtt) = self.mqtt
+            && mqtt.password.is_some()
+            && mqtt.username.is_none()
+        {
+            warnings.push("`mqtt.password` is not sent without `mqtt.username`".to_string());
+        }
+        if let Some(ref events) = self.events {
+            for event in self.event_sounds.iter().map(|x| &x.event) {
+                if !events.contains(event) {
+                    warnings.push(format!(
+                        "`event_sound` for `{event}` is never played, as `{event}` is not in \
+                        `events`"
+                    ));
+                }
+            }
+        }
+        if let Some(sample_rate) = self.sample_rate
+            && !(MIN_SAMPLE_RATE..=MAX_SAMPLE_RATE).contains(&sample_rate.get())
+        {
+            warnings.push(format!(
+                "`sample_rate = {sample_rate}` is outside of {MIN_SAMPLE_RATE} to \
+                {MAX_SAMPLE_RATE} Hz, which output devices don't play"
+            ));
+        }
+        if let Some(max) = self.max_rules_evaluated
+            && self.rules.len() > max
+        {
+            warnings.push(format!(
+                "Only {max} of the {} rules are checked for a bell, so bells that match none of \
+                them play the global sound. Raise `max_rules_evaluated` to check more.",
+                self.rules.len()
+            ));
+        }
+        if self.event_sounds.iter().any(|x| x.event == "bell") {
+            warnings.push(
+                "`event_sound` for `bell` plays on top of the sound chosen by rules".to_string(),
+            );
+        }
+        for (i, rule) in self.rules.iter().enumerate() {
+            if let Some(ref jack_port) = rule.jack_port {
+                warnings.push(match split_jack_port(jack_port) {
+                    Some(_) => format!(
+                        "Rule #{i} has `jack_port`, but playing through JACK is not supported, so \
+                        it plays on the default output"
+                    ),
+                    None => format!(
+                        "Rule #{i} has `jack_port = {jack_port:?}`, which is not in the form \
+                        `client:port`"
+                    ),
+                });
+            }
+            if rule.field.is_none() && (rule.field_value.is_some() || rule.field_regex.is_some()) {
+                warnings.push(format!(
+                    "Rule #{i} has `field_value` or `field_regex` without `field`, so they are \
+                    ignored"
+                ));
+            }
+            if let Some(ref field) = rule.field
+                && !field.is_empty()
+                && !field.starts_with('/')
+            {
+                warnings.push(format!(
+                    "Rule #{i} has `field = {field:?}`, which is not a JSON pointer starting with \
+                    `/`, so it never matches"
+                ));
+            }
+            if rule.sound_cmd.is_some() && rule.all_sounds().next().is_some() {
+                warnings.push(format!(
+                    "Rule #{i} has `sound_cmd`, so its `sound` and `sounds` will never be played"
+                ));
+            }
+            if rule.volume_overridden {
+                warnings.push(format!(
+                    "Rule #{i} has both `volume` and `volume_db`, so its `volume` is ignored"
+                ));
+            }
+            if rule.window_key.is_some() && rule.once_per_window != Some(true) {
+                warnings.push(format!(
+                    "Rule #{i} has `window_key` without `once_per_window = true`, so it has no \
+                    effect"
+                ));
+            }
+            if let Some(ref beeps) = rule.beeps {
+                if rule.sound_cmd.is_some() {
+                    warnings.push(format!(
+                        "Rule #{i} has `sound_cmd`, so its `beeps` will never be played"
+                    ));
+                } else if rule.all_sounds().next().is_some() {
+                    warnings.push(format!(
+                        "Rule #{i} has `beeps`, so its `sound` and `sounds` will never be played"
+                    ));
+                }
+                if beeps.count == 0 || beeps.freq <= 0.0 {
+                    warnings.push(format!(
+                        "Rule #{i} has `beeps` with a `count` or `freq` of 0, so it plays nothing"
+                    ));
+                }
+            }
+            if rule.select == Some(Select::Hash) && rule.nth.is_some() {
+                warnings.push(format!(
+                    "Rule #{i} has `select = \"hash\"`, so its `nth` is ignored"
+                ));
+            }
+            if rule.sound.is_some() && rule.sounds.as_ref().is_some_and(|x| !x.is_empty()) {
+                warnings.push(format!(
+                    "Rule #{i} has both `sound` and `sounds`, so `sound` will never be played"
+                ));
+            }
+        }
+        warnings
+    }
+
+    /// Read the whole reader as the config source, e.g. when the config is piped through stdin.
+    pub fn from_reader(reader: impl Read) -> Result<Config, AppError> {
+        reader_to_string(reader).and_then(Config::from_source)
+    }
+
+    /// Read the config at `path`, which is parsed as JSON if its extension is `.json`, and as
+    /// TOML otherwise.
+    pub fn from_path(path: &Path) -> Result<Config, AppError> {
+        let source = reader_to_string(File::open(path)?)?;
+        if path
+            .extension()
+            .is_some_and(|x| x.eq_ignore_ascii_case("json"))
+        {
+            Config::from_json(source)
+        } else {
+            Config::from_source(source)
+        }
+    }
+}
+
+#[allow(unused)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn invalid_volume() {
+        let error = Config::from_source("volume = 100.0");
+        assert!(error.is_err());
+
+        let AppError::TomlDeserializationError(error) = error.unwrap_err() else {
+            unreachable!()
+        };
+        assert_eq!(
+            error.message(),
+            "invalid value: floating point `100.0`, expected volume must be between 0.0 and 1.0"
+        );
+        assert_eq!(error.span(), Some(9..14));
+
+        let error = Config::from_source(
+            r#"
+            [[rule]]
+            volume = 100.0
+            "#,
+        );
+        assert!(error.is_err());
+
+        let AppError::TomlDeserializationError(error) = error.unwrap_err() else {
+            unreachable!()
+        };
+        assert_eq!(
+            error.message(),
+            "invalid value: floating point `100.0`, expected volume must be between 0.0 and 1.0"
+        );
+        assert_eq!(error.span(), Some(43..48));
+    }
+
+    #[test]
+    fn test_from_reader() {
+        let config = Config::from_reader(std::io::Cursor::new(
+            r#"
+            sound = "/tmp/bell.wav"
+            volume = 0.5
+
+            [[rule]]
+            floating = true
+            "#,
+        ))
+        .unwrap();
+        assert_eq!(config.sound, Some(PathBuf::from("/tmp/bell.wav")));
+        assert_eq!(config.volume, 0.5);
+        assert_eq!(config.rules.len(), 1);
+        assert_eq!(config.rules[0].floating, Some(true));
+    }
+
+    #[test]
+    fn test_empty_source() {
+        let config = Config::from_source("").unwrap();
+        assert!(config.sound.is_none());
+        assert!(config.rules.is_empty());
+    }
+    #[test]
+    fn test_toplevel_fields() {
+        let config = Config::from_source(
+            "sound = \"/home/onion27/Music/2-14. 渦状銀河のシンフォニエッタ.mp3\"",
+        )
+        .unwrap();
+        assert_eq!(
+            config.sound,
+            Some(PathBuf::from(
+                "/home/onion27/Music/2-14. 渦状銀河のシンフォニエッタ.mp3"
+            ))
+        );
+        assert_eq!(config.volume, 1.0);
+        assert!(config.escalate.is_none());
+        assert!(config.rules.is_empty());
+    }
+
+    #[test]
+    fn test_interrupt() {
+        let config = Config::from_source("").unwrap();
+        assert!(!config.interrupt);
+        assert_eq!(config.crossfade(), Duration::ZERO);
+        let config = Config::from_source("interrupt = true\ncrossfade_ms = 80").unwrap();
+        assert!(config.interrupt);
+        assert_eq!(config.crossfade(), Duration::from_millis(80));
+    }
+
+    #[test]
+    fn test_fatigue() {
+        let config = Config::from_source(
+            r#"
+            [[rule]]
+            fatigue = { decay = 0.2 }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.rules[0].fatigue,
+            Some(Fatigue {
+                decay: 0.2,
+                recovery: 0.1,
+                min: 0.3,
+                window_ms: 2000,
+            })
+        );
+
+        for fatigue in ["decay = -0.1", "recovery = nan", "min = 1.5", "decay = inf"] {
+            let source = format!("[[rule]]\nfatigue = {{ {fatigue} }}");
+            assert!(Config::from_source(&source).is_err(), "{source}");
+        }
+    }
+
+    #[test]
+    fn test_escalate() {
+        let config = Config::from_source(
+            r#"
+            [escalate]
+            step = 0.2
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.escalate,
+            Some(Escalate {
+                step: 0.2,
+                cap: 1.5,
+                window_ms: 2000,
+            })
+        );
+
+        for escalate in ["step = -0.1", "step = nan", "cap = 0.5", "cap = inf"] {
+            let source = format!("[escalate]\n{escalate}");
+            assert!(Config::from_source(&source).is_err(), "{source}");
+        }
+    }
+
+    fn kitty_clients() -> Vec<HyprClient> {
+        serde_json::from_str(
+            r#"
+            [{
+                "address": "0x558e91924520",
+                "mapped": true,
+                "hidden": false,
+                "at": [9, 49],
+                "size": [1582, 942],
+                "workspace": {
+                    "id": 1,
+                    "name": "1"
+                },
+                "floating": false,
+                "pseudo": false,
+                "monitor": 0,
+                "class": "kitty",
+                "title": "tmux a",
+                "initialClass": "kitty",
+                "initialTitle": "kitty",
+                "pid": 1379,
+                "xwayland": false,
+                "pinned": false,
+                "fullscreen": 0,
+                "fullscreenClient": 0,
+                "grouped": [],
+                "tags": [],
+                "swallowing": "0x0",
+                "focusHistoryID": 0,
+                "inhibitingIdle": false,
+                "xdgTag": "",
+                "xdgDescription": "",
+                "contentType": "none"
+            }]
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_resolve_sound() {
+        let config = Config::from_source(
+            r#"
+            sound = "/tmp/global.wav"
+            volume = 0.5
+
+            [[rule]]
+            class_regex = "^kitty$"
+            volume = 0.8
+            inherit_sound = true
+
+            [[rule]]
+            class_regex = "^kitty$"
+            sound = "/tmp/kitty.wav"
+            "#,
+        )
+        .unwrap();
+        let clients = kitty_clients();
+
+        // Matched rule without sound inherits the global one, keeping its own volume.
+        assert_eq!(
+            config.resolve_sound(&clients, "558e91924520", &mut RuleState::default()),
+            ResolvedSound {
+                sound: Some(PathBuf::from("/tmp/global.wav")),
+                command: None,
+                volume: 0.8,
+                volume_floor: None,
+                rule: Some(0),
+            }
+        );
+
+        // No match falls back to global sound and volume.
+        assert_eq!(
+            config.resolve_sound(&clients, "deadbeef", &mut RuleState::default()),
+            ResolvedSound {
+                sound: Some(PathBuf::from("/tmp/global.wav")),
+                command: None,
+                volume: 0.5,
+                volume_floor: None,
+                rule: None,
+            }
+        );
+        assert_eq!(
+            config.resolve_sound(&[], "558e91924520", &mut RuleState::default()),
+            ResolvedSound {
+                sound: Some(PathBuf::from("/tmp/global.wav")),
+                command: None,
+                volume: 0.5,
+                volume_floor: None,
+                rule: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_resolve_sound_silence() {
+        let config = Config::from_source(
+            r#"
+            sound = "/tmp/global.wav"
+
+            [[rule]]
+            class_regex = "^kitty$"
+            inherit_sound = false
+
+            [[rule]]
+            sound = "/tmp/other.wav"
+            "#,
+        )
+        .unwrap();
+        let clients = kitty_clients();
+        assert_eq!(
+            config.resolve_sound(&clients, "558e91924520", &mut RuleState::default()),
+            ResolvedSound {
+                sound: None,
+                command: None,
+                volume: 1.0,
+                volume_floor: None,
+                rule: Some(0),
+            }
+        );
+
+        // Absent `inherit_sound` keeps the silence semantics as well.
+        let config = Config::from_source(
+            r#"
+            sound = "/tmp/global.wav"
+
+            [[rule]]
+            class_regex = "^kitty$"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config
+                .resolve_sound(&clients, "558e91924520", &mut RuleState::default())
+                .sound,
+            None
+        );
+    }
+
+    #[test]
+    fn test_hash_sound() {
+        let config = Config::from_source(
+            r#"
+            [[rule]]
+            sounds = ["/tmp/a.wav", "/tmp/b.wav", "/tmp/c.wav"]
+            select = "hash"
+
+            [[rule]]
+            sound = "/tmp/single.wav"
+            select = "hash"
+            "#,
+        )
+        .unwrap();
+        let rule = &config.rules[0];
+        assert_eq!(rule.select, Some(Select::Hash));
+
+        // The same window gets the same sound every time, whatever the count.
+        let first = rule.pick_sound(0, "558e91924520");
+        for count in 1..10 {
+            assert_eq!(rule.pick_sound(count, "558e91924520"), first);
+        }
+
+        // Different windows are spread over the list.
+        let picked = (0..100)
+            .map(|x| rule.pick_sound(0, &format!("558e9{x:07x}")).unwrap())
+            .collect::<HashSet<_>>();
+        assert_eq!(picked.len(), 3);
+
+        assert_eq!(
+            config.rules[1].pick_sound(0, "558e91924520"),
+            Some(&PathBuf::from("/tmp/single.wav"))
+        );
+        assert!(Config::from_source("[[rule]]\nselect = \"random\"").is_err());
+    }
+
+    #[test]
+    fn test_select_sound() {
+        let config = Config::from_source(
+            r#"
+            [[rule]]
+            sounds = ["/tmp/a.wav", "/tmp/b.wav", "/tmp/c.wav"]
+
+            [[rule]]
+            sounds = ["/tmp/a.wav", "/tmp/b.wav", "/tmp/special.wav"]
+            nth = 3
+
+            [[rule]]
+            sounds = ["/tmp/a.wav"]
+            nth = 2
+
+            [[rule]]
+            sound = "/tmp/single.wav"
+            nth = 2
+            "#,
+        )
+        .unwrap();
+        let picks = |rule: &Rule| {
+            (0..7)
+                .map(|count| {
+                    rule.select_sound(count)
+                        .unwrap()
+                        .to_string_lossy()
+                        .to_string()
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            picks(&config.rules[0]),
+            [
+                "/tmp/a.wav",
+                "/tmp/b.wav",
+                "/tmp/c.wav",
+                "/tmp/a.wav",
+                "/tmp/b.wav",
+                "/tmp/c.wav",
+                "/tmp/a.wav"
+            ]
+        );
+        assert_eq!(
+            picks(&config.rules[1]),
+            [
+                "/tmp/a.wav",
+                "/tmp/b.wav",
+                "/tmp/special.wav",
+                "/tmp/a.wav",
+                "/tmp/b.wav",
+                "/tmp/special.wav",
+                "/tmp/a.wav"
+            ]
+        );
+        assert_eq!(picks(&config.rules[2]), ["/tmp/a.wav"; 7]);
+        assert_eq!(picks(&config.rules[3]), ["/tmp/single.wav"; 7]);
+
+        assert!(Config::from_source("[[rule]]\nnth = 0").is_err());
+
+        let config = Config::from_source(
+            r#"
+            [[rule]]
+            sound = "/tmp/a.wav"
+            sounds = ["/tmp/b.wav"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.validate(),
+            vec!["Rule #0 has both `sound` and `sounds`, so `sound` will never be played"]
+        );
+    }
+
+    #[test]
+    fn test_jack_port() {
+        assert_eq!(
+            split_jack_port("system:playback_1"),
+            Some(("system", "playback_1"))
+        );
+        assert_eq!(
+            split_jack_port("ardour:Audio 1/audio_in 1:x"),
+            Some(("ardour", "Audio 1/audio_in 1:x"))
+        );
+        assert_eq!(split_jack_port("system"), None);
+        assert_eq!(split_jack_port(":playback_1"), None);
+        assert_eq!(split_jack_port("system:"), None);
+
+        let config = Config::from_source(
+            r#"
+            [[rule]]
+            jack_port = "system:playback_1"
+
+            [[rule]]
+            class_regex = "^kitty$"
+            jack_port = "system"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.validate(),
+            vec![
+                "Rule #0 has `jack_port`, but playing through JACK is not supported, so it plays on the default output",
+                "Rule #1 has `jack_port = \"system\"`, which is not in the form `client:port`",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_volume_floor() {
+        let clients = kitty_clients();
+        let config = Config::from_source(
+            r#"
+            [ext_volume]
+            wav = 0.5
+
+            [[rule]]
+            class_regex = "^kitty$"
+            sound = "/tmp/alert.wav"
+            volume = 0.8
+            volume_floor = 0.3
+
+            [[workspace_override]]
+            workspace = 1
+            volume = 0.1
+            "#,
+        )
+        .unwrap();
+        let resolved = config.resolve_sound(&clients, "558e91924520", &mut RuleState::default());
+        assert_eq!(resolved.volume, 0.1);
+        assert_eq!(resolved.volume_floor, Some(0.3));
+        let volume = resolved.volume * config.ext_volume(Path::new("/tmp/alert.wav"));
+        assert_eq!(floor_volume(volume, resolved.volume_floor), 0.3);
+        assert_eq!(floor_volume(0.9, resolved.volume_floor), 0.9);
+        assert_eq!(floor_volume(0.1, None), 0.1);
+
+        assert!(Config::from_source("[[rule]]\nvolume_floor = 1.5").is_err());
+    }
+
+    #[test]
+    fn test_ext_volume() {
+        let config = Config::from_source(
+            r#"
+            [ext_volume]
+            wav = 0.7
+            MP3 = 1.0
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.ext_volume(Path::new("/tmp/a.wav")), 0.7);
+        assert_eq!(config.ext_volume(Path::new("/tmp/a.WAV")), 0.7);
+        assert_eq!(config.ext_volume(Path::new("/tmp/a.mp3")), 1.0);
+        assert_eq!(config.ext_volume(Path::new("/tmp/a.ogg")), 1.0);
+        assert_eq!(config.ext_volume(Path::new("/tmp/wav")), 1.0);
+        assert_eq!(config.ext_volume(Path::new("builtin:ding")), 1.0);
+
+        assert!(Config::from_source("[ext_volume]\nwav = 1.5").is_err());
+        assert!(Config::from_source("[ext_volume]\nwav = -0.5").is_err());
+    }
+
+    #[test]
+    fn test_resolve_sound_counters() {
+        let clients = kitty_clients();
+        let config = Config::from_source(
+            r#"
+            [[rule]]
+            class_regex = "^kitty$"
+            sounds = ["/tmp/a.wav", "/tmp/every-5th.wav"]
+            nth = 5
+            "#,
+        )
+        .unwrap();
+        let mut state = RuleState::default();
+        let sounds = (0..10)
+            .map(|_| {
+                config
+                    .resolve_sound(&clients, "558e91924520", &mut state)
+                    .sound
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        let special = PathBuf::from("/tmp/every-5th.wav");
+        assert_eq!(
+            sounds
+                .iter()
+                .enumerate()
+                .filter(|(_, x)| **x == special)
+                .map(|(i, _)| i)
+                .collect::<Vec<_>>(),
+            [4, 9]
+        );
+        assert_eq!(state.counters, HashMap::from([(0, 10)]));
+
+        // Bells that no rule matches don't touch the counters.
+        config.resolve_sound(&clients, "deadbeef", &mut state);
+        assert_eq!(state.counters, HashMap::from([(0, 10)]));
+    }
+
+    #[test]
+    fn test_resolve_sound_orphan() {
+        let clients = kitty_clients();
+        let config = Config::from_source(
+            r#"
+            sound = "/tmp/global.wav"
+
+            [[rule]]
+            orphan = true
+            sound = "/tmp/orphan.wav"
+            "#,
+        )
+        .unwrap();
+        let resolve = |address| {
+            config
+                .resolve_sound(&clients, address, &mut RuleState::default())
+                .sound
+        };
+        assert_eq!(resolve("deadbeef"), Some(PathBuf::from("/tmp/orphan.wav")));
+        assert_eq!(
+            resolve("558e91924520"),
+            Some(PathBuf::from("/tmp/global.wav"))
+        );
+    }
+
+    #[test]
+    fn test_event_sounds() {
+        let config = Config::from_source(
+            r#"
+            [[event_sound]]
+            event = "submap"
+            sound = "builtin:knock"
+            volume = 0.5
+
+            [[event_sound]]
+            event = "screencast"
+            sound = "/tmp/screencast.wav"
+
+            [[event_sound]]
+            event = "submap"
+            sound = "/tmp/never.wav"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.event_sounds.len(), 3);
+        assert_eq!(
+            config.event_sound("submap", "resize"),
+            Some(&EventSound {
+                event: "submap".into(),
+                data: None,
+                sound: PathBuf::from("builtin:knock"),
+                volume: 0.5,
+            })
+        );
+        assert_eq!(
+            config.event_sound("screencast", "1,0").unwrap().sound,
+            PathBuf::from("/tmp/screencast.wav")
+        );
+        assert_eq!(config.event_sound("screencast", "1,0").unwrap().volume, 1.0);
+        assert_eq!(config.event_sound("openwindow", "abc"), None);
+        assert!(config.validate().is_empty());
+
+        assert!(Config::from_source("[[event_sound]]\nevent = \"submap\"").is_err());
+        let config =
+            Config::from_source("[[event_sound]]\nevent = \"bell\"\nsound = \"builtin:ding\"")
+                .unwrap();
+        assert_eq!(config.validate().len(), 1);
+    }
+
+    #[test]
+    fn test_submap_sounds() {
+        let config = Config::from_source(
+            r#"
+            [[event_sound]]
+            event = "submap"
+            data = "resize"
+            sound = "/tmp/resize.wav"
+
+            [[event_sound]]
+            event = "submap"
+            data = ""
+            sound = "/tmp/leave.wav"
+
+            [[event_sound]]
+            event = "submap"
+            sound = "/tmp/other.wav"
+            "#,
+        )
+        .unwrap();
+        let sound = |data| config.event_sound("submap", data).unwrap().sound.clone();
+        assert_eq!(sound("resize"), PathBuf::from("/tmp/resize.wav"));
+        assert_eq!(sound(""), PathBuf::from("/tmp/leave.wav"));
+        assert_eq!(sound("move"), PathBuf::from("/tmp/other.wav"));
+        assert_eq!(config.event_sound("bell", "resize"), None);
+    }
+
+    #[test]
+    fn test_json() {
+        let toml = Config::from_source(
+            r#"
+            sound = "/tmp/global.wav"
+            volume = 0.5
+
+            [[rule]]
+            name = "kitty"
+            class_regex = "^kitty$"
+            title_regex_all = ["tmux", "vim"]
+            workspace = 3
+            sounds = ["/tmp/a.wav", "/tmp/b.wav"]
+            nth = 2
+
+            [[rule]]
+            workspace = "special:magic"
+            floating = true
+
+            [[event_sound]]
+            event = "submap"
+            sound = "builtin:knock"
+            "#,
+        )
+        .unwrap();
+        let json = Config::from_json(
+            r#"{
+                "sound": "/tmp/global.wav",
+                "volume": 0.5,
+                "rules": [
+                    {
+                        "name": "kitty",
+                        "class_regex": "^kitty$",
+                        "title_regex_all": ["tmux", "vim"],
+                        "workspace": 3,
+                        "sounds": ["/tmp/a.wav", "/tmp/b.wav"],
+                        "nth": 2
+                    },
+                    { "workspace": "special:magic", "floating": true }
+                ],
+                "event_sound": [{ "event": "submap", "sound": "builtin:knock" }]
+            }"#,
+        )
+        .unwrap();
+        // Regex doesn't implement PartialEq, but its Debug output is the pattern.
+        assert_eq!(format!("{toml:?}"), format!("{json:?}"));
+        assert_eq!(
+            json.rules[1].workspace,
+            Some(WorkspaceRule::Name("special:magic".into()))
+        );
+
+        assert!(matches!(
+            Config::from_json(r#"{ "rules": [{ "class_regex": "(" }] }"#),
+            Err(AppError::JsonError(_))
+        ));
+    }
+
+    #[test]
+    fn test_from_path() {
+        let dir =
+            std::env::temp_dir().join(format!("onionbell-config-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("config.json"), r#"{ "volume": 0.5 }"#).unwrap();
+        std::fs::write(dir.join("config.toml"), "volume = 0.25").unwrap();
+        assert_eq!(
+            Config::from_path(&dir.join("config.json")).unwrap().volume,
+            0.5
+        );
+        assert_eq!(
+            Config::from_path(&dir.join("config.toml")).unwrap().volume,
+            0.25
+        );
+        assert!(Config::from_path(&dir.join("missing.toml")).is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_merge_file() {
+        let mut config = Config::from_source(
+            r#"
+            [groups]
+            chat = ["discord"]
+
+            [[rule]]
+            name = "main"
+            "#,
+        )
+        .unwrap();
+        let names = |config: &Config| {
+            config
+                .rules
+                .iter()
+                .map(|x| (x.name.clone().unwrap(), x.file.clone()))
+                .collect::<Vec<_>>()
+        };
+        let file = |x: &str| Some(x.to_string());
+
+        let a = "[[rule]]\nname = \"a1\"\n[[rule]]\nname = \"a2\"\n";
+        assert_eq!(config.merge_file("a.toml", a).unwrap(), 2);
+        assert_eq!(
+            config
+                .merge_file("b.toml", "[[rule]]\nname = \"b1\"\ngroup = \"chat\"")
+                .unwrap(),
+            1
+        );
+        assert!(config.rules[3].group_classes.is_some());
+
+        // Merging again replaces the rules in place.
+        assert_eq!(
+            config
+                .merge_file("a.toml", "[[rule]]\nname = \"a3\"")
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            names(&config),
+            [
+                ("main".to_string(), None),
+                ("a3".to_string(), file("a.toml")),
+                ("b1".to_string(), file("b.toml")),
+            ]
+        );
+
+        // Invalid files leave the rules alone.
+        assert!(
+            config
+                .merge_file("a.toml", "[[rule]]\nvolume = 2.0")
+                .is_err()
+        );
+        assert!(
+            config
+                .merge_file("b.toml", "[[rule]]\nname = \"b2\"\ngroup = \"mail\"")
+                .is_err()
+        );
+        assert_eq!(names(&config).len(), 3);
+
+        // An empty file removes its rules.
+        assert_eq!(config.merge_file("a.toml", "").unwrap(), 0);
+        assert_eq!(
+            names(&config),
+            [
+                ("main".to_string(), None),
+                ("b1".to_string(), file("b.toml")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_load_conf_d() {
+        let dir =
+            std::env::temp_dir().join(format!("onionbell-conf-d-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("20-b.toml"), "[[rule]]\nname = \"b\"").unwrap();
+        std::fs::write(dir.join("10-a.toml"), "[[rule]]\nname = \"a\"").unwrap();
+        std::fs::write(dir.join("notes.txt"), "not a rule file").unwrap();
+
+        let mut config = Config::default();
+        assert!(matches!(
+            config.reload_file("10-a.toml"),
+            Err(AppError::NoConfDir)
+        ));
+        config.load_conf_d(&dir).unwrap();
+        let names = |config: &Config| {
+            config
+                .rules
+                .iter()
+                .map(|x| x.name.clone().unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&config), ["a", "b"]);
+
+        std::fs::write(dir.join("10-a.toml"), "[[rule]]\nname = \"c\"").unwrap();
+        assert_eq!(config.reload_file("10-a.toml").unwrap(), 1);
+        assert_eq!(names(&config), ["c", "b"]);
+        assert!(config.reload_file("../10-a.toml").is_err());
+        assert_eq!(config.reload_file("missing.toml").unwrap(), 0);
+
+        // New files go in the order of their names, and deleted files take their rules along.
+        std::fs::write(dir.join("15-n.toml"), "[[rule]]\nname = \"n\"").unwrap();
+        assert_eq!(config.reload_file("15-n.toml").unwrap(), 1);
+        assert_eq!(names(&config), ["c", "n", "b"]);
+        std::fs::remove_file(dir.join("10-a.toml")).unwrap();
+        assert_eq!(config.reload_file("10-a.toml").unwrap(), 0);
+        assert_eq!(names(&config), ["n", "b"]);
+
+        // A missing directory has no rules.
+        let mut config = Config::default();
+        config.load_conf_d(&dir.join("missing")).unwrap();
+        assert!(config.rules.is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_once_per_window() {
+        let clients = kitty_clients();
+        let config = Config::from_source(
+            r#"
+            sound = "/tmp/global.wav"
+
+            [[rule]]
+            class_regex = "^kitty$"
+            once_per_window = true
+            sound = "/tmp/welcome.wav"
+            "#,
+        )
+        .unwrap();
+        let mut state = RuleState::default();
+        let mut resolve = |state: &mut RuleState| {
+            config
+                .resolve_sound(&clients, "558e91924520", state)
+                .sound
+                .unwrap()
+        };
+        assert_eq!(resolve(&mut state), PathBuf::from("/tmp/welcome.wav"));
+        assert_eq!(resolve(&mut state), PathBuf::from("/tmp/global.wav"));
+        assert_eq!(resolve(&mut state), PathBuf::from("/tmp/global.wav"));
+        assert_eq!(state.counters, HashMap::from([(0, 1)]));
+
+        // Closing another window changes nothing.
+        state.forget_window("deadbeef");
+        assert_eq!(resolve(&mut state), PathBuf::from("/tmp/global.wav"));
+
+        // A new window reusing the address of a closed one is welcomed again.
+        state.forget_window("558e91924520");
+        assert!(state.fired.is_empty());
+        assert_eq!(resolve(&mut state), PathBuf::from("/tmp/welcome.wav"));
+        assert_eq!(resolve(&mut state), PathBuf::from("/tmp/global.wav"));
+    }
+
+    #[test]
+    fn test_fire() {
+        let mut state = RuleState::default();
+        for i in 0..MAX_FIRED_WINDOWS {
+            state.fire(0, i.to_string());
+        }
+        assert_eq!(state.fired.len(), MAX_FIRED_WINDOWS);
+        state.fire(1, "new".into());
+        assert_eq!(state.fired.len(), MAX_FIRED_WINDOWS);
+        assert!(!state.fired.contains_key(&(0, "0".to_string())));
+        assert!(state.fired.contains_key(&(0, "1".to_string())));
+        assert!(state.fired.contains_key(&(1, "new".to_string())));
+    }
+
+    #[test]
+    fn test_window_key() {
+        let config = Config::from_source(
+            r#"
+            sound = "/tmp/global.wav"
+
+            [[rule]]
+            class_regex = "^kitty$"
+            once_per_window = true
+            window_key = "stable"
+            sound = "/tmp/welcome.wav"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.rules[0].window_key, Some(WindowKey::Stable));
+        assert!(config.key_warnings().is_empty());
+        let mut clients = kitty_clients();
+        let mut state = RuleState::default();
+        let mut resolve = |clients: &[HyprClient], address: &str, state: &mut RuleState| {
+            config.resolve_sound(clients, address, state).sound.unwrap()
+        };
+        assert_eq!(
+            resolve(&clients, "558e91924520", &mut state),
+            PathBuf::from("/tmp/welcome.wav")
+        );
+
+        // The window is closed and opened again at another address, which is still the same
+        // window.
+        state.forget_window("558e91924520");
+        clients[0].address = "0x558e91924999".into();
+        assert_eq!(
+            resolve(&clients, "558e91924999", &mut state),
+            PathBuf::from("/tmp/global.wav")
+        );
+
+        // Another title makes it another window.
+        clients[0].title = "htop".into();
+        assert_eq!(
+            resolve(&clients, "558e91924999", &mut state),
+            PathBuf::from("/tmp/welcome.wav")
+        );
+        assert_eq!(
+            resolve(&clients, "558e91924999", &mut state),
+            PathBuf::from("/tmp/global.wav")
+        );
+
+        let rule = Rule {
+            window_key: Some(WindowKey::Stable),
+            ..Default::default()
+        };
+        assert_eq!(rule.once_key(None, "deadbeef"), "deadbeef");
+        assert_eq!(
+            rule.once_key(Some(&clients[0]), "558e91924999"),
+            clients[0].window_key()
+        );
+        assert!(
+            Config::from_source("[[rule]]\nwindow_key = \"stable\"")
+                .unwrap()
+                .key_warnings()
+                .iter()
+                .any(|x| x.contains("window_key"))
+        );
+        assert!(Config::from_source("[[rule]]\nwindow_key = \"title\"").is_err());
+    }
+
+    #[test]
+    fn test_default() {
+        // The default config is the same as an empty config file.
+        assert_eq!(
+            Config::default().to_toml().unwrap(),
+            Config::from_source("").unwrap().to_toml().unwrap()
+        );
+        assert_eq!(Config::default().max_event_bytes, DEFAULT_MAX_EVENT_BYTES);
+    }
+
+    #[test]
+    fn test_sample_rate() {
+        assert!(Config::from_source("sample_rate = 0").is_err());
+        let config = Config::from_source("sample_rate = 48000").unwrap();
+        assert_eq!(config.sample_rate.map(NonZeroU32::get), Some(48000));
+        assert!(config.key_warnings().is_empty());
+        let config = Config::from_source("sample_rate = 1000").unwrap();
+        assert!(
+            config
+                .key_warnings()
+                .iter()
+                .any(|x| x.contains("sample_rate"))
+        );
+    }
+
+    #[test]
+    fn test_ignore_classes() {
+        let clients = kitty_clients();
+        let config = Config::from_source(r#"ignore_classes = ["^slack$", "^kit"]"#).unwrap();
+        assert!(config.is_ignored(clients.first()));
+        assert!(!config.is_ignored(None));
+        let config = Config::from_source(r#"ignore_classes = ["^slack$"]"#).unwrap();
+        assert!(!config.is_ignored(clients.first()));
+        assert!(!Config::default().is_ignored(clients.first()));
+        assert!(Config::from_source(r#"ignore_classes = ["("]"#).is_err());
+    }
+
+    #[test]
+    fn test_monitor_device() {
+        let clients = kitty_clients();
+        let config = Config::from_source(
+            r#"
+            [monitor_devices]
+            DP-1 = "hdmi:CARD=NVidia,DEV=0"
+            "#,
+        )
+        .unwrap();
+        assert!(config.uses_monitors());
+        let monitor = |id, name: &str| HyprMonitor {
+            id,
+            name: name.into(),
+            ..Default::default()
+        };
+        let id = clients[0].monitor;
+        let monitors = [monitor(id, "DP-1"), monitor(id + 1, "HDMI-A-1")];
+        assert_eq!(
+            config.monitor_device(clients.first(), Some(&monitors)),
+            Some("hdmi:CARD=NVidia,DEV=0")
+        );
+
+        // Unmapped or unknown monitors play on the default output.
+        let monitors = [monitor(id, "HDMI-A-1")];
+        assert_eq!(
+            config.monitor_device(clients.first(), Some(&monitors)),
+            None
+        );
+        assert_eq!(config.monitor_device(clients.first(), None), None);
+        assert_eq!(config.monitor_device(None, Some(&monitors)), None);
+        assert_eq!(
+            Config::default().monitor_device(clients.first(), Some(&monitors)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_devices() {
+        let clients = kitty_clients();
+        let config = Config::from_source(
+            r#"
+            [monitor_devices]
+            DP-1 = "hdmi:CARD=NVidia,DEV=0"
+
+            [[rule]]
+            class_regex = "^kitty$"
+            devices = ["front", "headset"]
+
+            [[rule]]
+            class_regex = "^foot$"
+            "#,
+        )
+        .unwrap();
+        let monitors = [HyprMonitor {
+            id: clients[0].monitor,
+            name: "DP-1".into(),
+            ..Default::default()
+        }];
+        assert_eq!(
+            config.devices(Some(0), clients.first(), Some(&monitors)),
+            ["front", "headset"]
+        );
+        assert_eq!(
+            config.devices(Some(1), clients.first(), Some(&monitors)),
+            ["hdmi:CARD=NVidia,DEV=0"]
+        );
+        assert_eq!(
+            config.devices(None, clients.first(), Some(&monitors)),
+            ["hdmi:CARD=NVidia,DEV=0"]
+        );
+        assert!(config.devices(None, None, Some(&monitors)).is_empty());
+    }
+
+    #[test]
+    fn test_beeps() {
+        let clients = kitty_clients();
+        let config = Config::from_source(
+            r#"
+            sound = "/tmp/global.wav"
+
+            [[rule]]
+            class_regex = "^kitty$"
+            beeps = { count = 3, freq = 440, ms = 100, gap = "50ms" }
+            "#,
+        )
+        .unwrap();
+        let beeps = config.rules[0].beeps.clone().unwrap();
+        assert_eq!(beeps.path(), PathBuf::from("beeps:3x440hz,100ms,50ms"));
+        assert_eq!(beeps.duration(), Duration::from_millis(400));
+        assert_eq!(
+            config
+                .resolve_sound(&clients, "558e91924520", &mut RuleState::default())
+                .sound,
+            Some(beeps.path())
+        );
+        assert!(config.key_warnings().is_empty());
+
+        let beeps = Config::from_source("[[rule]]\nbeeps = {}").unwrap().rules[0]
+            .beeps
+            .clone()
+            .unwrap();
+        assert_eq!(beeps.path(), PathBuf::from("beeps:1x880hz,120ms,80ms"));
+        assert_eq!(beeps.duration(), Duration::from_millis(120));
+
+        for source in [
+            "[[rule]]\nbeeps = {}\nsound = \"/tmp/a.wav\"",
+            "[[rule]]\nbeeps = {}\nsound_cmd = \"true\"",
+            "[[rule]]\nbeeps = { count = 0 }",
+        ] {
+            let config = Config::from_source(source).unwrap();
+            assert!(
+                config.key_warnings().iter().any(|x| x.contains("beeps")),
+                "{source}"
+            );
+        }
+
+        assert!(Config::from_source("[[rule]]\nbeeps = { count = 100 }").is_ok());
+        assert!(Config::from_source("[[rule]]\nbeeps = { count = 101 }").is_err());
+        let long = Beeps {
+            count: MAX_BEEPS,
+            freq: 880.0,
+            ms: u64::MAX,
+            gap: 0,
+        };
+        assert_eq!(long.duration(), Duration::MAX);
+    }
+
+    #[test]
+    fn test_monitor() {
+        let clients = kitty_clients();
+        let config = Config::from_source(
+            r#"
+            sound = "/tmp/global.wav"
+
+            [[rule]]
+            monitor_width = 3840
+            monitor_scale = 1.5
+            sound = "/tmp/4k.wav"
+
+            [[rule]]
+            monitor_height = 1080
+            sound = "/tmp/1080p.wav"
+            "#,
+        )
+        .unwrap();
+        assert!(config.uses_monitors());
+        assert!(!config.uses_active_window());
+        let resolve = |state: &mut RuleState| {
+            config
+                .resolve_sound(&clients, "558e91924520", state)
+                .sound
+                .unwrap()
+        };
+        let monitor = |width, height, scale| HyprMonitor {
+            id: clients[0].monitor,
+            width,
+            height,
+            scale,
+            ..Default::default()
+        };
+
+        // Without monitors, no monitor predicate matches.
+        let mut state = RuleState::default();
+        assert_eq!(resolve(&mut state), PathBuf::from("/tmp/global.wav"));
+
+        state.monitors = Some(vec![monitor(3840, 2160, 1.5)]);
+        assert_eq!(resolve(&mut state), PathBuf::from("/tmp/4k.wav"));
+        state.monitors = Some(vec![monitor(3840, 2160, 2.0)]);
+        assert_eq!(resolve(&mut state), PathBuf::from("/tmp/global.wav"));
+        state.monitors = Some(vec![monitor(1920, 1080, 1.0)]);
+        assert_eq!(resolve(&mut state), PathBuf::from("/tmp/1080p.wav"));
+
+        // The window is on another monitor than the one known.
+        state.monitors = Some(vec![HyprMonitor {
+            id: clients[0].monitor + 1,
+            ..monitor(1920, 1080, 1.0)
+        }]);
+        assert_eq!(resolve(&mut state), PathBuf::from("/tmp/global.wav"));
+    }
+
+    #[test]
+    fn test_active_class_regex() {
+        let clients = kitty_clients();
+        let config = Config::from_source(
+            r#"
+            sound = "/tmp/global.wav"
+
+            [[rule]]
+            class_regex = "^kitty$"
+            active_class_regex = "^(kitty|foot)$"
+            sound = "/tmp/terminal.wav"
+            "#,
+        )
+        .unwrap();
+        assert!(config.uses_active_window());
+        let resolve = |state: &mut RuleState| {
+            config
+                .resolve_sound(&clients, "558e91924520", state)
+                .sound
+                .unwrap()
+        };
+        let mut state = RuleState::default();
+        assert_eq!(resolve(&mut state), PathBuf::from("/tmp/global.wav"));
+
+        let active = |class: &str| HyprActiveWindow {
+            class: class.into(),
+            ..Default::default()
+        };
+        state.active_window = Some(active("foot"));
+        assert_eq!(resolve(&mut state), PathBuf::from("/tmp/terminal.wav"));
+        state.active_window = Some(active("firefox"));
+        assert_eq!(resolve(&mut state), PathBuf::from("/tmp/global.wav"));
+
+        // The source window has to match as well.
+        state.active_window = Some(active("kitty"));
+        assert_eq!(
+            config
+                .resolve_sound(&clients, "deadbeef", &mut state)
+                .sound
+                .unwrap(),
+            PathBuf::from("/tmp/global.wav")
+        );
+    }
+
+    #[test]
+    fn test_moved_resized() {
+        let mut clients = kitty_clients();
+        let config = Config::from_source(
+            r#"
+            sound = "/tmp/global.wav"
+
+            [[rule]]
+            moved = true
+            sound = "/tmp/moved.wav"
+
+            [[rule]]
+            resized = false
+            sound = "/tmp/untouched.wav"
+            "#,
+        )
+        .unwrap();
+        assert!(config.tracks_geometry());
+        let resolve = |clients: &[HyprClient], state: &mut RuleState| {
+            config
+                .resolve_sound(clients, "558e91924520", state)
+                .sound
+                .unwrap()
+        };
+
+        // Windows seen before onionbell started match neither.
+        let mut state = RuleState::default();
+        assert_eq!(
+            resolve(&clients, &mut state),
+            PathBuf::from("/tmp/global.wav")
+        );
+
+        state
+            .initial_geometry
+            .insert("558e91924520".to_string(), clients[0].geometry());
+        assert_eq!(
+            resolve(&clients, &mut state),
+            PathBuf::from("/tmp/untouched.wav")
+        );
+
+        clients[0].size = [800, 600];
+        assert_eq!(
+            resolve(&clients, &mut state),
+            PathBuf::from("/tmp/global.wav")
+        );
+
+        clients[0].at = [0, 0];
+        assert_eq!(
+            resolve(&clients, &mut state),
+            PathBuf::from("/tmp/moved.wav")
+        );
+
+        state.forget_window("558e91924520");
+        assert!(state.initial_geometry.is_empty());
+        assert_eq!(
+            resolve(&clients, &mut state),
+            PathBuf::from("/tmp/global.wav")
+        );
+    }
+
+    #[test]
+    fn test_urgent_state() {
+        let start = Instant::now();
+        let ttl = Duration::from_secs(1);
+        let ms = |ms| start + Duration::from_millis(ms);
+        let mut state = RuleState::default();
+        assert!(!state.is_urgent("a", ttl, start));
+
+        state.mark_urgent("a", ttl, start);
+        assert!(state.is_urgent("a", ttl, ms(999)));
+        assert!(!state.is_urgent("a", ttl, ms(1000)));
+        assert!(!state.is_urgent("b", ttl, ms(500)));
+
+        // Expired windows are dropped when another one requests attention.
+        state.mark_urgent("b", ttl, ms(1500));
+        assert!(!state.urgent.contains_key("a"));
+        assert!(state.is_urgent("b", ttl, ms(1500)));
+
+        state.forget_window("b");
+        assert!(state.urgent.is_empty());
+    }
+
+    #[test]
+    fn test_mqtt() {
+        let config = Config::from_source(
+            r#"
+            [mqtt]
+            host = "homeassistant.local"
+            topic = "desktop/{class}"
+            "#,
+        )
+        .unwrap();
+        let mqtt = config.mqtt.as_ref().unwrap();
+        assert_eq!(mqtt.port, 1883);
+        assert_eq!(mqtt.topic, "desktop/{class}");
+        assert_eq!(mqtt.client_id, "onionbell");
+        assert!(mqtt.username.is_none());
+        assert_eq!(config.validate().is_empty(), cfg!(feature = "mqtt"));
+
+        // The password is never printed, and not sent without a username.
+        let config = Config::from_source(
+            r#"
+            [mqtt]
+            host = "homeassistant.local"
+            password = "secret"
+            "#,
+        )
+        .unwrap();
+        assert!(!config.to_toml().unwrap().contains("secret"));
+        assert!(
+            config
+                .key_warnings()
+                .iter()
+                .any(|x| x.contains("mqtt.password"))
+        );
+    }
+
+    #[test]
+    fn test_handled_events() {
+        let sorted = |config: &Config| {
+            let mut events = config.handled_events().into_iter().collect::<Vec<_>>();
+            events.sort();
+            events
+        };
+        assert_eq!(sorted(&Config::default()), ["bell", "closewindow"]);
+
+        let config = Config::from_source(
+            r#"
+            [[rule]]
+            urgent = true
+
+            [[rule]]
+            active_class_regex = "^firefox$"
+
+            [[event_sound]]
+            event = "submap"
+            sound = "builtin:knock"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            sorted(&config),
+            [
+                "activewindow",
+                "activewindowv2",
+                "bell",
+                "closewindow",
+                "submap",
+                "urgent"
+            ]
+        );
+
+        // The list in the config replaces `bell` and the events of `event_sound`s, but not the
+        // events that state depends on.
+        let config = Config::from_source(
+            r#"
+            events = ["bell"]
+
+            [[rule]]
+            urgent = true
+
+            [[event_sound]]
+            event = "submap"
+            sound = "builtin:knock"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(sorted(&config), ["bell", "closewindow", "urgent"]);
+        assert_eq!(
+            config.key_warnings(),
+            ["`event_sound` for `submap` is never played, as `submap` is not in `events`"]
+        );
+    }
+
+    #[test]
+    fn test_max_rules_evaluated() {
+        let clients = kitty_clients();
+        let source = |max: usize| {
+            format!(
+                r#"
+                sound = "/tmp/global.wav"
+                max_rules_evaluated = {max}
+
+                [[rule]]
+                class_regex = "^foot$"
+                sound = "/tmp/foot.wav"
+
+                [[rule]]
+                class_regex = "^alacritty$"
+                sound = "/tmp/alacritty.wav"
+
+                [[rule]]
+                class_regex = "^kitty$"
+                sound = "/tmp/kitty.wav"
+                "#
+            )
+        };
+        let resolve = |max| {
+            Config::from_source(source(max)).unwrap().resolve_sound(
+                &clients,
+                "558e91924520",
+                &mut RuleState::default(),
+            )
+        };
+        let resolved = resolve(2);
+        assert_eq!(resolved.sound, Some(PathBuf::from("/tmp/global.wav")));
+        assert_eq!(resolved.rule, None);
+        assert_eq!(resolve(3).sound, Some(PathBuf::from("/tmp/kitty.wav")));
+        assert_eq!(resolve(0).sound, Some(PathBuf::from("/tmp/global.wav")));
+
+        assert_eq!(
+            Config::from_source(source(2)).unwrap().validate(),
+            vec![
+                "Only 2 of the 3 rules are checked for a bell, so bells that match none of them \
+                play the global sound. Raise `max_rules_evaluated` to check more."
+            ]
+        );
+        assert!(
+            Config::from_source(source(3))
+                .unwrap()
+                .validate()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_delay() {
+        let config = Config::from_source(
+            r#"
+            [[rule]]
+            delay_ms = 100
+
+            [[rule]]
+            delay_ms = 100
+            delay_range_ms = 50
+
+            [[rule]]
+            delay_range_ms = 0
+            "#,
+        )
+        .unwrap();
+        let mut rng = crate::util::make_rng(Some(0));
+        assert_eq!(config.rules[0].delay(&mut rng), Duration::from_millis(100));
+        for _ in 0..20 {
+            let delay = config.rules[1].delay(&mut rng);
+            assert!((Duration::from_millis(100)..=Duration::from_millis(150)).contains(&delay));
+        }
+        assert_eq!(config.rules[2].delay(&mut rng), Duration::ZERO);
+        assert_eq!(Rule::default().delay(&mut rng), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_urgent() {
+        let clients = kitty_clients();
+        let config = Config::from_source(
+            r#"
+            sound = "/tmp/global.wav"
+
+            [[rule]]
+            urgent = true
+            sound = "/tmp/urgent.wav"
+
+            [[rule]]
+            urgent = false
+            class = "kitty"
+            sound = "/tmp/calm.wav"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.urgent_ttl(), Duration::from_secs(10));
+        let mut state = RuleState::default();
+        let mut resolve =
+            |state: &mut RuleState| config.resolve_sound(&clients, "558e91924520", state).sound;
+        assert_eq!(resolve(&mut state), Some(PathBuf::from("/tmp/calm.wav")));
+
+        state.mark_urgent("558e91924520", config.urgent_ttl(), Instant::now());
+        assert_eq!(resolve(&mut state), Some(PathBuf::from("/tmp/urgent.wav")));
+    }
+
+    #[test]
+    fn test_sound_cmd() {
+        let mut clients = kitty_clients();
+        clients[0].title = "it's vim".to_string();
+        let config = Config::from_source(
+            r#"
+            [[rule]]
+            class_regex = "^kitty$"
+            sound_cmd = "say --class {class} {title}"
+            volume = 0.5
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.resolve_sound(&clients, "558e91924520", &mut RuleState::default()),
+            ResolvedSound {
+                sound: None,
+                command: Some(r#"say --class 'kitty' 'it'\''s vim'"#.to_string()),
+                volume: 0.5,
+                volume_floor: None,
+                rule: Some(0),
+            }
+        );
+        assert_eq!(
+            expand_sound_cmd("say {title}{workspace}{address}", None),
+            "say ''''''"
+        );
+    }
+
+    #[test]
+    fn test_groups() {
+        let clients = kitty_clients();
+        let config = Config::from_source(
+            r#"
+            sound = "/tmp/global.wav"
+
+            [groups]
+            chat = ["discord", "element"]
+            terminals = ["foot", "kitty"]
+
+            [[rule]]
+            group = "chat"
+            sound = "/tmp/chat.wav"
+
+            [[rule]]
+            group = "terminals"
+            sound = "/tmp/terminal.wav"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config
+                .resolve_sound(&clients, "558e91924520", &mut RuleState::default())
+                .sound,
+            Some(PathBuf::from("/tmp/terminal.wav"))
+        );
+
+        // Classes are matched exactly, not as regular expressions.
+        let rule = &config.rules[1];
+        let mut client = clients[0].clone();
+        client.class = "kitty2".to_string();
+        assert!(!client.matches(&clients, rule, &WindowState::default()));
+
+        let Err(err) = Config::from_source("[[rule]]\ngroup = \"chat\"") else {
+            unreachable!()
+        };
+        assert_eq!(
+            err.to_string(),
+            "Rule #0 refers to the undefined group `chat`"
+        );
+    }
+
+    #[test]
+    fn test_describe() {
+        let config = Config::from_source(
+            r#"
+            [[rule]]
+            name = "kitty"
+            workspace = "special:magic"
+            floating = true
+            class_regex = "^kitty$"
+            min_aspect = 0.8
+            sounds = ["/tmp/a.wav", "builtin:ding"]
+            volume = 0.5
+
+            [[rule]]
+            force = true
+            title_regex_all = ["vim", "\\.rs"]
+            inherit_sound = true
+
+            [[rule]]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.rules[0].describe(),
+            r#"kitty: workspace = "special:magic", floating = true, class_regex = "^kitty$", min_aspect = 0.8 -> [/tmp/a.wav, builtin:ding] at volume 0.5"#
+        );
+        assert_eq!(
+            config.rules[1].describe(),
+            r#"force = true, title_regex_all = ["vim", "\\.rs"] -> the global sound at volume 1"#
+        );
+        assert_eq!(config.rules[2].describe(), "any window -> silence");
+    }
+
+    #[test]
+    fn test_workspace_overrides() {
+        let mut clients = kitty_clients();
+        let config = Config::from_source(
+            r#"
+            sound = "/tmp/global.wav"
+            volume = 0.8
 
-/// A rule that matches against properties of the window who sends the `bell` event (we'll call it
-/// the *source window* afterwards).
-#[derive(Debug, Deserialize, Default)]
-pub struct Rule {
-    /// `sound` is an optional key, represents path to an audio file that will be played when the
-    /// `bell` event is triggered and the current rule matches. When this key is not present, no
-    /// sound will play at all when the rule matches the source window, even if the global `sound`
-    /// key is present.
-    pub sound: Option<PathBuf>,
+            [[rule]]
+            class_regex = "^kitty$"
+            title_regex = "tmux"
+            sound = "/tmp/tmux.wav"
 
-    /// The volume of the sound, ranges from 0.0 to 1.0.
-    /// The default value is 1.0.
-    #[serde(default = "default_volume", deserialize_with = "validate_volume")]
-    pub volume: f32,
+            [[rule]]
+            class_regex = "^kitty$"
 
-    /// The workspace that the source window lives in.
-    pub workspace: Option<WorkspaceRule>,
+            [[workspace_override]]
+            workspace = 1
+            volume = 0.2
 
-    /// Whether the source window is floating.
-    pub floating: Option<bool>,
+            [[workspace_override]]
+            workspace = "special:magic"
+            sound = "/tmp/magic.wav"
 
-    /// A regular expression to match with the `class` property of the source window.
-    #[serde(with = "serde_regex")]
-    #[serde(default)]
-    pub class_regex: Option<Regex>,
+            [[workspace_override]]
+            workspace = 1
+            volume = 1.0
+            "#,
+        )
+        .unwrap();
+        let mut resolve = |clients: &[HyprClient]| {
+            config.resolve_sound(clients, "558e91924520", &mut RuleState::default())
+        };
 
-    /// A regular expression to match with the `title` property of the source window.
-    #[serde(with = "serde_regex")]
-    #[serde(default)]
-    pub title_regex: Option<Regex>,
+        // The kitty window is on workspace 1, so the rule's sound is played quieter.
+        assert_eq!(
+            resolve(&clients),
+            ResolvedSound {
+                sound: Some(PathBuf::from("/tmp/tmux.wav")),
+                command: None,
+                volume: 0.2,
+                volume_floor: None,
+                rule: Some(0),
+            }
+        );
 
-    /// Whether the source window is an XWayland window.
-    pub xwayland: Option<bool>,
-}
+        clients[0].workspace = HyprWorkspace {
+            id: -98,
+            name: "special:magic".into(),
+        };
+        assert_eq!(
+            resolve(&clients),
+            ResolvedSound {
+                sound: Some(PathBuf::from("/tmp/magic.wav")),
+                command: None,
+                volume: 1.0,
+                volume_floor: None,
+                rule: Some(0),
+            }
+        );
 
-/// The type of `workspace` key in the rule.
-/// This key is an untagged enum. When `workspace` is a number, it will be matched against the
-/// `workspace.id` property of the source window. When it is a string, `workspace.name` will be
-/// checked instead.
-#[derive(Debug, Deserialize, PartialEq, Eq)]
-#[serde(untagged)]
-pub enum WorkspaceRule {
-    /// `id` of the workspace.
-    Id(i32),
+        // Silence from a rule isn't overridden.
+        clients[0].title = "zsh".into();
+        assert_eq!(resolve(&clients).sound, None);
 
-    /// `name` of the workspace.
-    Name(String),
-}
+        // Other workspaces keep what rules and globals chose.
+        clients[0].workspace = HyprWorkspace {
+            id: 2,
+            name: "2".into(),
+        };
+        clients[0].class = "foot".into();
+        assert_eq!(
+            resolve(&clients),
+            ResolvedSound {
+                sound: Some(PathBuf::from("/tmp/global.wav")),
+                command: None,
+                volume: 0.8,
+                volume_floor: None,
+                rule: None,
+            }
+        );
 
-impl Config {
-    pub fn from_source<S: AsRef<str>>(source: S) -> Result<Config, AppError> {
-        toml::from_str(source.as_ref()).map_err(AppError::from)
+        assert!(
+            Config::from_source("[[workspace_override]]\nworkspace = 1\nvolume = 2.0").is_err()
+        );
     }
-}
 
-#[allow(unused)]
-mod test {
-    use super::*;
+    #[test]
+    fn test_to_toml() {
+        let config = Config::from_source(
+            r#"
+            sound = "builtin:ding"
+            volume = 0.3
+            seed = 42
+
+            [escalate]
+            step = 0.2
+
+            [[rule]]
+            name = "kitty"
+            class_regex = "^kitty$"
+            title_regex_all = ["tmux", "v\\.im"]
+            workspace = "special:magic"
+            sounds = ["/tmp/a.wav", "/tmp/b.wav"]
+            nth = 2
+            volume = 0.7
+
+            [[rule]]
+            workspace = 3
+            min_aspect = 0.8
+
+            [[workspace_override]]
+            workspace = 1
+            volume = 0.2
+
+            [[event_sound]]
+            event = "submap"
+            sound = "builtin:knock"
+            "#,
+        )
+        .unwrap();
+        let dumped = config.to_toml().unwrap();
+        let reparsed = Config::from_source(&dumped).unwrap();
+        assert_eq!(format!("{config:?}"), format!("{reparsed:?}"));
+
+        let config = Config::default();
+        let reparsed = Config::from_source(config.to_toml().unwrap()).unwrap();
+        assert_eq!(format!("{config:?}"), format!("{reparsed:?}"));
+    }
 
     #[test]
-    fn invalid_volume() {
-        let error = Config::from_source("volume = 100.0");
-        assert!(error.is_err());
+    fn test_force() {
+        let mut clients = kitty_clients();
+        let config = Config::from_source(
+            r#"
+            sound = "/tmp/global.wav"
 
-        let AppError::TomlDeserializationError(error) = error.unwrap_err() else {
-            unreachable!()
+            [[rule]]
+            name = "silence everything"
+
+            [[rule]]
+            title_regex = "tmux"
+            sound = "/tmp/tmux.wav"
+            force = true
+
+            [[rule]]
+            class_regex = "^kitty$"
+            sound = "/tmp/kitty.wav"
+            force = true
+
+            [[rule]]
+            class_regex = "^kitty$"
+            sound = "/tmp/unforced.wav"
+            "#,
+        )
+        .unwrap();
+        let mut resolve = |clients: &[HyprClient]| {
+            let resolved = config.resolve_sound(clients, "558e91924520", &mut RuleState::default());
+            (resolved.sound, resolved.rule)
         };
+
+        // The first forced rule that matches wins over the earlier silence rule.
         assert_eq!(
-            error.message(),
-            "invalid value: floating point `100.0`, expected volume must be between 0.0 and 1.0"
+            resolve(&clients),
+            (Some(PathBuf::from("/tmp/tmux.wav")), Some(1))
+        );
+        clients[0].title = "zsh".into();
+        assert_eq!(
+            resolve(&clients),
+            (Some(PathBuf::from("/tmp/kitty.wav")), Some(2))
         );
-        assert_eq!(error.span(), Some(9..14));
 
-        let error = Config::from_source(
+        // Without a matching forced rule, rules are checked in order as usual.
+        clients[0].class = "foot".into();
+        assert_eq!(resolve(&clients), (None, Some(0)));
+
+        let config = Config::from_source(
             r#"
             [[rule]]
-            volume = 100.0
+            class_regex = "^kitty$"
+
+            [[rule]]
+            class_regex = "^kitty$"
+            force = true
             "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.validate(),
+            vec!["Rule #0 has the same conditions as forced rule #1, so it will never match"]
         );
-        assert!(error.is_err());
+    }
 
-        let AppError::TomlDeserializationError(error) = error.unwrap_err() else {
-            unreachable!()
-        };
+    #[test]
+    fn test_field() {
+        let config = Config::from_source(
+            r#"
+            [[rule]]
+            field = "/tags"
+            field_value = ["urgent"]
+
+            [[rule]]
+            field = "tags"
+
+            [[rule]]
+            field_regex = "urgent"
+            "#,
+        )
+        .unwrap();
         assert_eq!(
-            error.message(),
-            "invalid value: floating point `100.0`, expected volume must be between 0.0 and 1.0"
+            config.rules[0].field_value,
+            Some(serde_json::json!(["urgent"]))
+        );
+        assert!(config.rules[0].matches_field(Some(&serde_json::json!(["urgent"]))));
+        assert!(!config.rules[0].matches_field(Some(&serde_json::json!([]))));
+        assert!(!config.rules[0].matches_field(None));
+        assert_eq!(
+            config.validate(),
+            vec![
+                r#"Rule #1 has `field = "tags"`, which is not a JSON pointer starting with `/`, so it never matches"#,
+                "Rule #2 has `field_value` or `field_regex` without `field`, so they are ignored",
+            ]
         );
-        assert_eq!(error.span(), Some(43..48));
     }
 
     #[test]
-    fn test_empty_source() {
-        let config = Config::from_source("").unwrap();
-        assert!(config.sound.is_none());
-        assert!(config.rules.is_empty());
+    fn test_volume_db() {
+        let config = Config::from_source(
+            r#"
+            volume_offset_db = -6.0
+
+            [[rule]]
+            volume_db = 0.0
+
+            [[rule]]
+            volume_db = 3.0
+
+            [[rule]]
+            volume = 0.3
+            "#,
+        )
+        .unwrap();
+        assert!((config.rules[0].volume - 0.5012).abs() < 1e-4);
+        assert!((config.rules[1].volume - 0.7079).abs() < 1e-4);
+        assert_eq!(config.rules[2].volume, 0.3);
+
+        // Resolving again gives the same volumes.
+        let config = Config::from_source(config.to_toml().unwrap()).unwrap();
+        assert!((config.rules[0].volume - 0.5012).abs() < 1e-4);
+        assert!(config.key_warnings().is_empty());
+
+        let config = Config::from_source("[[rule]]\nvolume = 0.3\nvolume_db = -6.0").unwrap();
+        assert!((config.rules[0].volume - 0.5012).abs() < 1e-4);
+        assert!(
+            config
+                .key_warnings()
+                .iter()
+                .any(|x| x.contains("`volume` and `volume_db`"))
+        );
+
+        for source in [
+            "[[rule]]\nvolume_db = nan",
+            "[[rule]]\nvolume_db = -inf",
+            "volume_offset_db = inf",
+        ] {
+            assert!(Config::from_source(source).is_err(), "{source}");
+        }
     }
+
     #[test]
-    fn test_toplevel_fields() {
+    fn test_auditions() {
         let config = Config::from_source(
-            "sound = \"/home/onion27/Music/2-14. 渦状銀河のシンフォニエッタ.mp3\"",
+            r#"
+            sound = "/tmp/global.wav"
+
+            [[rule]]
+            name = "first"
+            sound = "/tmp/first.wav"
+
+            [[rule]]
+            floating = true
+
+            [[rule]]
+            sound = "/tmp/third.wav"
+
+            [[rule]]
+            sounds = ["/tmp/fourth.wav"]
+            "#,
         )
         .unwrap();
+        let auditions = config
+            .auditions()
+            .map(|(i, rule)| {
+                (
+                    i,
+                    rule.name.as_deref(),
+                    rule.select_sound(0).unwrap().clone(),
+                )
+            })
+            .collect::<Vec<_>>();
         assert_eq!(
-            config.sound,
-            Some(PathBuf::from(
-                "/home/onion27/Music/2-14. 渦状銀河のシンフォニエッタ.mp3"
-            ))
+            auditions,
+            vec![
+                (0, Some("first"), PathBuf::from("/tmp/first.wav")),
+                (2, None, PathBuf::from("/tmp/third.wav")),
+                (3, None, PathBuf::from("/tmp/fourth.wav")),
+            ]
         );
-        assert_eq!(config.volume, 1.0);
-        assert!(config.rules.is_empty());
     }
+
+    #[test]
+    fn test_special_workspace() {
+        let config = Config::from_source(
+            r#"
+            [[rule]]
+            workspace = -98
+
+            [[rule]]
+            workspace = "special:magic"
+
+            [[rule]]
+            workspace = 1.5
+            "#,
+        );
+        let AppError::TomlDeserializationError(error) = config.unwrap_err() else {
+            unreachable!()
+        };
+        assert!(error.message().contains("did not match any variant"));
+
+        let config = Config::from_source(
+            r#"
+            [[rule]]
+            workspace = -98
+
+            [[rule]]
+            workspace = "special:magic"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.rules[0].workspace, Some(WorkspaceRule::Id(-98)));
+        assert_eq!(
+            config.rules[1].workspace,
+            Some(WorkspaceRule::Name("special:magic".into()))
+        );
+    }
+
+    #[test]
+    fn test_duplicate_rules() {
+        let config = Config::from_source(
+            r#"
+            [[rule]]
+            class_regex = "^kitty$"
+            floating = true
+            sound = "/tmp/a.wav"
+
+            [[rule]]
+            class_regex = "^kitty$"
+            floating = false
+
+            [[rule]]
+            floating = true
+            class_regex = "^kitty$"
+            sound = "/tmp/b.wav"
+            volume = 0.5
+            "#,
+        )
+        .unwrap();
+        assert!(!config.rules[0].same_predicates(&config.rules[1]));
+        assert!(config.rules[0].same_predicates(&config.rules[2]));
+        assert_eq!(
+            config.validate(),
+            vec!["Rule #2 has the same conditions as rule #0, so it will never match"]
+        );
+
+        let config = Config::from_source(
+            r#"
+            [[rule]]
+            title_regex_all = ["a", "b"]
+
+            [[rule]]
+            title_regex_all = ["a"]
+            "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_empty());
+    }
+
+    #[test]
+    fn test_matches_value() {
+        let config = Config::from_source(
+            r#"
+            [[rule]]
+            class_regex = "^kitty$"
+            floating = false
+            workspace = "dev"
+            "#,
+        )
+        .unwrap();
+        let rule = &config.rules[0];
+
+        assert!(rule.matches_value(
+            &serde_json::json!({
+                "class": "kitty",
+                "floating": false,
+                "workspace": { "id": 2, "name": "dev" },
+            }),
+            &WindowState::default()
+        ));
+        // Missing fields are defaulted.
+        assert!(rule.matches_value(
+            &serde_json::json!({
+                "class": "kitty",
+                "workspace": { "name": "dev" },
+            }),
+            &WindowState::default()
+        ));
+        assert!(!rule.matches_value(
+            &serde_json::json!({
+                "class": "kitty",
+                "floating": true,
+                "workspace": { "id": 2, "name": "dev" },
+            }),
+            &WindowState::default()
+        ));
+        assert!(!rule.matches_value(
+            &serde_json::json!({ "class": "QQ" }),
+            &WindowState::default()
+        ));
+        assert!(!rule.matches_value(&serde_json::json!({ "class": 42 }), &WindowState::default()));
+        assert!(!rule.matches_value(&serde_json::json!("kitty"), &WindowState::default()));
+    }
+
     #[test]
     fn test_rules() {
         let config = Config::from_source(
@@ -253,4 +3952,29 @@ mod test {
         );
         assert_eq!(config.rules[1].xwayland, Some(false));
     }
+
+    #[test]
+    fn test_title_regex_all() {
+        let config = Config::from_source(
+            r#"
+            [[rule]]
+            sound = "/tmp/a.wav"
+
+            [[rule]]
+            title_regex_all = ["foo", "bar"]
+            "#,
+        )
+        .unwrap();
+        assert!(config.rules[0].title_regex_all.is_none());
+        assert_eq!(
+            config.rules[1]
+                .title_regex_all
+                .as_ref()
+                .unwrap()
+                .iter()
+                .map(Regex::as_str)
+                .collect::<Vec<_>>(),
+            vec!["foo", "bar"]
+        );
+    }
 }
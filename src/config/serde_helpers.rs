@@ -1,4 +1,10 @@
-use serde::{de, de::Deserialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+use regex::Regex;
+use serde::{Serialize, Serializer, de, de::Deserialize};
 
 pub fn default_volume() -> f32 {
     1.0
@@ -19,3 +25,324 @@ where
         }
     })
 }
+
+/// Read a map of volumes, each between 0.0 and 1.0 like [`validate_volume`].
+pub fn validate_volume_map<'de, D>(d: D) -> Result<BTreeMap<String, f32>, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    #[derive(serde::Deserialize)]
+    struct Volume(#[serde(deserialize_with = "validate_volume")] f32);
+
+    BTreeMap::<String, Volume>::deserialize(d)
+        .map(|x| x.into_iter().map(|(key, Volume(x))| (key, x)).collect())
+}
+
+pub fn validate_db<'de, D>(d: D) -> Result<f32, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    f32::deserialize(d).and_then(|x| {
+        if x.is_finite() {
+            Ok(x)
+        } else {
+            Err(de::Error::invalid_value(
+                de::Unexpected::Float(x as f64),
+                &"decibels must be a finite number",
+            ))
+        }
+    })
+}
+
+pub fn validate_optional_db<'de, D>(d: D) -> Result<Option<f32>, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    validate_db(d).map(Some)
+}
+
+/// Convert a volume in decibels, shifted by `offset_db`, to the linear volume used everywhere
+/// else. The result is clamped to 1.0 (0 dB), so a positive offset can't make sounds clip.
+pub fn db_to_volume(db: f32, offset_db: f32) -> f32 {
+    10f32.powf((db + offset_db) / 20.0).clamp(0.0, 1.0)
+}
+
+pub fn validate_optional_volume<'de, D>(d: D) -> Result<Option<f32>, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    validate_volume(d).map(Some)
+}
+
+/// Parse a duration like `250ms`, `2s`, `1.5s`, `1m` or `1h`. A number without a unit is in
+/// milliseconds.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number = number
+        .parse::<f64>()
+        .map_err(|_| format!("invalid duration {s:?}"))?;
+    let seconds_per_unit = match unit.trim_start() {
+        "" | "ms" => 0.001,
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        _ => {
+            return Err(format!(
+                "invalid unit in duration {s:?}, expected ms, s, m or h"
+            ));
+        }
+    };
+    Duration::try_from_secs_f64(number * seconds_per_unit)
+        .map_err(|_| format!("duration {s:?} is out of range"))
+}
+
+struct MillisVisitor;
+
+impl de::Visitor<'_> for MillisVisitor {
+    type Value = u64;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(r#"milliseconds or a duration like "250ms", "2s" or "1m""#)
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<u64, E> {
+        Ok(v)
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<u64, E> {
+        u64::try_from(v).map_err(|_| E::invalid_value(de::Unexpected::Signed(v), &self))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<u64, E> {
+        let duration = parse_duration(v).map_err(E::custom)?;
+        u64::try_from(duration.as_millis()).map_err(|_| E::custom("duration is out of range"))
+    }
+}
+
+/// Read a time field in milliseconds, given either as a number of milliseconds or as a duration
+/// string, see [`parse_duration`].
+pub fn deserialize_ms<'de, D>(d: D) -> Result<u64, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    d.deserialize_any(MillisVisitor)
+}
+
+pub fn deserialize_optional_ms<'de, D>(d: D) -> Result<Option<u64>, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    deserialize_ms(d).map(Some)
+}
+
+/// `serde_regex` can't serialize `Option<Vec<Regex>>`, so write the patterns ourselves.
+pub fn serialize_optional_regexes<S: Serializer>(
+    regexes: &Option<Vec<Regex>>,
+    s: S,
+) -> Result<S::Ok, S::Error> {
+    regexes
+        .as_ref()
+        .map(|x| x.iter().map(Regex::as_str).collect::<Vec<_>>())
+        .serialize(s)
+}
+
+/// Read a number in `range`, which NaN never is.
+fn number_in<'de, D>(
+    d: D,
+    range: RangeInclusive<f32>,
+    expected: &'static str,
+) -> Result<f32, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    f32::deserialize(d).and_then(|x| {
+        if range.contains(&x) {
+            Ok(x)
+        } else {
+            Err(de::Error::invalid_value(
+                de::Unexpected::Float(x as f64),
+                &expected,
+            ))
+        }
+    })
+}
+
+pub fn validate_fraction<'de, D>(d: D) -> Result<f32, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    number_in(d, 0.0..=1.0, "a number between 0.0 and 1.0")
+}
+
+pub fn validate_escalate_step<'de, D>(d: D) -> Result<f32, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    number_in(d, 0.0..=f32::MAX, "a finite number of at least 0.0")
+}
+
+pub fn validate_escalate_cap<'de, D>(d: D) -> Result<f32, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    number_in(d, 1.0..=f32::MAX, "a finite number of at least 1.0")
+}
+
+pub fn default_escalate_step() -> f32 {
+    0.1
+}
+
+pub fn default_escalate_cap() -> f32 {
+    1.5
+}
+
+pub fn default_escalate_window_ms() -> u64 {
+    2000
+}
+
+pub fn default_fatigue_decay() -> f32 {
+    0.1
+}
+
+pub fn default_fatigue_recovery() -> f32 {
+    0.1
+}
+
+pub fn default_fatigue_min() -> f32 {
+    0.3
+}
+
+pub fn default_fatigue_window_ms() -> u64 {
+    2000
+}
+
+pub fn default_beeps_count() -> u32 {
+    1
+}
+
+pub fn validate_beeps_count<'de, D>(d: D) -> Result<u32, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    u32::deserialize(d).and_then(|x| {
+        if x <= super::MAX_BEEPS {
+            Ok(x)
+        } else {
+            Err(de::Error::invalid_value(
+                de::Unexpected::Unsigned(x as u64),
+                &"count of beeps must be at most 100",
+            ))
+        }
+    })
+}
+
+pub fn default_beeps_freq() -> f32 {
+    880.0
+}
+
+pub fn default_beeps_ms() -> u64 {
+    120
+}
+
+pub fn default_beeps_gap() -> u64 {
+    80
+}
+
+pub fn default_urgent_ttl_ms() -> u64 {
+    10000
+}
+
+pub fn default_play_retries() -> u32 {
+    2
+}
+
+pub fn default_mqtt_port() -> u16 {
+    1883
+}
+
+pub fn default_mqtt_topic() -> String {
+    "onionbell/bell".to_string()
+}
+
+pub fn default_mqtt_client_id() -> String {
+    "onionbell".to_string()
+}
+
+pub fn default_max_event_bytes() -> usize {
+    super::DEFAULT_MAX_EVENT_BYTES
+}
+
+#[allow(unused)]
+mod test {
+    use super::*;
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-4, "{a} != {b}");
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_duration("2s"), Ok(Duration::from_secs(2)));
+        assert_eq!(parse_duration("1.5s"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("1m"), Ok(Duration::from_secs(60)));
+        assert_eq!(parse_duration("1h"), Ok(Duration::from_secs(3600)));
+        assert_eq!(parse_duration("300"), Ok(Duration::from_millis(300)));
+        assert_eq!(parse_duration(" 10 s "), Ok(Duration::from_secs(10)));
+
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("ms").is_err());
+        assert!(parse_duration("-5s").is_err());
+        assert!(parse_duration("5 seconds").is_err());
+        assert!(parse_duration("1.2.3s").is_err());
+        assert!(parse_duration("1e400s").is_err());
+    }
+
+    #[test]
+    fn test_deserialize_ms() {
+        #[derive(serde::Deserialize)]
+        struct Times {
+            #[serde(deserialize_with = "deserialize_ms")]
+            a: u64,
+            #[serde(default, deserialize_with = "deserialize_optional_ms")]
+            b: Option<u64>,
+        }
+        let times: Times = toml::from_str(r#"a = "2s""#).unwrap();
+        assert_eq!((times.a, times.b), (2000, None));
+        let times: Times = toml::from_str(
+            r#"a = 250
+b = "1m""#,
+        )
+        .unwrap();
+        assert_eq!((times.a, times.b), (250, Some(60000)));
+        let times: Times = serde_json::from_str(r#"{ "a": "250ms", "b": 5 }"#).unwrap();
+        assert_eq!((times.a, times.b), (250, Some(5)));
+
+        assert!(toml::from_str::<Times>("a = -1").is_err());
+        assert!(toml::from_str::<Times>(r#"a = "2 weeks""#).is_err());
+        assert!(toml::from_str::<Times>("a = 1.5").is_err());
+    }
+
+    #[test]
+    fn test_db_to_volume() {
+        assert_eq!(db_to_volume(0.0, 0.0), 1.0);
+        assert_close(db_to_volume(-6.0, 0.0), 0.5012);
+        assert_close(db_to_volume(-20.0, 0.0), 0.1);
+        assert_eq!(db_to_volume(f32::NEG_INFINITY, 0.0), 0.0);
+
+        // The offset is added in decibels, before converting.
+        assert_close(db_to_volume(-12.0, 6.0), db_to_volume(-6.0, 0.0));
+        assert_close(db_to_volume(-6.0, -14.0), 0.1);
+    }
+
+    #[test]
+    fn test_db_to_volume_clamp() {
+        assert_eq!(db_to_volume(6.0, 0.0), 1.0);
+        assert_eq!(db_to_volume(-3.0, 10.0), 1.0);
+        assert_eq!(db_to_volume(0.0, f32::INFINITY), 1.0);
+    }
+}
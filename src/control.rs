@@ -0,0 +1,293 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::Shutdown;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+
+use log::{debug, warn};
+
+use crate::app::AppMessage;
+use crate::builtin;
+use crate::error::AppError;
+use crate::util::{reader_to_string, runtime_dir};
+
+/// Replies of failed commands start with this prefix.
+const ERROR_PREFIX: &str = "error: ";
+
+/// Commands accepted by the control socket. Each connection sends a single command line and
+/// receives the reply before the socket is closed.
+#[derive(Debug, PartialEq)]
+pub enum ControlCommand {
+    /// Report playback latency statistics.
+    Stats,
+
+    /// List the preloaded sounds and their sizes.
+    Sounds,
+
+    /// Read the sound files again, keeping the config.
+    ReloadSounds,
+
+    /// Read the rule file with the given name in `conf.d` again, replacing the rules it had.
+    ReloadFile(String),
+
+    /// Report whether onionbell is connected to Hyprland and able to play sounds.
+    Health,
+
+    /// Play the sound at an absolute path, or a builtin sound, right away, at the given volume or
+    /// the global `volume`.
+    Play(PathBuf, Option<f32>),
+
+    /// Play sounds again after `disable`.
+    Enable,
+
+    /// Stop playing sounds, until `enable`. This is remembered across restarts.
+    Disable,
+
+    /// Stop playing bells from the focused window, until it's closed or `unmute-active`.
+    MuteActive,
+
+    /// Play bells from the focused window again after `mute-active`.
+    UnmuteActive,
+
+    /// Print what is remembered between bells as JSON, see [`crate::snapshot::Snapshot`].
+    DumpState,
+
+    /// Replace what is remembered between bells with the output of `dump-state` saved at an
+    /// absolute path, e.g. by a previous process.
+    RestoreState(PathBuf),
+}
+
+impl ControlCommand {
+    pub fn parse(line: &str) -> Result<ControlCommand, AppError> {
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            Some("stats") => ControlCommand::Stats,
+            Some("sounds") => ControlCommand::Sounds,
+            Some("reload-sounds") => ControlCommand::ReloadSounds,
+            Some("reload-file") => match words.next() {
+                Some(name) => ControlCommand::ReloadFile(name.to_string()),
+                None => return Err(AppError::InvalidCommand("missing file name".into())),
+            },
+            Some("health") => ControlCommand::Health,
+            Some("play") => {
+                let Some(path) = words.next() else {
+                    return Err(AppError::InvalidCommand("missing sound path".into()));
+                };
+                // onionbell's working directory means nothing to the caller.
+                let path = PathBuf::from(path);
+                if !path.is_absolute() && builtin::name(&path).is_none() {
+                    return Err(AppError::InvalidCommand(format!(
+                        "{} is not an absolute path",
+                        path.to_string_lossy()
+                    )));
+                }
+                let volume = words.next().map(parse_volume).transpose()?;
+                ControlCommand::Play(path, volume)
+            }
+            Some("enable") => ControlCommand::Enable,
+            Some("disable") => ControlCommand::Disable,
+            Some("mute-active") => ControlCommand::MuteActive,
+            Some("unmute-active") => ControlCommand::UnmuteActive,
+            Some("dump-state") => ControlCommand::DumpState,
+            Some("restore-state") => {
+                let Some(path) = words.next() else {
+                    return Err(AppError::InvalidCommand("missing state path".into()));
+                };
+                let path = PathBuf::from(path);
+                if !path.is_absolute() {
+                    return Err(AppError::InvalidCommand(format!(
+                        "{} is not an absolute path",
+                        path.to_string_lossy()
+                    )));
+                }
+                ControlCommand::RestoreState(path)
+            }
+            Some(x) => return Err(AppError::InvalidCommand(format!("unknown command {x}"))),
+            None => return Err(AppError::InvalidCommand("empty command".into())),
+        };
+        if let Some(x) = words.next() {
+            return Err(AppError::InvalidCommand(format!("unexpected argument {x}")));
+        }
+        Ok(command)
+    }
+}
+
+fn parse_volume(volume: &str) -> Result<f32, AppError> {
+    match volume.parse::<f32>() {
+        Ok(x) if (0.0..=1.0).contains(&x) => Ok(x),
+        _ => Err(AppError::InvalidCommand(format!(
+            "volume {volume} is not between 0.0 and 1.0"
+        ))),
+    }
+}
+
+/// Path to the control socket, which is `$XDG_RUNTIME_DIR/onionbell.sock`.
+pub fn socket_path() -> Result<PathBuf, AppError> {
+    Ok(runtime_dir()?.join("onionbell.sock"))
+}
+
+/// Bind the control socket and accept connections on a background thread. Commands are forwarded
+/// to the event loop through `sender` so they are handled in order with Hyprland events.
+pub fn listen(path: &Path, sender: Sender<AppMessage>) -> Result<(), AppError> {
+    if UnixStream::connect(path).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("{} is used by another instance", path.to_string_lossy()),
+        )
+        .into());
+    }
+    // A stale socket left behind by a previous instance would make binding fail.
+    let _ = fs::remove_file(path);
+    let listener = UnixListener::bind(path)?;
+    debug!("Control socket: {}", path.to_string_lossy());
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            if let Err(err) = stream
+                .map_err(AppError::from)
+                .and_then(|x| serve(x, &sender))
+            {
+                warn!("Control connection failed: {}", err);
+            }
+        }
+    });
+    Ok(())
+}
+
+fn serve(mut stream: UnixStream, sender: &Sender<AppMessage>) -> Result<(), AppError> {
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+
+    let (reply_sender, reply_receiver) = mpsc::channel();
+    if sender
+        .send(AppMessage::Control(line.trim().to_string(), reply_sender))
+        .is_err()
+    {
+        // The event loop is gone, nobody is going to answer.
+        return Ok(());
+    }
+    let reply = match reply_receiver.recv() {
+        Ok(Ok(reply)) => reply,
+        Ok(Err(err)) => format!("{ERROR_PREFIX}{err}\n"),
+        Err(_) => return Ok(()),
+    };
+    stream.write_all(reply.as_bytes())?;
+    Ok(())
+}
+
+/// Send `command` to the running instance listening on `path` and return its reply.
+pub fn send(path: &Path, command: &str) -> Result<String, AppError> {
+    let mut stream = UnixStream::connect(path)?;
+    writeln!(stream, "{command}")?;
+    stream.shutdown(Shutdown::Write)?;
+    let reply = reader_to_string(stream)?;
+    match reply.strip_prefix(ERROR_PREFIX) {
+        Some(err) => Err(AppError::ControlError(err.trim_end().to_string())),
+        None => Ok(reply),
+    }
+}
+
+#[allow(unused)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            ControlCommand::parse("stats").unwrap(),
+            ControlCommand::Stats
+        );
+        assert_eq!(
+            ControlCommand::parse("  stats \n").unwrap(),
+            ControlCommand::Stats
+        );
+        assert_eq!(
+            ControlCommand::parse("sounds").unwrap(),
+            ControlCommand::Sounds
+        );
+        assert_eq!(
+            ControlCommand::parse("reload-sounds").unwrap(),
+            ControlCommand::ReloadSounds
+        );
+        assert_eq!(
+            ControlCommand::parse("health").unwrap(),
+            ControlCommand::Health
+        );
+        assert_eq!(
+            ControlCommand::parse("enable").unwrap(),
+            ControlCommand::Enable
+        );
+        assert_eq!(
+            ControlCommand::parse("disable").unwrap(),
+            ControlCommand::Disable
+        );
+        assert_eq!(
+            ControlCommand::parse("reload-file 10-kitty.toml").unwrap(),
+            ControlCommand::ReloadFile("10-kitty.toml".into())
+        );
+        assert!(ControlCommand::parse("reload-file").is_err());
+        assert!(ControlCommand::parse("reload-file a.toml b.toml").is_err());
+        assert_eq!(
+            ControlCommand::parse("play /tmp/a.wav").unwrap(),
+            ControlCommand::Play("/tmp/a.wav".into(), None)
+        );
+        assert_eq!(
+            ControlCommand::parse("play builtin:ding 0.5").unwrap(),
+            ControlCommand::Play("builtin:ding".into(), Some(0.5))
+        );
+        assert!(ControlCommand::parse("play").is_err());
+        assert!(ControlCommand::parse("play a.wav").is_err());
+        assert!(ControlCommand::parse("play /tmp/a.wav 1.5").is_err());
+        assert!(ControlCommand::parse("play /tmp/a.wav NaN").is_err());
+        assert!(ControlCommand::parse("play /tmp/a.wav loud").is_err());
+        assert!(ControlCommand::parse("play /tmp/a.wav 0.5 0.5").is_err());
+        assert_eq!(
+            ControlCommand::parse("mute-active").unwrap(),
+            ControlCommand::MuteActive
+        );
+        assert_eq!(
+            ControlCommand::parse("unmute-active").unwrap(),
+            ControlCommand::UnmuteActive
+        );
+        assert!(ControlCommand::parse("mute-active now").is_err());
+        assert_eq!(
+            ControlCommand::parse("dump-state").unwrap(),
+            ControlCommand::DumpState
+        );
+        assert_eq!(
+            ControlCommand::parse("restore-state /tmp/state.json").unwrap(),
+            ControlCommand::RestoreState("/tmp/state.json".into())
+        );
+        assert!(ControlCommand::parse("restore-state").is_err());
+        assert!(ControlCommand::parse("restore-state state.json").is_err());
+        assert!(ControlCommand::parse("").is_err());
+        assert!(ControlCommand::parse("foo").is_err());
+        assert!(ControlCommand::parse("stats now").is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let path = std::env::temp_dir().join(format!("onionbell-test-{}.sock", std::process::id()));
+        let (sender, receiver) = mpsc::channel();
+        listen(&path, sender).unwrap();
+        thread::spawn(move || {
+            for message in receiver {
+                if let AppMessage::Control(line, reply) = message {
+                    let _ = reply.send(match line.as_str() {
+                        "stats" => Ok("ok\n".into()),
+                        _ => Err(AppError::InvalidCommand(line)),
+                    });
+                }
+            }
+        });
+
+        assert_eq!(send(&path, "stats").unwrap(), "ok\n");
+        let Err(AppError::ControlError(err)) = send(&path, "foo") else {
+            unreachable!()
+        };
+        assert_eq!(err, "Invalid command: foo");
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,93 @@
+use std::time::{Duration, Instant};
+
+use crate::snapshot::{CooldownSnapshot, instant_ago, ms_ago};
+
+/// The cooldown of a rule with `window_cooldown_ms`. Once the rule plays for a window, more bells
+/// from the same window stay quiet for a while, but a bell from another window plays right away
+/// and restarts the cooldown for that window.
+#[derive(Debug, Default)]
+pub struct WindowCooldown {
+    /// The address of the window the rule last played for, and when.
+    last_played: Option<(String, Instant)>,
+}
+
+impl WindowCooldown {
+    /// Whether a bell from the window at `address` at `now` stays quiet.
+    pub fn is_cooling(&self, address: &str, cooldown: Duration, now: Instant) -> bool {
+        self.last_played.as_ref().is_some_and(|(last, since)| {
+            last == address && now.saturating_duration_since(*since) < cooldown
+        })
+    }
+
+    /// Start the cooldown for the window at `address`, when the rule plays for it at `now`. Quiet
+    /// bells aren't recorded, so they don't extend the cooldown.
+    pub fn record(&mut self, address: &str, now: Instant) {
+        self.last_played = Some((address.to_string(), now));
+    }
+
+    /// The state to carry over to another process, or `None` if the rule hasn't played yet.
+    pub fn snapshot(&self, now: Instant) -> Option<CooldownSnapshot> {
+        let (ref address, since) = *self.last_played.as_ref()?;
+        Some(CooldownSnapshot {
+            address: address.clone(),
+            played_ms_ago: ms_ago(since, now),
+        })
+    }
+
+    pub fn restore(snapshot: &CooldownSnapshot, now: Instant) -> WindowCooldown {
+        WindowCooldown {
+            last_played: instant_ago(snapshot.played_ms_ago, now)
+                .map(|x| (snapshot.address.clone(), x)),
+        }
+    }
+}
+
+#[allow(unused)]
+mod test {
+    use super::*;
+
+    /// Register a bell like the app does, returning whether it plays.
+    fn advance(
+        state: &mut WindowCooldown,
+        address: &str,
+        cooldown: Duration,
+        now: Instant,
+    ) -> bool {
+        if state.is_cooling(address, cooldown, now) {
+            return false;
+        }
+        state.record(address, now);
+        true
+    }
+
+    fn ms(start: Instant, ms: u64) -> Instant {
+        start + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn test_same_window() {
+        let start = Instant::now();
+        let cooldown = Duration::from_secs(10);
+        let mut state = WindowCooldown::default();
+        assert!(advance(&mut state, "a", cooldown, start));
+        assert!(!advance(&mut state, "a", cooldown, ms(start, 100)));
+        assert!(!advance(&mut state, "a", cooldown, ms(start, 9999)));
+
+        // Quiet bells don't extend it.
+        assert!(advance(&mut state, "a", cooldown, ms(start, 10000)));
+    }
+
+    #[test]
+    fn test_other_window() {
+        let start = Instant::now();
+        let cooldown = Duration::from_secs(10);
+        let mut state = WindowCooldown::default();
+        assert!(advance(&mut state, "a", cooldown, start));
+        assert!(advance(&mut state, "b", cooldown, ms(start, 100)));
+        assert!(!advance(&mut state, "b", cooldown, ms(start, 200)));
+
+        // Switching back resets it again.
+        assert!(advance(&mut state, "a", cooldown, ms(start, 300)));
+        assert!(!advance(&mut state, "a", cooldown, ms(start, 400)));
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+/// Events reporting window states that are debounced with `debounce_ms`. Their `event_sound`s
+/// play once the state settles rather than on every event.
+pub const DEBOUNCED_EVENTS: [&str; 2] = ["changefloatingmode", "fullscreen"];
+
+/// Tracks states that may toggle quickly, like a window's floating or fullscreen state during
+/// animations. A change is only reported once the new state has been stable for a while, and
+/// only if it differs from the last reported one.
+#[derive(Debug)]
+pub struct StateDebouncer<K, S> {
+    /// States that changed but haven't been stable for long enough, with the time they changed.
+    pending: HashMap<K, (S, Instant)>,
+
+    /// The last reported state of every key.
+    stable: HashMap<K, S>,
+}
+
+impl<K, S> Default for StateDebouncer<K, S> {
+    fn default() -> Self {
+        StateDebouncer {
+            pending: HashMap::new(),
+            stable: HashMap::new(),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone, S: PartialEq> StateDebouncer<K, S> {
+    /// Record that `key` switched to `state` at `now`.
+    pub fn observe(&mut self, key: K, state: S, now: Instant) {
+        match self.pending.get_mut(&key) {
+            Some(pending) if pending.0 == state => {}
+            _ => {
+                self.pending.insert(key, (state, now));
+            }
+        }
+    }
+
+    /// Report every state that has been stable for `stable_for` at `now` and differs from the
+    /// last reported state of its key. Toggling back and forth within `stable_for` reports
+    /// nothing.
+    pub fn settle(&mut self, stable_for: Duration, now: Instant) -> Vec<(K, &S)> {
+        let settled = self
+            .pending
+            .iter()
+            .filter(|(_, (_, since))| now.saturating_duration_since(*since) >= stable_for)
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+
+        let mut changed = Vec::new();
+        for key in settled {
+            let Some((state, _)) = self.pending.remove(&key) else {
+                continue;
+            };
+            if self.stable.get(&key) != Some(&state) {
+                self.stable.insert(key.clone(), state);
+                changed.push(key);
+            }
+        }
+        changed
+            .into_iter()
+            .map(|key| {
+                let state = &self.stable[&key];
+                (key, state)
+            })
+            .collect()
+    }
+
+    /// When the next pending state will have been stable for `stable_for`.
+    pub fn next_deadline(&self, stable_for: Duration) -> Option<Instant> {
+        self.pending
+            .values()
+            .map(|(_, since)| *since + stable_for)
+            .min()
+    }
+
+    /// The last reported state of `key`.
+    pub fn stable_state(&self, key: &K) -> Option<&S> {
+        self.stable.get(key)
+    }
+
+    /// Forget everything about `key`, e.g. when its window is closed.
+    pub fn forget(&mut self, key: &K) {
+        self.pending.remove(key);
+        self.stable.remove(key);
+    }
+
+    /// Forget everything about the keys `f` returns `true` for, e.g. all the states of a window
+    /// that's closed.
+    pub fn forget_where(&mut self, mut f: impl FnMut(&K) -> bool) {
+        self.pending.retain(|key, _| !f(key));
+        self.stable.retain(|key, _| !f(key));
+    }
+}
+
+#[allow(unused)]
+mod test {
+    use super::*;
+
+    const STABLE_FOR: Duration = Duration::from_millis(100);
+
+    fn ms(start: Instant, ms: u64) -> Instant {
+        start + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn test_stable_change() {
+        let start = Instant::now();
+        let mut debouncer = StateDebouncer::default();
+        debouncer.observe("a", true, start);
+        assert_eq!(debouncer.next_deadline(STABLE_FOR), Some(ms(start, 100)));
+        assert!(debouncer.settle(STABLE_FOR, ms(start, 99)).is_empty());
+        assert_eq!(
+            debouncer.settle(STABLE_FOR, ms(start, 100)),
+            vec![("a", &true)]
+        );
+        assert_eq!(debouncer.stable_state(&"a"), Some(&true));
+        assert_eq!(debouncer.next_deadline(STABLE_FOR), None);
+
+        // Observing the same state again doesn't report anything new.
+        debouncer.observe("a", true, ms(start, 200));
+        assert!(debouncer.settle(STABLE_FOR, ms(start, 300)).is_empty());
+    }
+
+    #[test]
+    fn test_flapping() {
+        let start = Instant::now();
+        let mut debouncer = StateDebouncer::default();
+        debouncer.observe("a", false, start);
+        debouncer.settle(STABLE_FOR, ms(start, 100));
+
+        // Toggling restarts the timer, and toggling back to the stable state reports nothing.
+        debouncer.observe("a", true, ms(start, 200));
+        debouncer.observe("a", false, ms(start, 250));
+        debouncer.observe("a", true, ms(start, 280));
+        assert!(debouncer.settle(STABLE_FOR, ms(start, 350)).is_empty());
+        debouncer.observe("a", false, ms(start, 360));
+        assert!(debouncer.settle(STABLE_FOR, ms(start, 500)).is_empty());
+        assert_eq!(debouncer.stable_state(&"a"), Some(&false));
+    }
+
+    #[test]
+    fn test_forget() {
+        let start = Instant::now();
+        let mut debouncer = StateDebouncer::default();
+        debouncer.observe("a", true, start);
+        debouncer.observe("b", true, start);
+        debouncer.settle(STABLE_FOR, ms(start, 100));
+        debouncer.forget(&"a");
+        assert_eq!(debouncer.stable_state(&"a"), None);
+        assert_eq!(debouncer.stable_state(&"b"), Some(&true));
+
+        debouncer.observe("c", false, start);
+        debouncer.forget_where(|x| *x != "a");
+        assert_eq!(debouncer.stable_state(&"b"), None);
+        assert_eq!(debouncer.next_deadline(STABLE_FOR), None);
+    }
+}
@@ -0,0 +1,88 @@
+use std::io::{self, Write};
+
+use serde_json::{Value, json};
+
+use crate::config::{ResolvedSound, Rule};
+use crate::hypr::HyprClient;
+
+/// Describe a handled bell as a JSON object for `--emit-json`. `address` is the address of the
+/// source window from the event, `client` the source window if it was found, and `rule` the rule
+/// that matched, if any.
+pub fn bell_json(
+    address: &str,
+    client: Option<&HyprClient>,
+    rule: Option<&Rule>,
+    resolved: &ResolvedSound,
+) -> Value {
+    json!({
+        "event": "bell",
+        "address": address,
+        "rule": resolved.rule,
+        "rule_name": rule.and_then(|x| x.name.as_deref()),
+        "window": client.map(|x| &x.raw),
+        "sound": resolved.sound.as_ref().map(|x| x.to_string_lossy()),
+        "command": resolved.command,
+        "volume": resolved.volume,
+    })
+}
+
+/// Write `value` to `out` in a single line and flush it, so consumers see it right away.
+pub fn write_line(out: &mut impl Write, value: &Value) -> io::Result<()> {
+    writeln!(out, "{value}")?;
+    out.flush()
+}
+
+#[allow(unused)]
+mod test {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn test_bell_json() {
+        let clients = HyprClient::parse_partial_clients(
+            r#"[{ "address": "0x558e91924520", "class": "kitty", "title": "vim" }]"#,
+        )
+        .unwrap();
+        let rule = Rule {
+            name: Some("terminals".into()),
+            ..Default::default()
+        };
+        let resolved = ResolvedSound {
+            sound: Some(PathBuf::from("builtin:ding")),
+            command: None,
+            volume: 0.5,
+            volume_floor: None,
+            rule: Some(2),
+        };
+        let mut out = Vec::new();
+        let value = bell_json("558e91924520", clients.first(), Some(&rule), &resolved);
+        write_line(&mut out, &value).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.lines().count(), 1);
+        assert!(out.ends_with('\n'));
+        assert_eq!(
+            serde_json::from_str::<Value>(&out).unwrap(),
+            json!({
+                "event": "bell",
+                "address": "558e91924520",
+                "rule": 2,
+                "rule_name": "terminals",
+                "window": { "address": "0x558e91924520", "class": "kitty", "title": "vim" },
+                "sound": "builtin:ding",
+                "command": null,
+                "volume": 0.5,
+            })
+        );
+
+        // Orphan bells played with the global sound.
+        let resolved = ResolvedSound {
+            rule: None,
+            ..resolved
+        };
+        let value = bell_json("deadbeef", None, None, &resolved);
+        assert_eq!(value["rule"], Value::Null);
+        assert_eq!(value["rule_name"], Value::Null);
+        assert_eq!(value["window"], Value::Null);
+    }
+}
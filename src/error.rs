@@ -1,4 +1,5 @@
 use std::env::VarError;
+use std::path::PathBuf;
 
 use thiserror::Error;
 
@@ -13,6 +14,9 @@ pub enum AppError {
     #[error(transparent)]
     TomlDeserializationError(#[from] toml::de::Error),
 
+    #[error(transparent)]
+    TomlSerializationError(#[from] toml::ser::Error),
+
     #[error(transparent)]
     RodioStreamError(#[from] rodio::StreamError),
 
@@ -21,4 +25,49 @@ pub enum AppError {
 
     #[error(transparent)]
     JsonError(#[from] serde_json::Error),
+
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(String),
+
+    #[error("Invalid command: {0}")]
+    InvalidCommand(String),
+
+    #[error("{0}")]
+    ControlError(String),
+
+    #[error(
+        "XDG_RUNTIME_DIR is not set, and the fallback {} does not exist",
+        .0.to_string_lossy()
+    )]
+    RuntimeDirNotFound(PathBuf),
+
+    #[error("Expected a sound file, found a directory: {}", .0.to_string_lossy())]
+    SoundIsDirectory(PathBuf),
+
+    #[error("Unknown builtin sound: {0}")]
+    UnknownBuiltinSound(String),
+
+    #[error(
+        "{} is a bundle, but onionbell was built without the `bundle` feature",
+        .0.to_string_lossy()
+    )]
+    BundleUnsupported(PathBuf),
+
+    #[error("Bundle {} has no entry {}", .0.to_string_lossy(), .1)]
+    MissingBundleEntry(PathBuf, String),
+
+    #[error("Invalid zip archive: {0}")]
+    InvalidZip(String),
+
+    #[error("Audio backend {0} is not available")]
+    UnavailableBackend(String),
+
+    #[error("Audio device {0} is not available")]
+    UnavailableDevice(String),
+
+    #[error("Rule #{0} refers to the undefined group `{1}`")]
+    UndefinedGroup(usize, String),
+
+    #[error("The config was not read from a file, so there is no conf.d directory")]
+    NoConfDir,
 }
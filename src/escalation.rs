@@ -0,0 +1,145 @@
+use std::time::{Duration, Instant};
+
+use crate::config::Escalate;
+use crate::snapshot::{LevelSnapshot, instant_ago, ms_ago};
+
+/// Tracks how far the volume has escalated across repeated bells.
+#[derive(Debug, Default)]
+pub struct Escalation {
+    /// Extra volume factor on top of 1.0.
+    level: f32,
+    last_bell: Option<Instant>,
+}
+
+impl Escalation {
+    /// Register a bell at `now` and return the volume factor it should be played with.
+    pub fn advance(&mut self, config: &Escalate, now: Instant) -> f32 {
+        let window = Duration::from_millis(config.window_ms);
+        let max_level = (config.cap - 1.0).max(0.0);
+        if let Some(last_bell) = self.last_bell {
+            let elapsed = now.saturating_duration_since(last_bell);
+            if elapsed <= window {
+                self.level = (self.level + config.step).min(max_level);
+            } else {
+                // Every full window of silence takes one step back down.
+                let quiet_windows = elapsed.as_millis() / window.as_millis().max(1);
+                self.level = (self.level - config.step * quiet_windows as f32).max(0.0);
+            }
+        }
+        self.last_bell = Some(now);
+        1.0 + self.level
+    }
+
+    /// The state to carry over to another process, see [`Snapshot`](crate::snapshot::Snapshot).
+    pub fn snapshot(&self, now: Instant) -> LevelSnapshot {
+        LevelSnapshot {
+            level: self.level,
+            last_bell_ms_ago: self.last_bell.map(|x| ms_ago(x, now)),
+        }
+    }
+
+    pub fn restore(snapshot: &LevelSnapshot, now: Instant) -> Self {
+        Self {
+            level: snapshot.level,
+            last_bell: snapshot.last_bell_ms_ago.and_then(|x| instant_ago(x, now)),
+        }
+    }
+}
+
+/// Combine the `volume` chosen for a bell with the escalation `factor`. The result is clamped to
+/// 1.0 so it doesn't clip, and the second value tells whether that happened.
+pub fn compose_volume(volume: f32, factor: f32) -> (f32, bool) {
+    let composed = volume * factor;
+    if composed > 1.0 {
+        (1.0, true)
+    } else {
+        (composed, false)
+    }
+}
+
+#[allow(unused)]
+mod test {
+    use super::*;
+
+    fn config() -> Escalate {
+        Escalate {
+            step: 0.25,
+            cap: 1.5,
+            window_ms: 1000,
+        }
+    }
+
+    #[test]
+    fn test_escalate_and_cap() {
+        let config = config();
+        let start = Instant::now();
+        let mut escalation = Escalation::default();
+        assert_eq!(escalation.advance(&config, start), 1.0);
+        assert_eq!(
+            escalation.advance(&config, start + Duration::from_millis(500)),
+            1.25
+        );
+        assert_eq!(
+            escalation.advance(&config, start + Duration::from_millis(1000)),
+            1.5
+        );
+        assert_eq!(
+            escalation.advance(&config, start + Duration::from_millis(1500)),
+            1.5
+        );
+    }
+
+    #[test]
+    fn test_decay() {
+        let config = config();
+        let start = Instant::now();
+        let mut escalation = Escalation::default();
+        escalation.advance(&config, start);
+        escalation.advance(&config, start + Duration::from_millis(100));
+        escalation.advance(&config, start + Duration::from_millis(200));
+        // One full quiet window decays a single step.
+        assert_eq!(
+            escalation.advance(&config, start + Duration::from_millis(1500)),
+            1.25
+        );
+        // A long silence brings it back to normal.
+        assert_eq!(
+            escalation.advance(&config, start + Duration::from_millis(10000)),
+            1.0
+        );
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let config = config();
+        let start = Instant::now();
+        let mut escalation = Escalation::default();
+        escalation.advance(&config, start);
+        escalation.advance(&config, start + Duration::from_millis(500));
+        let snapshot = escalation.snapshot(start + Duration::from_millis(700));
+        assert_eq!(snapshot.level, 0.25);
+        assert_eq!(snapshot.last_bell_ms_ago, Some(200));
+
+        // Restored in another process, the last bell is as long ago as it was.
+        let later = start + Duration::from_secs(60);
+        let mut restored = Escalation::restore(&snapshot, later);
+        assert_eq!(
+            restored.advance(&config, later + Duration::from_millis(300)),
+            1.5
+        );
+        assert_eq!(
+            Escalation::restore(&Escalation::default().snapshot(later), later)
+                .advance(&config, later),
+            1.0
+        );
+    }
+
+    #[test]
+    fn test_compose_volume() {
+        assert_eq!(compose_volume(0.5, 1.0), (0.5, false));
+        assert_eq!(compose_volume(0.5, 1.5), (0.75, false));
+        assert_eq!(compose_volume(1.0, 1.0), (1.0, false));
+        assert_eq!(compose_volume(0.8, 1.5), (1.0, true));
+        assert_eq!(compose_volume(0.0, 1.5), (0.0, false));
+    }
+}
@@ -0,0 +1,138 @@
+use crate::config::{Config, ResolvedSound, RuleState};
+use crate::hypr::{HyprActiveWindow, HyprClient, HyprMonitor};
+
+/// Titles longer than this many characters are cut in the table.
+const MAX_TITLE_LEN: usize = 32;
+
+/// Resolve a bell from every window in `clients` as if it rang first, and lay out which rule
+/// would match and what would be played as a table for `--explain`. `active_window` and
+/// `monitors` are used for the rules that need them.
+pub fn explain(
+    config: &Config,
+    clients: &[HyprClient],
+    active_window: Option<&HyprActiveWindow>,
+    monitors: Option<&[HyprMonitor]>,
+) -> String {
+    let mut rows = vec![["ADDRESS", "CLASS", "TITLE", "RULE", "SOUND", "VOLUME"].map(String::from)];
+    for client in clients {
+        let address = client.address.strip_prefix("0x").unwrap_or(&client.address);
+        // Every window starts from scratch, so counters and `once_per_window` of one don't
+        // affect the next.
+        let mut state = RuleState {
+            active_window: active_window.cloned(),
+            monitors: monitors.map(<[_]>::to_vec),
+            ..Default::default()
+        };
+        let resolved = config.resolve_sound(clients, address, &mut state);
+        rows.push([
+            client.address.clone(),
+            client.class.clone(),
+            truncate(&client.title, MAX_TITLE_LEN),
+            rule_cell(config, &resolved),
+            sound_cell(&resolved),
+            format!("{:.2}", resolved.volume),
+        ]);
+    }
+    format_table(&rows)
+}
+
+fn rule_cell(config: &Config, resolved: &ResolvedSound) -> String {
+    match resolved.rule {
+        Some(i) => match config.rules[i].name {
+            Some(ref name) => format!("#{i} ({name})"),
+            None => format!("#{i}"),
+        },
+        None => "global".to_string(),
+    }
+}
+
+fn sound_cell(resolved: &ResolvedSound) -> String {
+    match (&resolved.sound, &resolved.command) {
+        (Some(sound), _) => sound.to_string_lossy().into_owned(),
+        (None, Some(command)) => format!("`{command}`"),
+        (None, None) => "silence".to_string(),
+    }
+}
+
+/// Cut `s` to `max` characters, marking the cut with an ellipsis.
+fn truncate(s: &str, max: usize) -> String {
+    if s.chars().count() <= max {
+        return s.to_string();
+    }
+    let mut truncated = s.chars().take(max - 1).collect::<String>();
+    truncated.push('…');
+    truncated
+}
+
+/// Lay out `rows` in columns padded to their widest cell, the first row being the header.
+fn format_table<const N: usize>(rows: &[[String; N]]) -> String {
+    let mut widths = [0; N];
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut table = String::new();
+    for row in rows {
+        let line = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        table += line.trim_end();
+        table.push('\n');
+    }
+    table
+}
+
+#[allow(unused)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_explain() {
+        let clients = HyprClient::parse_partial_clients(
+            r#"[
+                { "address": "0x558e91924520", "class": "kitty", "title": "vim" },
+                { "address": "0x558e9243ab50", "class": "firefox", "title": "A very long title of a web page that goes on" },
+                { "address": "0x558e9243cd70", "class": "foot", "title": "htop" }
+            ]"#,
+        )
+        .unwrap();
+        let config = Config::from_source(
+            r#"
+            sound = "builtin:chime"
+            volume = 0.8
+
+            [[rule]]
+            name = "terminals"
+            class_regex = "^kitty$"
+            sound = "builtin:ding"
+            volume = 0.5
+            once_per_window = true
+
+            [[rule]]
+            class_regex = "^foot$"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            explain(&config, &clients, None, None),
+            [
+                "ADDRESS         CLASS    TITLE                             RULE            SOUND          VOLUME",
+                "0x558e91924520  kitty    vim                               #0 (terminals)  builtin:ding   0.50",
+                "0x558e9243ab50  firefox  A very long title of a web page…  global          builtin:chime  0.80",
+                "0x558e9243cd70  foot     htop                              #1              silence        1.00",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("vim", 3), "vim");
+        assert_eq!(truncate("渦状銀河のシンフォニエッタ", 5), "渦状銀河…");
+    }
+}
@@ -0,0 +1,91 @@
+use std::time::{Duration, Instant};
+
+use crate::config::Fatigue;
+use crate::snapshot::{LevelSnapshot, instant_ago, ms_ago};
+
+/// Tracks how far the volume of a rule has dropped across repeated bells.
+#[derive(Debug, Default)]
+pub struct FatigueState {
+    /// Volume factor taken off 1.0.
+    level: f32,
+    last_bell: Option<Instant>,
+}
+
+impl FatigueState {
+    /// Register a bell at `now` and return the volume factor it should be played with.
+    pub fn advance(&mut self, config: &Fatigue, now: Instant) -> f32 {
+        let window = Duration::from_millis(config.window_ms);
+        let max_level = (1.0 - config.min).clamp(0.0, 1.0);
+        if let Some(last_bell) = self.last_bell {
+            let elapsed = now.saturating_duration_since(last_bell);
+            if elapsed <= window {
+                self.level = (self.level + config.decay).min(max_level);
+            } else {
+                // Every full window of silence recovers some volume.
+                let quiet_windows = elapsed.as_millis() / window.as_millis().max(1);
+                self.level = (self.level - config.recovery * quiet_windows as f32).max(0.0);
+            }
+        }
+        self.last_bell = Some(now);
+        1.0 - self.level
+    }
+
+    /// The state to carry over to another process, see [`Snapshot`](crate::snapshot::Snapshot).
+    pub fn snapshot(&self, now: Instant) -> LevelSnapshot {
+        LevelSnapshot {
+            level: self.level,
+            last_bell_ms_ago: self.last_bell.map(|x| ms_ago(x, now)),
+        }
+    }
+
+    pub fn restore(snapshot: &LevelSnapshot, now: Instant) -> Self {
+        Self {
+            level: snapshot.level,
+            last_bell: snapshot.last_bell_ms_ago.and_then(|x| instant_ago(x, now)),
+        }
+    }
+}
+
+#[allow(unused)]
+mod test {
+    use super::*;
+
+    fn config() -> Fatigue {
+        Fatigue {
+            decay: 0.25,
+            recovery: 0.125,
+            min: 0.5,
+            window_ms: 1000,
+        }
+    }
+
+    fn ms(start: Instant, ms: u64) -> Instant {
+        start + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn test_decay() {
+        let config = config();
+        let start = Instant::now();
+        let mut fatigue = FatigueState::default();
+        assert_eq!(fatigue.advance(&config, start), 1.0);
+        assert_eq!(fatigue.advance(&config, ms(start, 500)), 0.75);
+        assert_eq!(fatigue.advance(&config, ms(start, 1500)), 0.5);
+        assert_eq!(fatigue.advance(&config, ms(start, 2000)), 0.5);
+    }
+
+    #[test]
+    fn test_recovery() {
+        let config = config();
+        let start = Instant::now();
+        let mut fatigue = FatigueState::default();
+        fatigue.advance(&config, start);
+        fatigue.advance(&config, ms(start, 100));
+        assert_eq!(fatigue.advance(&config, ms(start, 200)), 0.5);
+
+        // One full window of silence recovers one step, and two recover two.
+        assert_eq!(fatigue.advance(&config, ms(start, 1700)), 0.625);
+        assert_eq!(fatigue.advance(&config, ms(start, 3800)), 0.875);
+        assert_eq!(fatigue.advance(&config, ms(start, 60_000)), 1.0);
+    }
+}
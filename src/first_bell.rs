@@ -0,0 +1,67 @@
+use std::time::{Duration, Instant};
+
+use crate::snapshot::{instant_ago, ms_ago};
+
+/// Tracks whether a bell is the first one of a session, i.e. the first since onionbell started or
+/// since a long enough silence. See `first_bell_sound` in the config.
+#[derive(Debug, Default)]
+pub struct FirstBell {
+    last_bell: Option<Instant>,
+}
+
+impl FirstBell {
+    /// Register a bell at `now` and return whether it's the first of a session. Without a
+    /// `timeout`, only the first bell after starting is.
+    pub fn advance(&mut self, timeout: Option<Duration>, now: Instant) -> bool {
+        let fresh = match (self.last_bell, timeout) {
+            (None, _) => true,
+            (Some(last_bell), Some(timeout)) => now.saturating_duration_since(last_bell) >= timeout,
+            (Some(_), None) => false,
+        };
+        self.last_bell = Some(now);
+        fresh
+    }
+
+    /// How many milliseconds before `now` the last bell was, to carry over to another process.
+    pub fn snapshot(&self, now: Instant) -> Option<u64> {
+        self.last_bell.map(|x| ms_ago(x, now))
+    }
+
+    pub fn restore(last_bell_ms_ago: Option<u64>, now: Instant) -> FirstBell {
+        FirstBell {
+            last_bell: last_bell_ms_ago.and_then(|x| instant_ago(x, now)),
+        }
+    }
+}
+
+#[allow(unused)]
+mod test {
+    use super::*;
+
+    fn ms(start: Instant, ms: u64) -> Instant {
+        start + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn test_timeout() {
+        let start = Instant::now();
+        let timeout = Some(Duration::from_secs(1));
+        let mut first_bell = FirstBell::default();
+        assert!(first_bell.advance(timeout, start));
+        assert!(!first_bell.advance(timeout, ms(start, 500)));
+
+        // Every bell restarts the silence.
+        assert!(!first_bell.advance(timeout, ms(start, 1400)));
+        assert!(first_bell.advance(timeout, ms(start, 2400)));
+        assert!(!first_bell.advance(timeout, ms(start, 2400)));
+    }
+
+    #[test]
+    fn test_no_timeout() {
+        let start = Instant::now();
+        let mut first_bell = FirstBell::default();
+        assert!(first_bell.advance(None, start));
+        assert!(!first_bell.advance(None, ms(start, 10)));
+        assert!(!first_bell.advance(None, ms(start, 3_600_000)));
+    }
+}
@@ -0,0 +1,113 @@
+use std::fmt;
+use std::time::Duration;
+
+/// Status of a running onionbell, reported by the `health` control command.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Health {
+    /// Whether Hyprland's request socket accepts connections, which is needed to match rules.
+    pub hyprland: bool,
+
+    /// Whether events are read from the event socket. onionbell exits when it's lost, so this is
+    /// only `false` while shutting down.
+    pub socket2: bool,
+
+    /// How long ago the last event arrived, or `None` if none has arrived yet.
+    pub last_event: Option<Duration>,
+
+    /// Whether the audio output stream is open and the audio worker is still running.
+    pub audio: bool,
+
+    /// How many of the sounds referenced by the config are loaded.
+    pub sounds_loaded: usize,
+
+    /// How many sounds the config references.
+    pub sounds_total: usize,
+
+    /// How many rules the config has.
+    pub rules: usize,
+}
+
+impl Health {
+    /// Whether everything is working, so bells will play as configured.
+    pub fn ok(&self) -> bool {
+        self.hyprland && self.socket2 && self.audio && self.sounds_loaded == self.sounds_total
+    }
+}
+
+impl fmt::Display for Health {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn status(ok: bool) -> &'static str {
+            if ok { "ok" } else { "failed" }
+        }
+
+        writeln!(f, "status: {}", if self.ok() { "ok" } else { "degraded" })?;
+        writeln!(f, "hyprland: {}", status(self.hyprland))?;
+        writeln!(f, "socket2: {}", status(self.socket2))?;
+        match self.last_event {
+            Some(x) => writeln!(f, "last event: {:.1}s ago", x.as_secs_f64())?,
+            None => writeln!(f, "last event: never")?,
+        }
+        writeln!(f, "audio: {}", status(self.audio))?;
+        writeln!(
+            f,
+            "sounds: {} of {} loaded",
+            self.sounds_loaded, self.sounds_total
+        )?;
+        writeln!(f, "rules: {}", self.rules)
+    }
+}
+
+#[allow(unused)]
+mod test {
+    use super::*;
+
+    fn healthy() -> Health {
+        Health {
+            hyprland: true,
+            socket2: true,
+            last_event: Some(Duration::from_millis(3200)),
+            audio: true,
+            sounds_loaded: 2,
+            sounds_total: 2,
+            rules: 5,
+        }
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(
+            healthy().to_string(),
+            [
+                "status: ok\n",
+                "hyprland: ok\n",
+                "socket2: ok\n",
+                "last event: 3.2s ago\n",
+                "audio: ok\n",
+                "sounds: 2 of 2 loaded\n",
+                "rules: 5\n",
+            ]
+            .concat()
+        );
+
+        let health = Health {
+            hyprland: false,
+            last_event: None,
+            sounds_loaded: 1,
+            ..healthy()
+        };
+        assert!(!health.ok());
+        let health = health.to_string();
+        assert!(health.starts_with("status: degraded\nhyprland: failed\n"));
+        assert!(health.contains("last event: never\n"));
+        assert!(health.contains("sounds: 1 of 2 loaded\n"));
+    }
+
+    #[test]
+    fn test_missing_sound() {
+        let health = Health {
+            sounds_loaded: 1,
+            ..healthy()
+        };
+        assert!(!health.ok());
+    }
+}
@@ -2,14 +2,16 @@ use std::io::Write;
 use std::{os::unix::net::UnixStream, path::Path};
 
 use log::trace;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use crate::config::Rule;
-use crate::config::WorkspaceRule;
 use crate::error::AppError;
+use crate::procfs;
 use crate::util::reader_to_string;
 
-#[derive(Deserialize, Debug, Clone)]
+/// A window as reported by `hyprctl clients -j`. See [`HyprClient::with_defaults`] for windows
+/// described by hand, which may leave fields out.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct HyprClient {
     pub address: String,
@@ -39,68 +41,508 @@ pub struct HyprClient {
     pub xdg_tag: String,
     pub xdg_description: String,
     pub content_type: String,
+
+    /// The opacity of the window from 0.0 to 1.0, for Hyprland versions that report it. It's
+    /// `None` otherwise.
+    pub opacity: Option<f32>,
+
+    /// The window as Hyprland reported it, including fields that are not modeled above, for
+    /// rules with `field`. Only filled in by [`HyprClient::from_value`].
+    #[serde(skip)]
+    pub raw: serde_json::Value,
+}
+
+/// The focused window as reported by `hyprctl activewindow -j`. Only the fields used by rules are
+/// kept. When no window is focused, Hyprland reports an empty object, which leaves every field
+/// empty.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct HyprActiveWindow {
+    pub address: String,
+    pub class: String,
+    pub title: String,
+}
+
+impl HyprActiveWindow {
+    pub fn get<P: AsRef<Path>>(socket: P) -> Result<HyprActiveWindow, AppError> {
+        let mut socket = UnixStream::connect(socket)?;
+        write!(socket, "-j/activewindow")?;
+        Ok(serde_json::from_str(&reader_to_string(socket)?)?)
+    }
+}
+
+/// A monitor as reported by `hyprctl monitors -j`. Only the fields used by rules are kept.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct HyprMonitor {
+    pub id: i32,
+    pub name: String,
+
+    /// Resolution of the current mode in physical pixels, regardless of `scale`.
+    pub width: i32,
+    pub height: i32,
+    pub scale: f32,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+impl HyprMonitor {
+    pub fn get<P: AsRef<Path>>(socket: P) -> Result<Vec<HyprMonitor>, AppError> {
+        let mut socket = UnixStream::connect(socket)?;
+        write!(socket, "-j/monitors")?;
+        Ok(serde_json::from_str(&reader_to_string(socket)?)?)
+    }
+
+    /// Find the monitor with the ID `id`, as in the `monitor` of windows.
+    pub fn find(monitors: &[HyprMonitor], id: i32) -> Option<&HyprMonitor> {
+        monitors.iter().find(|x| x.id == id)
+    }
+}
+
+/// Where a window is and how large it is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Geometry {
+    pub at: [i32; 2],
+    pub size: [i32; 2],
+}
+
+/// What is known about the source window of a bell besides `hyprctl clients`, for rules with
+/// `urgent`, `moved`, `resized`, `active_class_regex` or the monitor predicates. See
+/// [`crate::config::RuleState::window_state`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WindowState<'a> {
+    /// The geometry of the window when it opened, or `None` if it opened before onionbell
+    /// started.
+    pub initial_geometry: Option<Geometry>,
+
+    /// Whether the window requested attention recently.
+    pub urgent: bool,
+
+    /// The monitors, or `None` if they're unknown.
+    pub monitors: Option<&'a [HyprMonitor]>,
+
+    /// The focused window, or `None` if it's unknown.
+    pub active_window: Option<&'a HyprActiveWindow>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
 pub struct HyprWorkspace {
     pub id: i32,
     pub name: String,
 }
 
+impl HyprWorkspace {
+    /// The name of the special workspace without the `special:` prefix, or `None` for a regular
+    /// workspace.
+    pub fn special_name(&self) -> Option<&str> {
+        self.name.strip_prefix("special:")
+    }
+}
+
+/// Split a socket2 event into its type and data.
+pub fn parse_event(event: &str) -> Option<(&str, &str)> {
+    // The response is always in format "event_type>>data" according to Hyprland's
+    // documentation (https://wiki.hypr.land/IPC/#xdg_runtime_dirhyprhissocket2sock).
+    event.split_once(">>")
+}
+
 impl HyprClient {
     pub fn get_clients<P: AsRef<Path>>(socket: P) -> Result<Vec<HyprClient>, AppError> {
         let mut socket = UnixStream::connect(socket)?;
         write!(socket, "-j/clients")?;
-        Ok(serde_json::from_str(&reader_to_string(socket)?)?)
+        HyprClient::parse_clients(&reader_to_string(socket)?)
+    }
+
+    /// Parse the output of `hyprctl clients -j`, keeping each window's JSON in `raw`.
+    pub fn parse_clients(source: &str) -> Result<Vec<HyprClient>, AppError> {
+        let values: Vec<serde_json::Value> = serde_json::from_str(source)?;
+        values
+            .into_iter()
+            .map(|raw| HyprClient::from_value(raw).map_err(AppError::from))
+            .collect()
+    }
+
+    /// Like [`HyprClient::parse_clients`], for windows described by hand, see
+    /// [`HyprClient::with_defaults`].
+    pub fn parse_partial_clients(source: &str) -> Result<Vec<HyprClient>, AppError> {
+        let values: Vec<serde_json::Value> = serde_json::from_str(source)?;
+        values
+            .into_iter()
+            .map(|x| HyprClient::from_partial_value(x).map_err(AppError::from))
+            .collect()
+    }
+
+    /// Parse a single window of `hyprctl clients -j`, keeping its JSON in `raw`.
+    pub fn from_value(raw: serde_json::Value) -> Result<HyprClient, serde_json::Error> {
+        let client = HyprClient::deserialize(&raw)?;
+        Ok(HyprClient { raw, ..client })
+    }
+
+    /// Like [`HyprClient::from_value`], for a window described by hand, see
+    /// [`HyprClient::with_defaults`]. `raw` is kept as it's given.
+    pub fn from_partial_value(raw: serde_json::Value) -> Result<HyprClient, serde_json::Error> {
+        let client = HyprClient::from_value(HyprClient::with_defaults(raw.clone()))?;
+        Ok(HyprClient { raw, ..client })
+    }
+
+    /// Fill in the fields that `value`, a window described by hand as a JSON object, leaves out
+    /// with their default values, so it can be parsed with [`HyprClient::from_value`]. Hyprland
+    /// itself always reports every field, so its windows are parsed as they are.
+    pub fn with_defaults(value: serde_json::Value) -> serde_json::Value {
+        fn merge(base: &mut serde_json::Value, value: serde_json::Value) {
+            match (base, value) {
+                (serde_json::Value::Object(base), serde_json::Value::Object(value)) => {
+                    for (key, value) in value {
+                        match base.get_mut(&key) {
+                            Some(x) => merge(x, value),
+                            None => {
+                                base.insert(key, value);
+                            }
+                        }
+                    }
+                }
+                (base, value) => *base = value,
+            }
+        }
+
+        let mut base = serde_json::to_value(HyprClient::default())
+            .expect("windows can always be written as JSON");
+        merge(&mut base, value);
+        base
+    }
+
+    pub fn geometry(&self) -> Geometry {
+        Geometry {
+            at: self.at,
+            size: self.size,
+        }
     }
 
-    pub fn match_rule(clients: &[HyprClient], data: &str, rule: &Rule) -> bool {
-        let mut client = None;
+    /// A key identifying the window by its class, title and workspace rather than its address, so
+    /// it stays the same for a window that is closed and opened again, e.g. across restarts of
+    /// the application. Two windows that agree on all three share the key.
+    pub fn window_key(&self) -> String {
+        format!(
+            "{}\x1f{}\x1f{}",
+            self.class, self.title, self.workspace.name
+        )
+    }
+
+    /// Match `rule` against the window at address `data`. A bell from a window that is not in
+    /// `clients` is an orphan, which only rules with `orphan = true` match. `clients` being empty
+    /// means they couldn't be fetched, so no rule matches then.
+    pub fn match_rule(
+        clients: &[HyprClient],
+        data: &str,
+        rule: &Rule,
+        state: &WindowState,
+    ) -> bool {
+        let Some(client) = HyprClient::find(clients, data) else {
+            trace!("client not found");
+            return rule.orphan == Some(true)
+                && !clients.is_empty()
+                && rule.matches_urgent(state.urgent)
+                && rule.matches_geometry(None, state.initial_geometry)
+                && rule.matches_monitor(None)
+                && rule.matches_active(state.active_window);
+        };
+        client.matches(clients, rule, state)
+    }
+
+    /// Find the window at address `data`, which is written without the `0x` prefix as in
+    /// Hyprland's events.
+    pub fn find<'a>(clients: &'a [HyprClient], data: &str) -> Option<&'a HyprClient> {
         for c in clients {
             if !c.address.starts_with("0x") {
                 trace!("Invalid address: {}", c.address);
                 continue;
             }
             if &c.address[2..] == data {
-                client = Some(c);
-                break;
+                return Some(c);
             }
         }
-        let Some(client) = client else {
-            trace!("client not found");
-            return false;
+        None
+    }
+
+    /// Match `rule` against this window. `clients` are all the windows, which some predicates look
+    /// at besides the window itself, and `state` is what is known about it besides them.
+    pub fn matches(&self, clients: &[HyprClient], rule: &Rule, state: &WindowState) -> bool {
+        self.matches_with(clients, rule, state, procfs::comm, |_| {})
+    }
+
+    /// Like [`HyprClient::matches`], but looks up executable names of processes with `comm` and
+    /// calls `evaluated` with the name of every predicate that is actually evaluated. Cheap
+    /// predicates are checked first, and matching stops at the first one that fails, so the
+    /// regular expressions are only run when they can still make a difference.
+    fn matches_with(
+        &self,
+        clients: &[HyprClient],
+        rule: &Rule,
+        state: &WindowState,
+        mut comm: impl FnMut(i32) -> Option<String>,
+        mut evaluated: impl FnMut(&str),
+    ) -> bool {
+        let mut client = self;
+
+        if rule.match_group_leader == Some(true)
+            && let Some(leader) = client.grouped.first()
+        {
+            match clients.iter().find(|x| &x.address == leader) {
+                Some(x) => client = x,
+                None => trace!("group leader {leader} not found"),
+            }
+        }
+        trace!("matching against {}", client.address);
+
+        let mut check = |name: &str, accumulator: bool| {
+            evaluated(name);
+            trace!("{name}: accumulator = {accumulator}");
+            accumulator
         };
 
-        let mut accumulator = true;
-        if let Some(ref workspace) = rule.workspace {
-            accumulator = accumulator
-                && match workspace {
-                    WorkspaceRule::Id(id) => &client.workspace.id == id,
-                    WorkspaceRule::Name(name) => &client.workspace.name == name,
-                };
+        // The window is known, so it's not an orphan.
+        if rule.orphan == Some(true) && !check("orphan", false) {
+            return false;
+        }
+
+        if let Some(ref workspace) = rule.workspace
+            && !check("workspace", workspace.matches(&client.workspace))
+        {
+            return false;
+        }
+
+        if let Some(special_workspace) = rule.special_workspace
+            && !check(
+                "special_workspace",
+                client.workspace.special_name().is_some() == special_workspace,
+            )
+        {
+            return false;
+        }
+
+        if let Some(ref special_workspace_name) = rule.special_workspace_name
+            && !check(
+                "special_workspace_name",
+                client.workspace.special_name() == Some(special_workspace_name),
+            )
+        {
+            return false;
+        }
+
+        if let Some(ref floating) = rule.floating
+            && !check("floating", &client.floating == floating)
+        {
+            return false;
+        }
+
+        if let Some(ref xwayland) = rule.xwayland
+            && !check("xwayland", &client.xwayland == xwayland)
+        {
+            return false;
+        }
+
+        if let Some(ref mapped) = rule.mapped
+            && !check("mapped", &client.mapped == mapped)
+        {
+            return false;
+        }
+
+        if let Some(ref hidden) = rule.hidden
+            && !check("hidden", &client.hidden == hidden)
+        {
+            return false;
+        }
+
+        // Hyprland reports "0x0" when nothing is swallowed.
+        if let Some(ref swallowing) = rule.swallowing
+            && !check("swallowing", &(client.swallowing != "0x0") == swallowing)
+        {
+            return false;
+        }
+
+        // Urgency, geometry and monitors are those of the window that rang, even when matching
+        // against its group leader.
+        if rule.urgent.is_some() && !check("urgent", rule.matches_urgent(state.urgent)) {
+            return false;
+        }
+
+        if (rule.moved.is_some() || rule.resized.is_some())
+            && !check(
+                "geometry",
+                rule.matches_geometry(Some(self.geometry()), state.initial_geometry),
+            )
+        {
+            return false;
+        }
+
+        if (rule.monitor_width.is_some()
+            || rule.monitor_height.is_some()
+            || rule.monitor_scale.is_some())
+            && !check(
+                "monitor",
+                rule.matches_monitor(
+                    state
+                        .monitors
+                        .and_then(|x| HyprMonitor::find(x, self.monitor)),
+                ),
+            )
+        {
+            return false;
+        }
+
+        if rule.min_aspect.is_some() || rule.max_aspect.is_some() {
+            let [width, height] = client.size;
+            trace!("size = {width}x{height}");
+            if !check(
+                "aspect",
+                height != 0 && {
+                    let aspect = width as f32 / height as f32;
+                    rule.min_aspect.is_none_or(|min| aspect >= min)
+                        && rule.max_aspect.is_none_or(|max| aspect <= max)
+                },
+            ) {
+                return false;
+            }
+        }
+
+        // Windows whose opacity isn't reported never match.
+        if rule.min_opacity.is_some() || rule.max_opacity.is_some() {
+            trace!("opacity = {:?}", client.opacity);
+            if !check(
+                "opacity",
+                client.opacity.is_some_and(|opacity| {
+                    rule.min_opacity.is_none_or(|min| opacity >= min)
+                        && rule.max_opacity.is_none_or(|max| opacity <= max)
+                }),
+            ) {
+                return false;
+            }
+        }
+
+        // Hyprland reports no members for windows that are not in a group, which count as a group
+        // of their own.
+        if rule.min_group_size.is_some() || rule.max_group_size.is_some() {
+            let size = client.grouped.len().max(1);
+            trace!("group size = {size}");
+            if !check(
+                "group_size",
+                rule.min_group_size.is_none_or(|min| size >= min)
+                    && rule.max_group_size.is_none_or(|max| size <= max),
+            ) {
+                return false;
+            }
+        }
+
+        if let Some(ref xdg_description_contains) = rule.xdg_description_contains
+            && !check(
+                "xdg_description_contains",
+                client
+                    .xdg_description
+                    .contains(xdg_description_contains.as_str()),
+            )
+        {
+            return false;
+        }
+
+        if let Some(ref field) = rule.field
+            && !check("field", rule.matches_field(client.raw.pointer(field)))
+        {
+            return false;
+        }
+
+        if let Some(ref class_regex) = rule.class_regex
+            && !check("class_regex", class_regex.is_match(&client.class))
+        {
+            return false;
+        }
+
+        if rule.active_class_regex.is_some()
+            && !check(
+                "active_class_regex",
+                rule.matches_active(state.active_window),
+            )
+        {
+            return false;
+        }
+
+        // Groups that were not resolved never match.
+        if rule.group.is_some()
+            && !check(
+                "group",
+                rule.group_classes
+                    .as_ref()
+                    .is_some_and(|x| x.is_match(&client.class)),
+            )
+        {
+            return false;
+        }
+
+        if let Some(ref title_regex) = rule.title_regex
+            && !check("title_regex", title_regex.is_match(&client.title))
+        {
+            return false;
+        }
+
+        if let Some(ref title_regex_all) = rule.title_regex_all
+            && !check(
+                "title_regex_all",
+                title_regex_all.iter().all(|x| x.is_match(&client.title)),
+            )
+        {
+            return false;
         }
-        trace!("workspace: accumulator = {accumulator}");
 
-        if let Some(ref floating) = rule.floating {
-            accumulator = accumulator && (&client.floating == floating)
+        if let Some(ref combined_regex) = rule.combined_regex
+            && !check(
+                "combined_regex",
+                combined_regex.is_match(&format!("{}|{}", client.class, client.title)),
+            )
+        {
+            return false;
         }
-        trace!("floating: accumulator = {accumulator}");
 
-        if let Some(ref xwayland) = rule.xwayland {
-            accumulator = accumulator && (&client.xwayland == xwayland)
+        // Special workspaces like scratchpads have IDs of their own, so they count as separate
+        // workspaces.
+        if let Some(ref solo_on_workspace) = rule.solo_on_workspace {
+            let count = clients
+                .iter()
+                .filter(|x| x.workspace.id == client.workspace.id)
+                .count();
+            trace!("windows on workspace {} = {count}", client.workspace.id);
+            if !check("solo_on_workspace", &(count <= 1) == solo_on_workspace) {
+                return false;
+            }
         }
-        trace!("xwayland: accumulator = {accumulator}");
 
-        if let Some(ref class_regex) = rule.class_regex {
-            accumulator = accumulator && class_regex.is_match(&client.class)
+        // Counting runs `class_regex` on every window, so it goes last.
+        if rule.min_window_count.is_some() || rule.max_window_count.is_some() {
+            let count = clients
+                .iter()
+                .filter(|x| {
+                    rule.class_regex
+                        .as_ref()
+                        .is_none_or(|r| r.is_match(&x.class))
+                })
+                .count();
+            trace!("window count = {count}");
+            if !check(
+                "window_count",
+                rule.min_window_count.is_none_or(|min| count >= min)
+                    && rule.max_window_count.is_none_or(|max| count <= max),
+            ) {
+                return false;
+            }
         }
-        trace!("class_regex: accumulator = {accumulator}");
 
-        if let Some(ref title_regex) = rule.title_regex {
-            accumulator = accumulator && title_regex.is_match(&client.title)
+        // Reading `/proc` is the most expensive check. A process that can't be looked up never
+        // matches.
+        if let Some(ref exe_regex) = rule.exe_regex
+            && !check(
+                "exe_regex",
+                comm(client.pid).is_some_and(|x| exe_regex.is_match(&x)),
+            )
+        {
+            return false;
         }
-        trace!("title_regex: accumulator = {accumulator}");
-        accumulator
+        true
     }
 }
 
@@ -111,7 +553,9 @@ mod test {
     use regex::Regex;
 
     use super::*;
+    use crate::config::WorkspaceRule;
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_clients_parse() {
         let client_source = r#"
         {
@@ -176,6 +620,100 @@ mod test {
         assert_eq!(client.content_type, "none");
     }
 
+    #[test]
+    fn test_partial_clients() {
+        let source = r#"[{ "address": "0x1", "class": "kitty", "workspace": { "name": "dev" } }]"#;
+        assert!(HyprClient::parse_clients(source).is_err());
+        let clients = HyprClient::parse_partial_clients(source).unwrap();
+        assert_eq!(clients[0].class, "kitty");
+        assert_eq!(clients[0].workspace.name, "dev");
+        assert_eq!(clients[0].workspace.id, 0);
+        assert!(!clients[0].floating);
+        assert_eq!(clients[0].raw["class"], "kitty");
+        assert!(clients[0].raw.get("floating").is_none());
+
+        assert!(HyprClient::parse_partial_clients(r#"[{ "class": 42 }]"#).is_err());
+        assert!(HyprClient::parse_partial_clients(r#"["kitty"]"#).is_err());
+    }
+    #[test]
+    fn test_active_window_parse() {
+        let active: HyprActiveWindow = serde_json::from_str(
+            r#"{
+                "address": "0x558e91924520",
+                "mapped": true,
+                "at": [9, 49],
+                "class": "kitty",
+                "title": "vim",
+                "focusHistoryID": 0
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            active,
+            HyprActiveWindow {
+                address: "0x558e91924520".into(),
+                class: "kitty".into(),
+                title: "vim".into(),
+            }
+        );
+        let active: HyprActiveWindow = serde_json::from_str("{}").unwrap();
+        assert_eq!(active, HyprActiveWindow::default());
+    }
+
+    #[test]
+    fn test_monitors_parse() {
+        let monitors: Vec<HyprMonitor> = serde_json::from_str(
+            r#"[{
+                "id": 0,
+                "name": "DP-1",
+                "description": "Dell Inc. DELL U2720Q",
+                "width": 3840,
+                "height": 2160,
+                "refreshRate": 59.99700,
+                "x": 0,
+                "y": 0,
+                "scale": 1.50,
+                "transform": 0,
+                "focused": true
+            }, {
+                "id": 1,
+                "name": "HDMI-A-1",
+                "width": 1920,
+                "height": 1080,
+                "scale": 1.00
+            }]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            monitors[0],
+            HyprMonitor {
+                id: 0,
+                name: "DP-1".into(),
+                width: 3840,
+                height: 2160,
+                scale: 1.5,
+            }
+        );
+        assert_eq!(HyprMonitor::find(&monitors, 1).unwrap().width, 1920);
+        assert!(HyprMonitor::find(&monitors, 2).is_none());
+    }
+
+    #[test]
+    fn test_parse_event() {
+        assert_eq!(
+            parse_event("bell>>558e91924520"),
+            Some(("bell", "558e91924520"))
+        );
+        assert_eq!(
+            parse_event("activewindow>>kitty,a>>b"),
+            Some(("activewindow", "kitty,a>>b"))
+        );
+        assert_eq!(parse_event("submap>>resize"), Some(("submap", "resize")));
+        // Leaving a submap reports the default one, whose name is empty.
+        assert_eq!(parse_event("submap>>"), Some(("submap", "")));
+        assert_eq!(parse_event("garbage"), None);
+    }
+
     #[test]
     fn match_rule() {
         // {{{ Huge Data
@@ -314,7 +852,8 @@ mod test {
                 class_regex: Some(Regex::new("^firefox$").unwrap()),
                 title_regex: Some(Regex::new("^rust.*").unwrap()),
                 ..Default::default()
-            }
+            },
+            &WindowState::default()
         ));
 
         assert!(!HyprClient::match_rule(
@@ -325,7 +864,8 @@ mod test {
                 class_regex: Some(Regex::new("^QQ$").unwrap()),
                 title_regex: Some(Regex::new("^rust.*").unwrap()),
                 ..Default::default()
-            }
+            },
+            &WindowState::default()
         ));
 
         assert!(!HyprClient::match_rule(
@@ -336,7 +876,8 @@ mod test {
                 class_regex: Some(Regex::new("^QQ$").unwrap()),
                 title_regex: Some(Regex::new("^rust.*").unwrap()),
                 ..Default::default()
-            }
+            },
+            &WindowState::default()
         ));
 
         assert!(HyprClient::match_rule(
@@ -349,7 +890,806 @@ mod test {
                 floating: Some(false),
                 xwayland: Some(false),
                 ..Default::default()
-            }
+            },
+            &WindowState::default()
+        ));
+
+        assert!(HyprClient::match_rule(
+            &clients,
+            "558e9243ab50",
+            &Rule {
+                title_regex_all: Some(vec![
+                    Regex::new("rust").unwrap(),
+                    Regex::new("検索").unwrap(),
+                    Regex::new("Firefox$").unwrap(),
+                ]),
+                ..Default::default()
+            },
+            &WindowState::default()
+        ));
+
+        assert!(!HyprClient::match_rule(
+            &clients,
+            "558e9243ab50",
+            &Rule {
+                title_regex_all: Some(vec![
+                    Regex::new("rust").unwrap(),
+                    Regex::new("python").unwrap(),
+                ]),
+                ..Default::default()
+            },
+            &WindowState::default()
+        ));
+
+        assert!(HyprClient::match_rule(
+            &clients,
+            "558e91924520",
+            &Rule {
+                swallowing: Some(false),
+                ..Default::default()
+            },
+            &WindowState::default()
+        ));
+        assert!(!HyprClient::match_rule(
+            &clients,
+            "558e91924520",
+            &Rule {
+                swallowing: Some(true),
+                ..Default::default()
+            },
+            &WindowState::default()
+        ));
+
+        let mut swallowing_clients = clients.clone();
+        swallowing_clients[1].swallowing = "0x558e9243ab50".into();
+        assert!(HyprClient::match_rule(
+            &swallowing_clients,
+            "558e91924520",
+            &Rule {
+                swallowing: Some(true),
+                ..Default::default()
+            },
+            &WindowState::default()
+        ));
+
+        let mut special_clients = clients.clone();
+        special_clients[2].workspace = HyprWorkspace {
+            id: -98,
+            name: "special:magic".into(),
+        };
+        for (workspace, expected) in [
+            (WorkspaceRule::Id(-98), true),
+            (WorkspaceRule::Name("special:magic".into()), true),
+            (WorkspaceRule::Name("magic".into()), false),
+            (WorkspaceRule::Id(98), false),
+            (WorkspaceRule::Id(3), false),
+        ] {
+            assert_eq!(
+                HyprClient::match_rule(
+                    &special_clients,
+                    "558e928c04d0",
+                    &Rule {
+                        workspace: Some(workspace.clone()),
+                        ..Default::default()
+                    },
+                    &WindowState::default()
+                ),
+                expected,
+                "workspace = {workspace:?}"
+            );
+        }
+
+        // A small square popup next to the 1582x942 (aspect ~1.68) windows.
+        let mut popup_clients = clients.clone();
+        popup_clients.push(HyprClient {
+            address: "0x558e93000000".into(),
+            class: "pavucontrol".into(),
+            floating: true,
+            size: [400, 400],
+            ..Default::default()
+        });
+        let square = Rule {
+            min_aspect: Some(0.8),
+            max_aspect: Some(1.25),
+            ..Default::default()
+        };
+        assert!(HyprClient::match_rule(
+            &popup_clients,
+            "558e93000000",
+            &square,
+            &WindowState::default()
+        ));
+        assert!(!HyprClient::match_rule(
+            &popup_clients,
+            "558e91924520",
+            &square,
+            &WindowState::default()
+        ));
+        let wide = Rule {
+            min_aspect: Some(1.5),
+            ..Default::default()
+        };
+        assert!(HyprClient::match_rule(
+            &popup_clients,
+            "558e91924520",
+            &wide,
+            &WindowState::default()
+        ));
+        assert!(!HyprClient::match_rule(
+            &popup_clients,
+            "558e93000000",
+            &wide,
+            &WindowState::default()
+        ));
+
+        // Zero height never matches, instead of dividing by zero.
+        popup_clients.last_mut().unwrap().size = [400, 0];
+        assert!(!HyprClient::match_rule(
+            &popup_clients,
+            "558e93000000",
+            &wide,
+            &WindowState::default()
+        ));
+        assert!(!HyprClient::match_rule(
+            &popup_clients,
+            "558e93000000",
+            &Rule {
+                max_aspect: Some(1.0),
+                ..Default::default()
+            },
+            &WindowState::default()
+        ));
+
+        let mut grouped_clients = clients.clone();
+        grouped_clients[3].grouped = vec!["0x558e91924520".into(), "0x558e9243ab50".into()];
+        grouped_clients[1].grouped = grouped_clients[3].grouped.clone();
+        let kitty_leader = Rule {
+            match_group_leader: Some(true),
+            class_regex: Some(Regex::new("^kitty$").unwrap()),
+            ..Default::default()
+        };
+        // The firefox window is matched as its group leader, kitty.
+        assert!(HyprClient::match_rule(
+            &grouped_clients,
+            "558e9243ab50",
+            &kitty_leader,
+            &WindowState::default()
+        ));
+        assert!(!HyprClient::match_rule(
+            &grouped_clients,
+            "558e9243ab50",
+            &Rule {
+                match_group_leader: None,
+                ..kitty_leader.clone()
+            },
+            &WindowState::default()
+        ));
+        // Ungrouped windows are matched as themselves.
+        assert!(HyprClient::match_rule(
+            &clients,
+            "558e91924520",
+            &kitty_leader,
+            &WindowState::default()
+        ));
+
+        let group_size = |min: Option<usize>, max: Option<usize>| Rule {
+            min_group_size: min,
+            max_group_size: max,
+            ..Default::default()
+        };
+        let sizes = |clients: &[HyprClient], rule: &Rule| {
+            clients
+                .iter()
+                .map(|x| {
+                    HyprClient::match_rule(clients, &x.address[2..], rule, &WindowState::default())
+                })
+                .collect::<Vec<_>>()
+        };
+        // Ungrouped windows are a group of 1.
+        assert_eq!(
+            sizes(&grouped_clients, &group_size(Some(2), None)),
+            [false, true, false, true]
+        );
+        assert_eq!(
+            sizes(&grouped_clients, &group_size(None, Some(1))),
+            [true, false, true, false]
+        );
+        let mut tabbed_clients = grouped_clients.clone();
+        tabbed_clients[0].grouped = vec![
+            "0x558e92a1b830".into(),
+            "0x558e928c04d0".into(),
+            "0x558e92ab0370".into(),
+        ];
+        tabbed_clients[2].grouped = tabbed_clients[0].grouped.clone();
+        assert_eq!(
+            sizes(&tabbed_clients, &group_size(Some(3), Some(3))),
+            [true, false, true, false]
+        );
+        assert_eq!(
+            sizes(&tabbed_clients, &group_size(Some(1), Some(2))),
+            [false, true, false, true]
+        );
+        assert!(
+            sizes(&clients, &group_size(Some(1), Some(1)))
+                .iter()
+                .all(|x| *x)
+        );
+        assert!(!HyprClient::match_rule(
+            &clients,
+            "558e9243ab50",
+            &kitty_leader,
+            &WindowState::default()
+        ));
+
+        for (pattern, expected) in [
+            (r"^firefox\|rust", true),
+            (r"fox\|rust test", true),
+            (r"^firefox$", false),
+            (r"^discord\|rust", false),
+        ] {
+            assert_eq!(
+                HyprClient::match_rule(
+                    &clients,
+                    "558e9243ab50",
+                    &Rule {
+                        combined_regex: Some(Regex::new(pattern).unwrap()),
+                        ..Default::default()
+                    },
+                    &WindowState::default()
+                ),
+                expected,
+                "combined_regex = {pattern:?}"
+            );
+        }
+
+        for (class_regex, min, max, expected) in [
+            (None, Some(4), None, true),
+            (None, Some(5), None, false),
+            (None, None, Some(4), true),
+            (None, None, Some(3), false),
+            (Some("^kitty$"), Some(1), Some(1), true),
+            (Some("^kitty$"), Some(2), None, false),
+            (Some("^(kitty|QQ)$"), Some(2), Some(2), true),
+            (Some("^(kitty|QQ)$"), None, Some(1), false),
+        ] {
+            assert_eq!(
+                HyprClient::match_rule(
+                    &clients,
+                    "558e91924520",
+                    &Rule {
+                        class_regex: class_regex.map(|x| Regex::new(x).unwrap()),
+                        min_window_count: min,
+                        max_window_count: max,
+                        ..Default::default()
+                    },
+                    &WindowState::default()
+                ),
+                expected,
+                "class_regex = {class_regex:?}, min = {min:?}, max = {max:?}"
+            );
+        }
+
+        for (mapped, hidden, expected) in [
+            (Some(true), None, true),
+            (Some(false), None, false),
+            (None, Some(false), true),
+            (None, Some(true), false),
+            (Some(true), Some(false), true),
+            (Some(true), Some(true), false),
+        ] {
+            assert_eq!(
+                HyprClient::match_rule(
+                    &clients,
+                    "558e92a1b830",
+                    &Rule {
+                        mapped,
+                        hidden,
+                        ..Default::default()
+                    },
+                    &WindowState::default()
+                ),
+                expected,
+                "mapped = {mapped:?}, hidden = {hidden:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_short_circuit() {
+        let client = HyprClient {
+            address: "0x1".into(),
+            class: "kitty".into(),
+            title: "tmux a".into(),
+            floating: true,
+            ..Default::default()
+        };
+        let clients = [client.clone()];
+        let rule = Rule {
+            floating: Some(false),
+            class_regex: Some(Regex::new("^kitty$").unwrap()),
+            title_regex: Some(Regex::new("tmux").unwrap()),
+            combined_regex: Some(Regex::new(r"kitty\|tmux").unwrap()),
+            min_window_count: Some(1),
+            ..Default::default()
+        };
+        let mut evaluated = Vec::new();
+        assert!(!client.matches_with(
+            &clients,
+            &rule,
+            &WindowState::default(),
+            |_| None,
+            |x| evaluated.push(x.to_string())
+        ));
+        assert_eq!(evaluated, ["floating"]);
+
+        // A failing regex stops the ones after it as well.
+        let rule = Rule {
+            floating: Some(true),
+            class_regex: Some(Regex::new("^firefox$").unwrap()),
+            ..rule
+        };
+        let mut evaluated = Vec::new();
+        assert!(!client.matches_with(
+            &clients,
+            &rule,
+            &WindowState::default(),
+            |_| None,
+            |x| evaluated.push(x.to_string())
+        ));
+        assert_eq!(evaluated, ["floating", "class_regex"]);
+
+        let rule = Rule {
+            class_regex: Some(Regex::new("^kitty$").unwrap()),
+            ..rule
+        };
+        let mut evaluated = Vec::new();
+        assert!(client.matches_with(
+            &clients,
+            &rule,
+            &WindowState::default(),
+            |_| None,
+            |x| evaluated.push(x.to_string())
+        ));
+        assert_eq!(
+            evaluated,
+            [
+                "floating",
+                "class_regex",
+                "title_regex",
+                "combined_regex",
+                "window_count"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_exe_regex() {
+        let client = HyprClient {
+            address: "0x1".into(),
+            class: "org.wezfurlong.wezterm".into(),
+            pid: 1234,
+            ..Default::default()
+        };
+        let clients = [client.clone()];
+        let comm = |pid| (pid == 1234).then(|| "wezterm-gui".to_string());
+        let rule = |pattern| Rule {
+            exe_regex: Some(Regex::new(pattern).unwrap()),
+            ..Default::default()
+        };
+        assert!(client.matches_with(
+            &clients,
+            &rule("^wezterm"),
+            &WindowState::default(),
+            comm,
+            |_| {}
+        ));
+        assert!(!client.matches_with(
+            &clients,
+            &rule("^kitty$"),
+            &WindowState::default(),
+            comm,
+            |_| {}
+        ));
+
+        // Unreadable processes don't match, not even an everything pattern.
+        let gone = HyprClient {
+            pid: 5678,
+            ..client
+        };
+        assert!(!gone.matches_with(&clients, &rule(""), &WindowState::default(), comm, |_| {}));
+    }
+
+    #[test]
+    fn test_xdg_description_contains() {
+        let client = HyprClient {
+            address: "0x1".into(),
+            xdg_description: "Éditeur de texte — 日本語".into(),
+            ..Default::default()
+        };
+        let clients = [client.clone()];
+        let rule = |text: &str| Rule {
+            xdg_description_contains: Some(text.into()),
+            ..Default::default()
+        };
+        assert!(client.matches(&clients, &rule("Éditeur"), &WindowState::default()));
+        assert!(client.matches(&clients, &rule("— 日本"), &WindowState::default()));
+        assert!(client.matches(&clients, &rule(""), &WindowState::default()));
+        assert!(!client.matches(&clients, &rule("éditeur"), &WindowState::default()));
+        assert!(!client.matches(&clients, &rule("中文"), &WindowState::default()));
+    }
+
+    #[test]
+    fn test_special_workspace() {
+        let clients = HyprClient::parse_partial_clients(
+            r#"[
+                { "address": "0x1", "workspace": { "id": 1, "name": "1" } },
+                { "address": "0x2", "workspace": { "id": 2, "name": "special" } },
+                { "address": "0x3", "workspace": { "id": -98, "name": "special:scratchpad" } },
+                { "address": "0x4", "workspace": { "id": -99, "name": "special:magic" } }
+            ]"#,
+        )
+        .unwrap();
+        let rule = |special: Option<bool>, name: Option<&str>| Rule {
+            special_workspace: special,
+            special_workspace_name: name.map(String::from),
+            ..Default::default()
+        };
+        let matches = |rule: &Rule| {
+            ["1", "2", "3", "4"].map(|address| {
+                HyprClient::match_rule(&clients, address, rule, &WindowState::default())
+            })
+        };
+        // A regular workspace may be called `special` too.
+        assert_eq!(matches(&rule(Some(true), None)), [false, false, true, true]);
+        assert_eq!(
+            matches(&rule(Some(false), None)),
+            [true, true, false, false]
+        );
+        assert_eq!(
+            matches(&rule(None, Some("magic"))),
+            [false, false, false, true]
+        );
+        assert_eq!(
+            matches(&rule(Some(true), Some("scratchpad"))),
+            [false, false, true, false]
+        );
+        assert_eq!(
+            matches(&rule(Some(false), Some("magic"))),
+            [false, false, false, false]
+        );
+    }
+
+    #[test]
+    fn test_solo_on_workspace() {
+        let clients = HyprClient::parse_partial_clients(
+            r#"[
+                { "address": "0x1", "workspace": { "id": 1, "name": "1" } },
+                { "address": "0x2", "workspace": { "id": 2, "name": "2" } },
+                { "address": "0x3", "workspace": { "id": 2, "name": "2" } },
+                { "address": "0x4", "workspace": { "id": -98, "name": "special:scratchpad" } },
+                { "address": "0x5", "workspace": { "id": -99, "name": "special:magic" } },
+                { "address": "0x6", "workspace": { "id": -99, "name": "special:magic" } }
+            ]"#,
+        )
+        .unwrap();
+        let solo = Rule {
+            solo_on_workspace: Some(true),
+            ..Default::default()
+        };
+        let not_solo = Rule {
+            solo_on_workspace: Some(false),
+            ..Default::default()
+        };
+        for (address, expected) in [
+            ("1", true),
+            ("2", false),
+            ("3", false),
+            ("4", true),
+            ("5", false),
+        ] {
+            assert_eq!(
+                HyprClient::match_rule(&clients, address, &solo, &WindowState::default()),
+                expected,
+                "address = {address}"
+            );
+            assert_eq!(
+                HyprClient::match_rule(&clients, address, &not_solo, &WindowState::default()),
+                !expected,
+                "address = {address}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_window_key() {
+        let clients = HyprClient::parse_partial_clients(
+            r#"[
+                { "address": "0x1", "class": "kitty", "title": "vim", "workspace": { "id": 1, "name": "1" } },
+                { "address": "0x2", "class": "kitty", "title": "vim", "workspace": { "id": 1, "name": "1" } },
+                { "address": "0x3", "class": "kitty", "title": "vim", "workspace": { "id": 2, "name": "2" } },
+                { "address": "0x4", "class": "kitty", "title": "htop", "workspace": { "id": 1, "name": "1" } },
+                { "address": "0x5", "class": "kitty vim", "title": "", "workspace": { "id": 1, "name": "1" } }
+            ]"#,
+        )
+        .unwrap();
+        let keys = clients
+            .iter()
+            .map(HyprClient::window_key)
+            .collect::<Vec<_>>();
+        // The address doesn't matter, but everything else does.
+        assert_eq!(keys[0], keys[1]);
+        assert_ne!(keys[0], keys[2]);
+        assert_ne!(keys[0], keys[3]);
+        assert_ne!(keys[0], keys[4]);
+        assert_eq!(keys[0], clients[0].clone().window_key());
+    }
+
+    #[test]
+    fn test_opacity() {
+        let clients = HyprClient::parse_partial_clients(
+            r#"[
+                { "address": "0x1", "opacity": 0.8 },
+                { "address": "0x2", "opacity": 1 },
+                { "address": "0x3" }
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(clients[0].opacity, Some(0.8));
+        assert_eq!(clients[1].opacity, Some(1.0));
+        assert_eq!(clients[2].opacity, None);
+
+        let rule = |min: Option<f32>, max: Option<f32>| Rule {
+            min_opacity: min,
+            max_opacity: max,
+            ..Default::default()
+        };
+        let translucent = rule(None, Some(0.9));
+        assert!(HyprClient::match_rule(
+            &clients,
+            "1",
+            &translucent,
+            &WindowState::default()
+        ));
+        assert!(!HyprClient::match_rule(
+            &clients,
+            "2",
+            &translucent,
+            &WindowState::default()
+        ));
+        assert!(!HyprClient::match_rule(
+            &clients,
+            "3",
+            &translucent,
+            &WindowState::default()
+        ));
+        let opaque = rule(Some(1.0), None);
+        assert!(!HyprClient::match_rule(
+            &clients,
+            "1",
+            &opaque,
+            &WindowState::default()
+        ));
+        assert!(HyprClient::match_rule(
+            &clients,
+            "2",
+            &opaque,
+            &WindowState::default()
+        ));
+        assert!(!HyprClient::match_rule(
+            &clients,
+            "3",
+            &opaque,
+            &WindowState::default()
+        ));
+
+        // Without opacity predicates, it doesn't matter whether it's reported.
+        assert!(HyprClient::match_rule(
+            &clients,
+            "3",
+            &rule(None, None),
+            &WindowState::default()
+        ));
+    }
+
+    #[test]
+    fn test_field() {
+        let clients = HyprClient::parse_partial_clients(
+            r#"[{
+                "address": "0x1",
+                "class": "kitty",
+                "workspace": { "id": 3, "name": "chat" },
+                "tags": ["urgent"],
+                "futureFlag": true
+            }]"#,
+        )
+        .unwrap();
+        let client = &clients[0];
+        assert_eq!(client.class, "kitty");
+        let rule = |field: &str, value: Option<serde_json::Value>, regex: Option<&str>| Rule {
+            field: Some(field.into()),
+            field_value: value,
+            field_regex: regex.map(|x| Regex::new(x).unwrap()),
+            ..Default::default()
+        };
+        assert!(client.matches(
+            &clients,
+            &rule("/futureFlag", None, None),
+            &WindowState::default()
+        ));
+        assert!(client.matches(
+            &clients,
+            &rule("/futureFlag", Some(true.into()), None),
+            &WindowState::default()
+        ));
+        assert!(!client.matches(
+            &clients,
+            &rule("/futureFlag", Some(false.into()), None),
+            &WindowState::default()
+        ));
+        assert!(client.matches(
+            &clients,
+            &rule("/workspace/name", None, Some("^ch")),
+            &WindowState::default()
+        ));
+        assert!(client.matches(
+            &clients,
+            &rule("/tags/0", Some("urgent".into()), None),
+            &WindowState::default()
+        ));
+        assert!(client.matches(
+            &clients,
+            &rule("/tags", None, Some(r#"^\["urgent"\]$"#)),
+            &WindowState::default()
+        ));
+        assert!(!client.matches(
+            &clients,
+            &rule("/tags", None, Some("^urgent$")),
+            &WindowState::default()
+        ));
+        assert!(!client.matches(
+            &clients,
+            &rule("/missing", None, None),
+            &WindowState::default()
+        ));
+        assert!(!client.matches(
+            &clients,
+            &rule("futureFlag", None, None),
+            &WindowState::default()
+        ));
+
+        // Windows that were not parsed from Hyprland's output have no fields to look at.
+        let client = HyprClient::default();
+        assert!(!client.matches(
+            &[],
+            &rule("/futureFlag", None, None),
+            &WindowState::default()
+        ));
+    }
+
+    #[test]
+    fn test_orphan() {
+        let clients = [HyprClient {
+            address: "0x1".into(),
+            class: "kitty".into(),
+            ..Default::default()
+        }];
+        let orphan = Rule {
+            orphan: Some(true),
+            ..Default::default()
+        };
+        let not_orphan = Rule {
+            orphan: Some(false),
+            ..Default::default()
+        };
+        assert!(HyprClient::match_rule(
+            &clients,
+            "2",
+            &orphan,
+            &WindowState::default()
+        ));
+        assert!(!HyprClient::match_rule(
+            &clients,
+            "1",
+            &orphan,
+            &WindowState::default()
+        ));
+        assert!(!HyprClient::match_rule(
+            &clients,
+            "2",
+            &not_orphan,
+            &WindowState::default()
+        ));
+        assert!(HyprClient::match_rule(
+            &clients,
+            "1",
+            &not_orphan,
+            &WindowState::default()
+        ));
+
+        // Without any clients, we can't tell whether the window exists.
+        assert!(!HyprClient::match_rule(
+            &[],
+            "2",
+            &orphan,
+            &WindowState::default()
+        ));
+    }
+
+    #[test]
+    fn test_window_state() {
+        let clients = [
+            HyprClient {
+                address: "0x1".into(),
+                class: "kitty".into(),
+                monitor: 1,
+                at: [10, 10],
+                grouped: vec!["0x2".into(), "0x1".into()],
+                ..Default::default()
+            },
+            HyprClient {
+                address: "0x2".into(),
+                class: "firefox".into(),
+                ..Default::default()
+            },
+        ];
+        let monitors = [HyprMonitor {
+            id: 1,
+            width: 3840,
+            ..Default::default()
+        }];
+        let active = HyprActiveWindow {
+            class: "firefox".into(),
+            ..Default::default()
+        };
+        let state = WindowState {
+            initial_geometry: Some(Geometry {
+                at: [0, 0],
+                size: [0, 0],
+            }),
+            urgent: true,
+            monitors: Some(&monitors),
+            active_window: Some(&active),
+        };
+        let rule = Rule {
+            urgent: Some(true),
+            moved: Some(true),
+            monitor_width: Some(3840),
+            active_class_regex: Some(Regex::new("^firefox$").unwrap()),
+            ..Default::default()
+        };
+        assert!(HyprClient::match_rule(&clients, "1", &rule, &state));
+        assert!(!HyprClient::match_rule(
+            &clients,
+            "1",
+            &rule,
+            &WindowState::default()
+        ));
+        let quiet = WindowState {
+            urgent: false,
+            ..state
+        };
+        assert!(!HyprClient::match_rule(&clients, "1", &rule, &quiet));
+
+        // The state belongs to the window that rang, not to its group leader, which is on
+        // another monitor and hasn't moved.
+        let leader = Rule {
+            match_group_leader: Some(true),
+            class_regex: Some(Regex::new("^firefox$").unwrap()),
+            ..rule
+        };
+        assert!(HyprClient::match_rule(&clients, "1", &leader, &state));
+
+        let mut evaluated = Vec::new();
+        assert!(clients[0].matches_with(
+            &clients,
+            &Rule {
+                urgent: Some(false),
+                ..Default::default()
+            },
+            &quiet,
+            |_| None,
+            |x| evaluated.push(x.to_string())
         ));
+        assert_eq!(evaluated, ["urgent"]);
     }
 }
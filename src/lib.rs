@@ -1,5 +1,32 @@
 pub mod app;
+pub mod backend;
+pub mod builtin;
+pub mod bundle;
+pub mod cache;
+pub mod cli;
+pub mod completions;
 pub mod config;
+pub mod control;
+pub mod cooldown;
+pub mod debounce;
+pub mod emit;
 pub mod error;
+pub mod escalation;
+pub mod explain;
+pub mod fatigue;
+pub mod first_bell;
+pub mod health;
 pub mod hypr;
+pub mod lint;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+pub mod playback;
+pub mod procfs;
+pub mod ratelimit;
+pub mod replay;
+pub mod schedule;
+pub mod snapshot;
+pub mod sound;
+pub mod state;
+pub mod stats;
 pub mod util;
@@ -0,0 +1,380 @@
+use std::collections::HashSet;
+use std::fmt::{self, Display, Formatter};
+use std::path::Path;
+
+use crate::builtin;
+use crate::bundle;
+use crate::config::Config;
+use crate::error::AppError;
+
+/// How bad a diagnostic is. Any error makes `onionbell lint` fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+/// A problem found in the config by one of the checks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    /// Name of the check that found it, which is also the category in the report.
+    pub check: &'static str,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn warning(check: &'static str, message: String) -> Diagnostic {
+        Diagnostic {
+            check,
+            severity: Severity::Warning,
+            message,
+        }
+    }
+
+    fn error(check: &'static str, message: String) -> Diagnostic {
+        Diagnostic {
+            check,
+            severity: Severity::Error,
+            message,
+        }
+    }
+}
+
+/// Checks that only look at the config itself, in the order they're reported.
+pub const CHECKS: [fn(&Config) -> Vec<Diagnostic>; 5] = [
+    suspicious_keys,
+    duplicate_rules,
+    unreachable_rules,
+    unanchored_regexes,
+    missing_files,
+];
+
+/// Run every check on `config`. `decode` reads and decodes a whole sound file, which is only
+/// tried on files that exist.
+pub fn lint(config: &Config, decode: impl Fn(&Path) -> Result<(), AppError>) -> Vec<Diagnostic> {
+    let mut diagnostics = CHECKS
+        .iter()
+        .flat_map(|check| check(config))
+        .collect::<Vec<_>>();
+    diagnostics.extend(undecodable_files(config, decode));
+    diagnostics
+}
+
+/// Keys that are ignored or contradict each other, as warned about when loading the config.
+pub fn suspicious_keys(config: &Config) -> Vec<Diagnostic> {
+    config
+        .key_warnings()
+        .into_iter()
+        .map(|x| Diagnostic::warning("suspicious-keys", x))
+        .collect()
+}
+
+/// Rules with the same conditions as another one.
+pub fn duplicate_rules(config: &Config) -> Vec<Diagnostic> {
+    config
+        .duplicate_rules()
+        .into_iter()
+        .map(|x| Diagnostic::warning("duplicate-rules", x))
+        .collect()
+}
+
+/// Rules that come after a rule without conditions, which matches every window first. Rules
+/// that duplicate it are left to `duplicate_rules`.
+pub fn unreachable_rules(config: &Config) -> Vec<Diagnostic> {
+    // Forced rules are checked first, like when resolving.
+    let is_forced = |i: &usize| config.rules[*i].force == Some(true);
+    let order = (0..config.rules.len())
+        .filter(is_forced)
+        .chain((0..config.rules.len()).filter(|x| !is_forced(x)));
+    let mut catch_all = None;
+    let mut diagnostics = Vec::new();
+    for i in order {
+        let rule = &config.rules[i];
+        match catch_all {
+            Some(j) if !rule.same_predicates(&config.rules[j]) => {
+                diagnostics.push(Diagnostic::warning(
+                    "unreachable-rules",
+                    format!(
+                        "Rule #{i} is checked after rule #{j}, which matches every window, so it \
+                        will never match"
+                    ),
+                ));
+            }
+            Some(_) => {}
+            // Rules that fire once per window let the next ones match afterwards.
+            None if rule.is_catch_all() && rule.once_per_window != Some(true) => {
+                catch_all = Some(i)
+            }
+            None => {}
+        }
+    }
+    diagnostics
+}
+
+/// Regexes without `^` and `$`, which match anywhere in the value, e.g. `kitty` matches
+/// `not-kitty` too.
+pub fn unanchored_regexes(config: &Config) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for (i, rule) in config.rules.iter().enumerate() {
+        for (key, regex) in rule.regexes() {
+            let pattern = regex.as_str();
+            if !pattern.starts_with('^') || !pattern.ends_with('$') {
+                diagnostics.push(Diagnostic::warning(
+                    "unanchored-regexes",
+                    format!(
+                        "Rule #{i} has `{key} = {pattern:?}`, which is not anchored with `^` and \
+                        `$`, so it matches anywhere in the value"
+                    ),
+                ));
+            }
+        }
+    }
+    diagnostics
+}
+
+/// Sounds that don't exist, including unknown builtin sounds and bundles whose archive doesn't
+/// exist. Missing entries of bundles are left to `undecodable_files`.
+pub fn missing_files(config: &Config) -> Vec<Diagnostic> {
+    let mut seen = HashSet::new();
+    config
+        .sound_paths()
+        .filter(|x| seen.insert(*x))
+        .filter(|x| match (builtin::name(x), bundle::split(x)) {
+            (Some(name), _) => builtin::get(name).is_none(),
+            (None, Some((archive, _))) => !archive.is_file(),
+            (None, None) => !x.exists(),
+        })
+        .map(|x| {
+            Diagnostic::error(
+                "missing-files",
+                format!("{} does not exist", x.to_string_lossy()),
+            )
+        })
+        .collect()
+}
+
+/// Sounds that exist but fail to decode with `decode`.
+pub fn undecodable_files(
+    config: &Config,
+    decode: impl Fn(&Path) -> Result<(), AppError>,
+) -> Vec<Diagnostic> {
+    let mut seen = HashSet::new();
+    config
+        .sound_paths()
+        .filter(|x| seen.insert(*x))
+        .filter(|x| match bundle::split(x) {
+            Some((archive, _)) => archive.is_file(),
+            None => builtin::name(x).is_none() && x.is_file(),
+        })
+        .filter_map(|x| {
+            let err = decode(x).err()?;
+            Some(Diagnostic::error(
+                "undecodable-files",
+                format!("{}: {}", x.to_string_lossy(), err),
+            ))
+        })
+        .collect()
+}
+
+/// A report of `diagnostics` grouped by check, ending with a summary line.
+pub fn report(diagnostics: &[Diagnostic]) -> String {
+    let mut report = String::new();
+    let mut checks = Vec::new();
+    for diagnostic in diagnostics {
+        if !checks.contains(&diagnostic.check) {
+            checks.push(diagnostic.check);
+        }
+    }
+    for check in checks {
+        report += &format!("{check}:\n");
+        for diagnostic in diagnostics.iter().filter(|x| x.check == check) {
+            report += &format!("  {}: {}\n", diagnostic.severity, diagnostic.message);
+        }
+    }
+    let count = |severity| {
+        diagnostics
+            .iter()
+            .filter(|x| x.severity == severity)
+            .count()
+    };
+    report += &format!(
+        "{} errors, {} warnings\n",
+        count(Severity::Error),
+        count(Severity::Warning)
+    );
+    report
+}
+
+/// Whether any of `diagnostics` is an error.
+pub fn has_errors(diagnostics: &[Diagnostic]) -> bool {
+    diagnostics.iter().any(|x| x.severity == Severity::Error)
+}
+
+#[allow(unused)]
+mod test {
+    use super::*;
+
+    fn messages(diagnostics: Vec<Diagnostic>) -> Vec<String> {
+        diagnostics.into_iter().map(|x| x.message).collect()
+    }
+
+    #[test]
+    fn test_suspicious_keys() {
+        let config = Config::from_source(
+            r#"
+            [[rule]]
+            sound = "/tmp/a.wav"
+            sounds = ["/tmp/b.wav"]
+            "#,
+        )
+        .unwrap();
+        let diagnostics = suspicious_keys(&config);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+    }
+
+    #[test]
+    fn test_duplicate_rules() {
+        let config = Config::from_source(
+            r#"
+            [[rule]]
+            class_regex = "^kitty$"
+
+            [[rule]]
+            class_regex = "^kitty$"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            messages(duplicate_rules(&config)),
+            vec!["Rule #1 has the same conditions as rule #0, so it will never match"]
+        );
+    }
+
+    #[test]
+    fn test_unreachable_rules() {
+        let config = Config::from_source(
+            r#"
+            [[rule]]
+            class_regex = "^foot$"
+
+            [[rule]]
+            sound = "/tmp/a.wav"
+
+            [[rule]]
+            class_regex = "^kitty$"
+
+            [[rule]]
+            sound = "/tmp/b.wav"
+
+            [[rule]]
+            class_regex = "^alacritty$"
+            force = true
+            "#,
+        )
+        .unwrap();
+        // The duplicate catch-all is left to duplicate_rules, and forced rules come first.
+        assert_eq!(
+            messages(unreachable_rules(&config)),
+            vec![
+                "Rule #2 is checked after rule #1, which matches every window, so it will never match"
+            ]
+        );
+
+        let config = Config::from_source(
+            r#"
+            [[rule]]
+            once_per_window = true
+
+            [[rule]]
+            class_regex = "^kitty$"
+            "#,
+        )
+        .unwrap();
+        assert!(unreachable_rules(&config).is_empty());
+    }
+
+    #[test]
+    fn test_unanchored_regexes() {
+        let config = Config::from_source(
+            r#"
+            [[rule]]
+            class_regex = "^kitty$"
+            title_regex_all = ["^vim", "make$"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            messages(unanchored_regexes(&config)),
+            vec![
+                r#"Rule #0 has `title_regex_all = "^vim"`, which is not anchored with `^` and `$`, so it matches anywhere in the value"#,
+                r#"Rule #0 has `title_regex_all = "make$"`, which is not anchored with `^` and `$`, so it matches anywhere in the value"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_missing_files() {
+        let config = Config::from_source(
+            r#"
+            sound = "/nonexistent/bell.wav"
+
+            [[rule]]
+            sounds = ["builtin:ding", "builtin:nope", "/nonexistent/bell.wav"]
+            "#,
+        )
+        .unwrap();
+        let diagnostics = missing_files(&config);
+        assert!(has_errors(&diagnostics));
+        assert_eq!(
+            messages(diagnostics),
+            vec![
+                "/nonexistent/bell.wav does not exist",
+                "builtin:nope does not exist"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_undecodable_files() {
+        let path = std::env::temp_dir().join("onionbell-test-lint.wav");
+        std::fs::write(&path, b"not a sound").unwrap();
+        let config = Config::from_source(format!(
+            "sound = {:?}\n[[rule]]\nsound = \"builtin:ding\"",
+            path.to_str().unwrap()
+        ))
+        .unwrap();
+        let diagnostics = undecodable_files(&config, |x| {
+            Err(AppError::SoundIsDirectory(x.to_path_buf()))
+        });
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].check, "undecodable-files");
+        assert!(undecodable_files(&config, |_| Ok(())).is_empty());
+    }
+
+    #[test]
+    fn test_report() {
+        let diagnostics = vec![
+            Diagnostic::warning("duplicate-rules", "a".to_string()),
+            Diagnostic::error("missing-files", "b".to_string()),
+            Diagnostic::warning("duplicate-rules", "c".to_string()),
+        ];
+        assert_eq!(
+            report(&diagnostics),
+            "duplicate-rules:\n  warning: a\n  warning: c\nmissing-files:\n  error: b\n1 errors, 2 warnings\n"
+        );
+        assert!(!has_errors(&diagnostics[..1]));
+        assert_eq!(report(&[]), "0 errors, 0 warnings\n");
+    }
+}
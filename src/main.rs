@@ -1,7 +1,11 @@
+use std::env;
 use std::process::ExitCode;
 
 use log::error;
 use onionbell::app::App;
+use onionbell::cli::Args;
+use onionbell::completions;
+use onionbell::control;
 
 fn main() -> Result<(), ExitCode> {
     #[cfg(debug_assertions)]
@@ -14,7 +18,100 @@ fn main() -> Result<(), ExitCode> {
         env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
     }
 
-    let app = App::new();
+    let args = match Args::parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            error!("{}", e);
+            return Err(ExitCode::FAILURE);
+        }
+    };
+
+    if let Some(shell) = args.completions {
+        print!("{}", completions::generate(shell));
+        return Ok(());
+    }
+
+    if let Some(command) = args.ctl {
+        return match control::socket_path().and_then(|path| control::send(&path, &command)) {
+            Ok(reply) => {
+                print!("{}", reply);
+                Ok(())
+            }
+            Err(e) => {
+                error!("{}", e);
+                Err(ExitCode::FAILURE)
+            }
+        };
+    }
+
+    if args.dump_effective {
+        return match App::dump_effective(&args) {
+            Ok(config) => {
+                print!("{}", config);
+                Ok(())
+            }
+            Err(e) => {
+                error!("Failed to dump config: {}", e);
+                Err(ExitCode::FAILURE)
+            }
+        };
+    }
+
+    if args.validate_sounds {
+        return match App::validate_sounds(&args) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(ExitCode::FAILURE),
+            Err(e) => {
+                error!("Failed to validate sounds: {}", e);
+                Err(ExitCode::FAILURE)
+            }
+        };
+    }
+
+    if args.lint {
+        return match App::lint(&args) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(ExitCode::FAILURE),
+            Err(e) => {
+                error!("Failed to lint config: {}", e);
+                Err(ExitCode::FAILURE)
+            }
+        };
+    }
+
+    if args.audition {
+        if let Err(e) = App::audition(&args) {
+            error!("Audition failed: {}", e);
+            return Err(ExitCode::FAILURE);
+        }
+        return Ok(());
+    }
+
+    if let Some(ref path) = args.replay {
+        if let Err(e) = App::replay(&args, path) {
+            error!("Replay failed: {}", e);
+            return Err(ExitCode::FAILURE);
+        }
+        return Ok(());
+    }
+
+    if args.explain {
+        if let Err(e) = App::explain(&args) {
+            error!("Explain failed: {}", e);
+            return Err(ExitCode::FAILURE);
+        }
+        return Ok(());
+    }
+
+    if args.tail {
+        if let Err(e) = App::tail(&args) {
+            error!("Tail failed: {}", e);
+            return Err(ExitCode::FAILURE);
+        }
+        return Ok(());
+    }
+
+    let app = App::new(&args);
     let Ok(mut app) = app else {
         if let Err(e) = app {
             error!("Application initialization failed: {}", e);
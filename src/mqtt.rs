@@ -0,0 +1,220 @@
+use std::thread;
+use std::time::Duration;
+
+use log::{debug, trace, warn};
+use rumqttc::{Client, MqttOptions, QoS};
+
+use crate::config::{Mqtt, Rule};
+use crate::hypr::HyprClient;
+
+/// How many messages may wait for the publisher before new ones are dropped.
+pub const QUEUE_CAPACITY: usize = 16;
+
+/// How often the broker is pinged while no bells are published.
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+
+/// How long to wait before connecting to the broker again after it failed.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Expand the placeholders of the `topic` template. Characters that have a meaning in MQTT topics
+/// are replaced with `_` in the values, so a window can't add topic levels or wildcards.
+pub fn topic(
+    template: &str,
+    client: Option<&HyprClient>,
+    rule_index: Option<usize>,
+    rule: Option<&Rule>,
+) -> String {
+    let (class, address, workspace) = match client {
+        Some(x) => (&*x.class, &*x.address, &*x.workspace.name),
+        None => ("", "", ""),
+    };
+    let rule = match (rule.and_then(|x| x.name.as_deref()), rule_index) {
+        (Some(name), _) => name.to_string(),
+        (None, Some(i)) => i.to_string(),
+        (None, None) => "none".to_string(),
+    };
+    let level = |x: &str| x.replace(['/', '+', '#'], "_");
+    template
+        .replace("{class}", &level(class))
+        .replace("{address}", &level(address))
+        .replace("{workspace}", &level(workspace))
+        .replace("{rule}", &level(&rule))
+}
+
+/// The options to connect to the broker of `config` with.
+fn options(config: &Mqtt) -> MqttOptions {
+    let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+    options.set_keep_alive(KEEP_ALIVE);
+    if let Some(ref username) = config.username {
+        options.set_credentials(username, config.password.as_deref().unwrap_or_default());
+    }
+    options
+}
+
+/// Publishes messages over a connection kept open in the background, so a slow broker never
+/// stalls the event loop. It reconnects when the connection is lost.
+pub struct Publisher {
+    config: Mqtt,
+    client: Client,
+}
+
+impl Publisher {
+    pub fn spawn(config: Mqtt) -> Publisher {
+        let (client, mut connection) = Client::new(options(&config), QUEUE_CAPACITY);
+        let broker = format!("{}:{}", config.host, config.port);
+        thread::spawn(move || {
+            let mut connected = true;
+            for notification in connection.iter() {
+                match notification {
+                    Ok(event) => {
+                        connected = true;
+                        trace!("MQTT: {event:?}");
+                    }
+                    Err(err) => {
+                        // Only the first failure in a row is worth a warning.
+                        if connected {
+                            warn!("Failed to connect to the MQTT broker {broker}: {err}");
+                        } else {
+                            debug!("Failed to connect to the MQTT broker {broker}: {err}");
+                        }
+                        connected = false;
+                        thread::sleep(RECONNECT_DELAY);
+                    }
+                }
+            }
+        });
+        Publisher { config, client }
+    }
+
+    /// The topic to publish a bell to, see [`topic`].
+    pub fn topic(
+        &self,
+        client: Option<&HyprClient>,
+        rule_index: Option<usize>,
+        rule: Option<&Rule>,
+    ) -> String {
+        topic(&self.config.topic, client, rule_index, rule)
+    }
+
+    /// Queue `payload` for `topic` at QoS 0 without blocking. It's dropped if the queue is full.
+    pub fn publish(&self, topic: String, payload: Vec<u8>) {
+        if let Err(err) = self
+            .client
+            .try_publish(&topic, QoS::AtMostOnce, false, payload)
+        {
+            warn!("Dropping a message to MQTT topic {topic}: {err}");
+        }
+    }
+}
+
+#[allow(unused)]
+mod test {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    use super::*;
+
+    fn config() -> Mqtt {
+        Mqtt {
+            host: "127.0.0.1".into(),
+            port: 1883,
+            topic: "onionbell/{class}/{rule}".into(),
+            client_id: "onionbell".into(),
+            username: None,
+            password: None,
+        }
+    }
+
+    #[test]
+    fn test_topic() {
+        let clients = HyprClient::parse_partial_clients(
+            r#"[{ "address": "0x558e91924520", "class": "org/foo+bar#", "workspace": { "id": 1, "name": "1" } }]"#,
+        )
+        .unwrap();
+        let rule = Rule {
+            name: Some("terminals".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            topic(
+                "desktop/{workspace}/{class}/{rule}",
+                clients.first(),
+                Some(2),
+                Some(&rule)
+            ),
+            "desktop/1/org_foo_bar_/terminals"
+        );
+        assert_eq!(
+            topic("{address}/{rule}", clients.first(), Some(2), None),
+            "0x558e91924520/2"
+        );
+        assert_eq!(topic("bell/{class}/{rule}", None, None, None), "bell//none");
+    }
+
+    /// Read a packet from `stream`, returning its first byte and its body.
+    fn read_packet(stream: &mut impl Read) -> (u8, Vec<u8>) {
+        let mut kind = [0];
+        stream.read_exact(&mut kind).unwrap();
+        let (mut len, mut shift) = (0, 0);
+        loop {
+            let mut byte = [0];
+            stream.read_exact(&mut byte).unwrap();
+            len |= usize::from(byte[0] & 0x7f) << shift;
+            shift += 7;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0; len];
+        stream.read_exact(&mut body).unwrap();
+        (kind[0], body)
+    }
+
+    #[test]
+    fn test_options() {
+        let options = options(&config());
+        assert_eq!(options.credentials(), None);
+        assert_eq!(options.keep_alive(), KEEP_ALIVE);
+
+        // A password alone can't be sent, so it's only used with a username.
+        let options = super::options(&Mqtt {
+            password: Some("pw".into()),
+            ..config()
+        });
+        assert_eq!(options.credentials(), None);
+        let options = super::options(&Mqtt {
+            username: Some("user".into()),
+            password: Some("pw".into()),
+            ..config()
+        });
+        assert_eq!(
+            options.credentials(),
+            Some(("user".to_string(), "pw".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_publish() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = Mqtt {
+            port: listener.local_addr().unwrap().port(),
+            ..config()
+        };
+        let publisher = Publisher::spawn(config);
+        publisher.publish("a/b".into(), b"{}".to_vec());
+        publisher.publish("a/c".into(), b"[]".to_vec());
+
+        // Both messages are published over the same connection.
+        let (mut stream, _) = listener.accept().unwrap();
+        assert_eq!(read_packet(&mut stream).0, 0x10);
+        stream.write_all(&[0x20, 2, 0, 0]).unwrap();
+        assert_eq!(
+            read_packet(&mut stream),
+            (0x30, [&[0, 3][..], b"a/b", b"{}"].concat())
+        );
+        assert_eq!(
+            read_packet(&mut stream),
+            (0x30, [&[0, 3][..], b"a/c", b"[]"].concat())
+        );
+    }
+}
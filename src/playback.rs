@@ -0,0 +1,586 @@
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use log::{debug, trace, warn};
+use rodio::decoder::DecoderError;
+use rodio::mixer::Mixer;
+use rodio::{ChannelCount, OutputStream, Sample, SampleRate, Source};
+
+use crate::backend;
+use crate::sound::{self, Fade, FadeHandle, Sound};
+use crate::stats::Stats;
+
+/// How many play requests may wait for the audio worker before new ones are dropped.
+pub const QUEUE_CAPACITY: usize = 16;
+
+/// How long to wait before trying a sound that failed with an I/O error again.
+const RETRY_DELAY: Duration = Duration::from_millis(50);
+
+/// Asks the audio worker to play a sound.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayRequest {
+    /// Path of the sound, only used in logs.
+    pub path: PathBuf,
+    pub sound: Sound,
+    pub volume: f32,
+
+    /// See `sample_rate` in the config.
+    pub sample_rate: Option<SampleRate>,
+
+    /// See `interrupt` in the config.
+    pub interrupt: bool,
+
+    /// See `crossfade_ms` in the config.
+    pub crossfade: Duration,
+
+    /// See `prelude_ms` in the config.
+    pub prelude: Duration,
+
+    /// See `downmix_mono` in the config.
+    pub downmix_mono: bool,
+
+    /// See `play_retries` in the config.
+    pub retries: u32,
+
+    /// The output devices to play on at once instead of the default one, see `devices` and
+    /// `monitor_devices` in the config.
+    pub devices: Vec<String>,
+}
+
+/// Where the app sends the sounds to play. The audio worker plays them on the output device, and
+/// tests can record them instead.
+pub trait AudioSink {
+    /// Play `request`, or drop it. Returns whether it was accepted.
+    fn play(&self, request: PlayRequest) -> bool;
+
+    /// Try sounds and devices that failed before again, e.g. after sounds are reloaded or a
+    /// monitor is plugged in.
+    fn reset(&self);
+
+    /// Whether requests are still played, rather than dropped because the worker is gone.
+    fn is_running(&self) -> bool;
+}
+
+/// What the audio worker is asked to do.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Job {
+    Play(PlayRequest),
+
+    /// See [`AudioSink::reset`].
+    Reset,
+}
+
+/// A thread that handles play requests in order, so building sources never stalls the event loop.
+pub struct AudioWorker {
+    sender: SyncSender<Job>,
+    thread: JoinHandle<()>,
+}
+
+impl AudioWorker {
+    /// Start a worker that passes every job to `handler`. At most `capacity` jobs wait in the
+    /// queue.
+    pub fn spawn(capacity: usize, mut handler: impl FnMut(Job) + Send + 'static) -> Self {
+        let (sender, receiver) = mpsc::sync_channel::<Job>(capacity);
+        let thread = thread::spawn(move || {
+            for job in receiver {
+                handler(job);
+            }
+        });
+        AudioWorker { sender, thread }
+    }
+
+    /// Queue `request` without blocking. Returns `false` if it's dropped because the queue is
+    /// full or the worker is gone.
+    pub fn play(&self, request: PlayRequest) -> bool {
+        let (state, job) = match self.sender.try_send(Job::Play(request)) {
+            Ok(()) => return true,
+            Err(TrySendError::Full(job)) => ("busy", job),
+            Err(TrySendError::Disconnected(job)) => ("gone", job),
+        };
+        if let Job::Play(request) = job {
+            warn!(
+                "Audio worker is {state}, dropping {}",
+                request.path.to_string_lossy()
+            );
+        }
+        false
+    }
+
+    /// Queue a reset. It waits for a free place in the queue, since it's rare and dropping it
+    /// would leave sounds skipped until the next one.
+    pub fn reset(&self) {
+        if self.sender.send(Job::Reset).is_err() {
+            warn!("Audio worker is gone, not resetting it");
+        }
+    }
+}
+
+/// Whether `err` may go away by trying again, like a file that's briefly unavailable, rather than
+/// a sound that can't be decoded.
+fn is_transient(err: &DecoderError) -> bool {
+    matches!(err, DecoderError::IoError(_))
+}
+
+/// Whether playing `sound` may work the next time after it failed with `err`. Only sounds read
+/// from somewhere else every time may, since sounds in memory fail the same way every time, even
+/// with I/O errors like the end of a truncated file.
+fn may_recover(sound: &Sound, err: &DecoderError) -> bool {
+    match sound {
+        Sound::Lazy(_) => is_transient(err),
+        Sound::Command(_) => true,
+        Sound::Encoded(_) | Sound::Decoded(_) | Sound::Beeps(_) => false,
+    }
+}
+
+/// Call `attempt` until it succeeds, trying again up to `retries` times `delay` apart when it
+/// fails with a transient error.
+fn retry<T>(
+    retries: u32,
+    delay: Duration,
+    mut attempt: impl FnMut() -> Result<T, DecoderError>,
+) -> Result<T, DecoderError> {
+    let mut tries = 0;
+    loop {
+        match attempt() {
+            Err(err) if tries < retries && is_transient(&err) => {
+                tries += 1;
+                debug!("Trying again after a transient error: {}", err);
+                thread::sleep(delay);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Reports the whole source as a single span. Some decoders, e.g. the one of Ogg Vorbis whose
+/// first packet has no audio, report an empty first span, which makes rodio's converters like
+/// the mixer of the [`Player`] end the source right away. The format of a file doesn't change
+/// midway, so a single span is fine.
+struct SingleSpan<S>(S);
+
+impl<S: Source> Iterator for SingleSpan<S> {
+    type Item = Sample;
+
+    fn next(&mut self) -> Option<Sample> {
+        self.0.next()
+    }
+}
+
+impl<S: Source> Source for SingleSpan<S> {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> ChannelCount {
+        self.0.channels()
+    }
+
+    fn sample_rate(&self) -> SampleRate {
+        self.0.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.0.total_duration()
+    }
+}
+
+/// Plays requests on the output mixer. This is what the audio worker of the app runs.
+pub struct Player {
+    mixer: Mixer,
+    stats: Arc<Mutex<Stats>>,
+
+    /// The sound that's playing, for `interrupt` to stop it.
+    playing: Option<FadeHandle>,
+
+    /// Sounds that failed to play, which are not tried again.
+    failed: HashSet<PathBuf>,
+
+    /// The audio host to open `devices` on, see `backend` in the config.
+    backend: Option<String>,
+
+    /// The devices requested so far, opened on first use.
+    devices: HashMap<String, Device>,
+
+    /// Devices that failed to open, which are not tried again until the player is reset.
+    failed_devices: HashSet<String>,
+}
+
+/// An output device opened by the [`Player`].
+struct Device {
+    mixer: Mixer,
+
+    /// The stream playing what's added to `mixer`, which stops when it's dropped. It's `None` in
+    /// tests.
+    _stream: Option<OutputStream>,
+}
+
+impl Player {
+    pub fn new(mixer: Mixer, stats: Arc<Mutex<Stats>>, backend: Option<String>) -> Player {
+        Player {
+            mixer,
+            stats,
+            playing: None,
+            failed: HashSet::new(),
+            backend,
+            devices: HashMap::new(),
+            failed_devices: HashSet::new(),
+        }
+    }
+
+    /// Handle a job of the audio worker.
+    pub fn run(&mut self, job: Job) {
+        match job {
+            Job::Play(request) => self.play(request),
+            Job::Reset => {
+                self.failed.clear();
+                self.failed_devices.clear();
+            }
+        }
+    }
+
+    pub fn play(&mut self, request: PlayRequest) {
+        if self.failed.contains(&request.path) {
+            trace!("skipping {}", request.path.to_string_lossy());
+            return;
+        }
+        let start = Instant::now();
+        // Running a command again may have side effects, so they're only tried once, and sounds in
+        // memory fail the same way every time.
+        let retries = match request.sound {
+            Sound::Lazy(_) => request.retries,
+            _ => 0,
+        };
+        let audio = retry(retries, RETRY_DELAY, || request.sound.source());
+        self.record(|stats| stats.decode.record(start.elapsed()));
+        match audio {
+            Ok(audio) => {
+                let audio = sound::downmix(Box::new(SingleSpan(audio)), request.downmix_mono);
+                let audio = sound::resample(audio, request.sample_rate);
+                let audio = self.interrupt(audio, &request);
+                // The silence goes before the fade-in, so the whole fade is heard.
+                let audio = sound::prelude(audio, request.prelude);
+                let mixers = self.mixers(&request.devices);
+                let start = Instant::now();
+                let audio = audio.amplify_normalized(request.volume);
+                if let [mixer] = mixers.as_slice() {
+                    mixer.add(audio);
+                } else {
+                    // Every device plays the same samples, decoded once.
+                    let audio = audio.buffered();
+                    for mixer in &mixers {
+                        mixer.add(audio.clone());
+                    }
+                }
+                self.record(|stats| stats.enqueue.record(start.elapsed()));
+            }
+            Err(err) => {
+                warn!(
+                    "Failed to play audio {}: {}",
+                    request.path.to_string_lossy(),
+                    err
+                );
+                if !may_recover(&request.sound, &err) {
+                    self.failed.insert(request.path);
+                }
+            }
+        }
+    }
+
+    /// The mixers of `devices`, or of the default output without any. Devices that fail to open
+    /// are skipped, which is reported the first time, and when none of them opens, the default
+    /// output is used.
+    fn mixers(&mut self, devices: &[String]) -> Vec<Mixer> {
+        let mut mixers = Vec::new();
+        for device in devices {
+            if !self.devices.contains_key(device) && !self.failed_devices.contains(device) {
+                match backend::open_device_stream(self.backend.as_deref(), device) {
+                    Ok(stream) => {
+                        debug!("Opened audio device {device}");
+                        let opened = Device {
+                            mixer: stream.mixer().clone(),
+                            _stream: Some(stream),
+                        };
+                        self.devices.insert(device.to_string(), opened);
+                    }
+                    Err(err) => {
+                        if devices.len() > 1 {
+                            warn!("{err}, playing on the other devices instead");
+                        } else {
+                            warn!("{err}, playing on the default output instead");
+                        }
+                        self.failed_devices.insert(device.to_string());
+                    }
+                }
+            }
+            if let Some(device) = self.devices.get(device) {
+                mixers.push(device.mixer.clone());
+            }
+        }
+        if mixers.is_empty() {
+            mixers.push(self.mixer.clone());
+        }
+        mixers
+    }
+
+    fn record(&self, f: impl FnOnce(&mut Stats)) {
+        f(&mut self.stats.lock().unwrap_or_else(|x| x.into_inner()));
+    }
+
+    /// With `interrupt` enabled, fade out the sound that's still playing, and fade `audio` in
+    /// over the same time so they cross-fade.
+    fn interrupt(
+        &mut self,
+        audio: Box<dyn Source + Send>,
+        request: &PlayRequest,
+    ) -> Box<dyn Source + Send> {
+        if !request.interrupt {
+            return audio;
+        }
+        let fade_in = match self.playing.take() {
+            Some(playing) if !playing.is_finished() => {
+                trace!("interrupting the playing sound");
+                playing.fade_out();
+                request.crossfade
+            }
+            _ => Duration::ZERO,
+        };
+        let (audio, handle) = Fade::new(audio, fade_in, request.crossfade);
+        self.playing = Some(handle);
+        Box::new(audio)
+    }
+}
+
+impl AudioSink for AudioWorker {
+    fn play(&self, request: PlayRequest) -> bool {
+        AudioWorker::play(self, request)
+    }
+
+    fn reset(&self) {
+        AudioWorker::reset(self)
+    }
+
+    fn is_running(&self) -> bool {
+        !self.thread.is_finished()
+    }
+}
+
+#[allow(unused)]
+mod test {
+    use super::*;
+    use crate::builtin;
+
+    fn request(path: &str) -> PlayRequest {
+        PlayRequest {
+            path: PathBuf::from(path),
+            sound: Sound::Encoded(Vec::new()),
+            volume: 1.0,
+            sample_rate: None,
+            interrupt: false,
+            crossfade: Duration::ZERO,
+            prelude: Duration::ZERO,
+            downmix_mono: false,
+            retries: 0,
+            devices: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_worker() {
+        let (sender, receiver) = mpsc::channel();
+        let worker = AudioWorker::spawn(QUEUE_CAPACITY, move |x| sender.send(x).unwrap());
+        assert!(worker.play(request("/tmp/a.wav")));
+        assert!(worker.play(request("/tmp/b.wav")));
+        worker.reset();
+        assert_eq!(receiver.recv().unwrap(), Job::Play(request("/tmp/a.wav")));
+        assert_eq!(receiver.recv().unwrap(), Job::Play(request("/tmp/b.wav")));
+        assert_eq!(receiver.recv().unwrap(), Job::Reset);
+    }
+
+    #[test]
+    fn test_worker_stopped() {
+        let worker = AudioWorker::spawn(QUEUE_CAPACITY, |_| panic!("the handler crashed"));
+        assert!(worker.is_running());
+        assert!(worker.play(request("/tmp/a.wav")));
+        for _ in 0..1000 {
+            if !worker.is_running() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(!worker.is_running());
+        assert!(!worker.play(request("/tmp/b.wav")));
+    }
+
+    #[test]
+    fn test_backpressure() {
+        // The handler blocks on the first request until it's released.
+        let (release, released) = mpsc::channel::<()>();
+        let (started, start) = mpsc::channel();
+        let worker = AudioWorker::spawn(2, move |_| {
+            started.send(()).unwrap();
+            released.recv().unwrap();
+        });
+        assert!(worker.play(request("/tmp/busy.wav")));
+        start.recv().unwrap();
+
+        // Two more fit into the queue, and the rest are dropped instead of blocking.
+        assert!(worker.play(request("/tmp/a.wav")));
+        assert!(worker.play(request("/tmp/b.wav")));
+        assert!(!worker.play(request("/tmp/c.wav")));
+        drop(release);
+    }
+
+    #[test]
+    fn test_retry() {
+        let transient = || DecoderError::IoError("device busy".to_string());
+
+        // Transient errors are tried again until it works.
+        let mut attempts = 0;
+        let result = retry(2, Duration::ZERO, || {
+            attempts += 1;
+            if attempts == 1 {
+                Err(transient())
+            } else {
+                Ok(attempts)
+            }
+        });
+        assert_eq!(result.ok(), Some(2));
+
+        // But only so often.
+        let mut attempts = 0;
+        let result = retry(2, Duration::ZERO, || {
+            attempts += 1;
+            Err::<(), _>(transient())
+        });
+        assert!(matches!(result, Err(DecoderError::IoError(_))));
+        assert_eq!(attempts, 3);
+
+        // Decode errors are permanent.
+        let mut attempts = 0;
+        let result = retry(2, Duration::ZERO, || {
+            attempts += 1;
+            Err::<(), _>(DecoderError::UnrecognizedFormat)
+        });
+        assert!(matches!(result, Err(DecoderError::UnrecognizedFormat)));
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn test_may_recover() {
+        let io = DecoderError::IoError("end of stream".to_string());
+        assert!(may_recover(&Sound::Lazy("/tmp/bell.wav".into()), &io));
+        assert!(!may_recover(
+            &Sound::Lazy("/tmp/bell.wav".into()),
+            &DecoderError::UnrecognizedFormat
+        ));
+        assert!(may_recover(
+            &Sound::Command("true".to_string()),
+            &DecoderError::UnrecognizedFormat
+        ));
+        // A truncated file in memory is truncated the next time too.
+        assert!(!may_recover(&Sound::Encoded(vec![0; 4]), &io));
+    }
+
+    #[test]
+    fn test_single_span() {
+        // The mixer sees the whole sound, not just the empty first span of Ogg Vorbis.
+        let sound = Sound::Encoded(builtin::get("ding").unwrap().to_vec());
+        let source = sound.source().unwrap();
+        let (mixer, output) = rodio::mixer::mixer(source.channels(), source.sample_rate());
+        let len = source.count();
+        mixer.add(SingleSpan(sound.source().unwrap()));
+        assert_eq!(output.count(), len);
+    }
+
+    #[test]
+    fn test_player() {
+        let (mixer, mut output) = rodio::mixer::mixer(1, 22050);
+        let stats = Arc::new(Mutex::new(Stats::default()));
+        let mut player = Player::new(mixer, stats.clone(), None);
+        player.play(PlayRequest {
+            sound: Sound::Encoded(builtin::get("ding").unwrap().to_vec()),
+            ..request("builtin:ding")
+        });
+        assert!(output.any(|x| x != 0.0));
+
+        // Broken sounds are only tried once, until the player is reset.
+        player.play(request("/tmp/broken.wav"));
+        player.play(request("/tmp/broken.wav"));
+        assert!(player.failed.contains(&PathBuf::from("/tmp/broken.wav")));
+        player.run(Job::Reset);
+        assert!(player.failed.is_empty());
+
+        // Devices that can't be opened fall back to the default output.
+        player.play(PlayRequest {
+            sound: Sound::Encoded(builtin::get("ding").unwrap().to_vec()),
+            devices: vec!["onionbell-nonexistent-device".into()],
+            ..request("builtin:ding")
+        });
+        assert!(
+            player
+                .failed_devices
+                .contains("onionbell-nonexistent-device")
+        );
+
+        let stats = stats.lock().unwrap();
+        assert_eq!(stats.decode.count(), 3);
+        assert_eq!(stats.enqueue.count(), 2);
+    }
+
+    #[test]
+    fn test_devices() {
+        let (mixer, mut output) = rodio::mixer::mixer(1, 22050);
+        let stats = Arc::new(Mutex::new(Stats::default()));
+        let mut player = Player::new(mixer, stats, None);
+        let mut outputs = Vec::new();
+        for name in ["front", "headset"] {
+            let (mixer, output) = rodio::mixer::mixer(1, 22050);
+            player.devices.insert(
+                name.to_string(),
+                Device {
+                    mixer,
+                    _stream: None,
+                },
+            );
+            outputs.push(output);
+        }
+        let ding = || PlayRequest {
+            sound: Sound::Encoded(builtin::get("ding").unwrap().to_vec()),
+            ..request("builtin:ding")
+        };
+
+        // Every device plays it, and a missing one is skipped.
+        player.play(PlayRequest {
+            devices: vec![
+                "front".into(),
+                "onionbell-nonexistent-device".into(),
+                "headset".into(),
+            ],
+            ..ding()
+        });
+        for output in &mut outputs {
+            assert!(output.take(22050).any(|x| x != 0.0));
+        }
+        assert!(output.by_ref().take(22050).all(|x| x == 0.0));
+        assert!(
+            player
+                .failed_devices
+                .contains("onionbell-nonexistent-device")
+        );
+
+        // When none of them opens, the default output plays it.
+        player.play(PlayRequest {
+            devices: vec!["onionbell-nonexistent-device".into()],
+            ..ding()
+        });
+        assert!(output.take(22050).any(|x| x != 0.0));
+
+        // Failed devices are tried again after a reset.
+        player.run(Job::Reset);
+        assert!(player.failed_devices.is_empty());
+    }
+}
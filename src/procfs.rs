@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use log::trace;
+
+/// How long a looked up process name is reused. Process ids are rarely reused that quickly, and
+/// a burst of bells from one window only reads `/proc` once.
+const CACHE_TTL: Duration = Duration::from_secs(5);
+
+/// Reads the executable name of a process, see [`read_comm`].
+type ReadComm = fn(i32) -> io::Result<String>;
+
+static COMM_CACHE: LazyLock<Mutex<CommCache<ReadComm>>> =
+    LazyLock::new(|| Mutex::new(CommCache::new(read_comm)));
+
+/// Read the executable name of the process `pid` from `/proc/<pid>/comm`.
+pub fn read_comm(pid: i32) -> io::Result<String> {
+    let comm = fs::read_to_string(format!("/proc/{pid}/comm"))?;
+    Ok(comm.trim_end_matches('\n').to_string())
+}
+
+/// The executable name of the process `pid`, or `None` if it can't be read, e.g. because the
+/// process is gone or `/proc` isn't mounted. Lookups are cached for a few seconds.
+pub fn comm(pid: i32) -> Option<String> {
+    COMM_CACHE
+        .lock()
+        .unwrap_or_else(|x| x.into_inner())
+        .get(pid, Instant::now())
+}
+
+/// Caches the results of `read`, including failures, for [`CACHE_TTL`].
+pub struct CommCache<F> {
+    read: F,
+    entries: HashMap<i32, (Instant, Option<String>)>,
+}
+
+impl<F: FnMut(i32) -> io::Result<String>> CommCache<F> {
+    pub fn new(read: F) -> CommCache<F> {
+        CommCache {
+            read,
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn get(&mut self, pid: i32, now: Instant) -> Option<String> {
+        if let Some((since, comm)) = self.entries.get(&pid)
+            && now.saturating_duration_since(*since) < CACHE_TTL
+        {
+            return comm.clone();
+        }
+        let comm = (self.read)(pid)
+            .inspect_err(|err| trace!("failed to read the name of process {pid}: {err}"))
+            .ok();
+        self.entries
+            .retain(|_, (since, _)| now.saturating_duration_since(*since) < CACHE_TTL);
+        self.entries.insert(pid, (now, comm.clone()));
+        comm
+    }
+}
+
+#[allow(unused)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cache() {
+        let mut reads = Vec::new();
+        let mut cache = CommCache::new(|pid| {
+            reads.push(pid);
+            match pid {
+                1 => Ok("systemd".to_string()),
+                _ => Err(io::Error::from(io::ErrorKind::NotFound)),
+            }
+        });
+        let start = Instant::now();
+        assert_eq!(cache.get(1, start), Some("systemd".to_string()));
+        assert_eq!(
+            cache.get(1, start + Duration::from_secs(1)),
+            Some("systemd".to_string())
+        );
+        assert_eq!(cache.get(2, start), None);
+        assert_eq!(cache.get(2, start + Duration::from_secs(1)), None);
+        assert_eq!(cache.get(1, start + CACHE_TTL), Some("systemd".to_string()));
+        drop(cache);
+        assert_eq!(reads, [1, 2, 1]);
+    }
+
+    #[test]
+    fn test_read_comm() {
+        let comm = read_comm(std::process::id() as i32).unwrap();
+        assert!(!comm.is_empty());
+        assert!(!comm.ends_with('\n'));
+        assert!(read_comm(-1).is_err());
+    }
+}
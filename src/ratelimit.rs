@@ -0,0 +1,68 @@
+use std::time::{Duration, Instant};
+
+/// Lets something through at most once per interval, counting what was held back in between.
+/// Used to keep the logs readable during bell storms.
+#[derive(Debug)]
+pub struct RateLimiter {
+    interval: Duration,
+    last: Option<Instant>,
+    suppressed: u64,
+}
+
+impl RateLimiter {
+    /// A zero `interval` lets everything through.
+    pub fn new(interval: Duration) -> RateLimiter {
+        RateLimiter {
+            interval,
+            last: None,
+            suppressed: 0,
+        }
+    }
+
+    /// Decide whether to let something through at `now`. Returns how many were suppressed since
+    /// the last one let through, or `None` if this one is suppressed too.
+    pub fn check(&mut self, now: Instant) -> Option<u64> {
+        if let Some(last) = self.last
+            && now.saturating_duration_since(last) < self.interval
+        {
+            self.suppressed += 1;
+            return None;
+        }
+        self.last = Some(now);
+        Some(std::mem::take(&mut self.suppressed))
+    }
+}
+
+#[allow(unused)]
+mod test {
+    use super::*;
+
+    fn ms(start: Instant, ms: u64) -> Instant {
+        start + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn test_check() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(Duration::from_secs(1));
+        assert_eq!(limiter.check(start), Some(0));
+        assert_eq!(limiter.check(ms(start, 10)), None);
+        assert_eq!(limiter.check(ms(start, 500)), None);
+        assert_eq!(limiter.check(ms(start, 999)), None);
+        assert_eq!(limiter.check(ms(start, 1000)), Some(3));
+
+        // The interval starts over from the last one let through.
+        assert_eq!(limiter.check(ms(start, 1500)), None);
+        assert_eq!(limiter.check(ms(start, 2000)), Some(1));
+        assert_eq!(limiter.check(ms(start, 5000)), Some(0));
+    }
+
+    #[test]
+    fn test_zero_interval() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(Duration::ZERO);
+        for _ in 0..3 {
+            assert_eq!(limiter.check(start), Some(0));
+        }
+    }
+}
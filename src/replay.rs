@@ -0,0 +1,277 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config::ResolvedSound;
+use crate::error::AppError;
+
+/// Appends socket2 event lines to a file, to be replayed later with `--replay`. Each line starts
+/// with the time the event arrived, in milliseconds since the Unix epoch, and a tab.
+pub struct Recorder<W: Write> {
+    writer: W,
+}
+
+impl Recorder<File> {
+    /// Open `path` for appending, creating it if it doesn't exist.
+    pub fn open(path: &Path) -> io::Result<Recorder<File>> {
+        Ok(Recorder::new(
+            OpenOptions::new().create(true).append(true).open(path)?,
+        ))
+    }
+}
+
+impl<W: Write> Recorder<W> {
+    pub fn new(writer: W) -> Recorder<W> {
+        Recorder { writer }
+    }
+
+    pub fn record(&mut self, event: &str) -> io::Result<()> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        self.record_at(event, now.as_millis() as u64)
+    }
+
+    /// Record `event` as arrived at `time_ms` milliseconds since the Unix epoch.
+    pub fn record_at(&mut self, event: &str, time_ms: u64) -> io::Result<()> {
+        writeln!(self.writer, "{time_ms}\t{event}")?;
+        self.writer.flush()
+    }
+}
+
+/// An event read from a recording.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedEvent {
+    /// When the event arrived, in milliseconds since the Unix epoch, or `None` for lines without
+    /// a time, like recordings of older versions.
+    pub time_ms: Option<u64>,
+    pub event: String,
+}
+
+impl RecordedEvent {
+    pub fn parse(line: &str) -> RecordedEvent {
+        // Event types are never numbers, so a number before the first tab is always a time.
+        match line.split_once('\t') {
+            Some((time, event)) if let Ok(time) = time.parse() => RecordedEvent {
+                time_ms: Some(time),
+                event: event.to_string(),
+            },
+            _ => RecordedEvent {
+                time_ms: None,
+                event: line.to_string(),
+            },
+        }
+    }
+}
+
+/// Read events recorded by [`Recorder`], skipping blank lines.
+pub fn read_events(reader: impl BufRead) -> impl Iterator<Item = io::Result<RecordedEvent>> {
+    reader
+        .lines()
+        .filter(|x| x.as_ref().map_or(true, |x| !x.trim().is_empty()))
+        .map(|x| x.map(|x| RecordedEvent::parse(&x)))
+}
+
+/// How fast `--replay` goes through the recorded events.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ReplaySpeed {
+    /// Without waiting between events.
+    #[default]
+    Max,
+
+    /// With the delays between events as they were recorded.
+    RealTime,
+
+    /// At a fixed number of events per second.
+    Rate(f64),
+}
+
+impl ReplaySpeed {
+    /// Parse `max`, `realtime`, or a positive number of events per second.
+    pub fn parse(s: &str) -> Result<ReplaySpeed, AppError> {
+        match s {
+            "max" => Ok(ReplaySpeed::Max),
+            "realtime" => Ok(ReplaySpeed::RealTime),
+            _ => match s.parse::<f64>() {
+                // Rates so low that the wait between events doesn't fit in a `Duration` are
+                // rejected too.
+                Ok(rate)
+                    if rate.is_finite()
+                        && rate > 0.0
+                        && Duration::try_from_secs_f64(1.0 / rate).is_ok() =>
+                {
+                    Ok(ReplaySpeed::Rate(rate))
+                }
+                _ => Err(AppError::InvalidArgument(format!(
+                    "replay speed must be max, realtime or a positive number of events per \
+                    second, not {s}"
+                ))),
+            },
+        }
+    }
+
+    /// How long to wait before replaying an event recorded at `time_ms`, after the `previous`
+    /// one. Nothing is waited for before the first event, or when times are missing in real time.
+    pub fn delay(&self, previous: Option<u64>, time_ms: Option<u64>, first: bool) -> Duration {
+        if first {
+            return Duration::ZERO;
+        }
+        match *self {
+            ReplaySpeed::Max => Duration::ZERO,
+            ReplaySpeed::RealTime => match (previous, time_ms) {
+                // A clock turned back doesn't make us wait.
+                (Some(previous), Some(time)) => {
+                    Duration::from_millis(time.saturating_sub(previous))
+                }
+                _ => Duration::ZERO,
+            },
+            ReplaySpeed::Rate(rate) => {
+                Duration::try_from_secs_f64(1.0 / rate).unwrap_or(Duration::MAX)
+            }
+        }
+    }
+}
+
+/// Describe what onionbell decided to do for `event`. `resolved` is `None` for events that are
+/// not handled.
+pub fn format_decision(event: &str, resolved: Option<&ResolvedSound>) -> String {
+    let Some(resolved) = resolved else {
+        return format!("{event} -> unhandled");
+    };
+    let sound = match (&resolved.sound, &resolved.command) {
+        (Some(sound), _) => format!("{} at volume {}", sound.to_string_lossy(), resolved.volume),
+        (None, Some(command)) => format!("`{command}` at volume {}", resolved.volume),
+        (None, None) => "silence".to_string(),
+    };
+    let source = match resolved.rule {
+        Some(i) => format!("rule #{i}"),
+        None => "global".to_string(),
+    };
+    format!("{event} -> {sound} ({source})")
+}
+
+#[allow(unused)]
+mod test {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let events = [
+            "bell>>558e91924520",
+            "activewindow>>kitty,tmux a",
+            "bell>>558e9243ab50",
+        ];
+        let mut buf = Vec::new();
+        let mut recorder = Recorder::new(&mut buf);
+        for (i, event) in events.iter().enumerate() {
+            recorder.record_at(event, 1000 + i as u64 * 250).unwrap();
+        }
+
+        // Blank lines, e.g. from hand editing, are ignored.
+        buf.extend_from_slice(b"\n");
+        let replayed = read_events(buf.as_slice())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            replayed.iter().map(|x| &x.event).collect::<Vec<_>>(),
+            events
+        );
+        assert_eq!(
+            replayed.iter().map(|x| x.time_ms).collect::<Vec<_>>(),
+            [Some(1000), Some(1250), Some(1500)]
+        );
+    }
+
+    #[test]
+    fn test_parse_recorded_event() {
+        // Recordings without times are still read.
+        assert_eq!(
+            RecordedEvent::parse("bell>>abc"),
+            RecordedEvent {
+                time_ms: None,
+                event: "bell>>abc".into()
+            }
+        );
+        assert_eq!(
+            RecordedEvent::parse("activewindow>>kitty,a\tb"),
+            RecordedEvent {
+                time_ms: None,
+                event: "activewindow>>kitty,a\tb".into()
+            }
+        );
+        assert_eq!(
+            RecordedEvent::parse("42\tactivewindow>>kitty,a\tb"),
+            RecordedEvent {
+                time_ms: Some(42),
+                event: "activewindow>>kitty,a\tb".into()
+            }
+        );
+    }
+
+    #[test]
+    fn test_replay_speed() {
+        assert_eq!(ReplaySpeed::parse("max").unwrap(), ReplaySpeed::Max);
+        assert_eq!(
+            ReplaySpeed::parse("realtime").unwrap(),
+            ReplaySpeed::RealTime
+        );
+        assert_eq!(ReplaySpeed::parse("4").unwrap(), ReplaySpeed::Rate(4.0));
+        assert!(ReplaySpeed::parse("0").is_err());
+        assert!(ReplaySpeed::parse("-1").is_err());
+        assert!(ReplaySpeed::parse("1e-300").is_err());
+        assert_eq!(
+            ReplaySpeed::Rate(1e-300).delay(Some(0), Some(0), false),
+            Duration::MAX
+        );
+        assert!(ReplaySpeed::parse("fast").is_err());
+
+        let ms = Duration::from_millis;
+        let speed = ReplaySpeed::RealTime;
+        assert_eq!(speed.delay(None, Some(1000), true), ms(0));
+        assert_eq!(speed.delay(Some(1000), Some(1250), false), ms(250));
+        assert_eq!(speed.delay(Some(1250), Some(1000), false), ms(0));
+        assert_eq!(speed.delay(None, Some(1000), false), ms(0));
+        assert_eq!(speed.delay(Some(1000), None, false), ms(0));
+
+        assert_eq!(ReplaySpeed::Rate(4.0).delay(None, None, true), ms(0));
+        assert_eq!(ReplaySpeed::Rate(4.0).delay(None, None, false), ms(250));
+        assert_eq!(ReplaySpeed::Max.delay(Some(0), Some(1000), false), ms(0));
+    }
+
+    #[test]
+    fn test_format_decision() {
+        assert_eq!(
+            format_decision("openwindow>>abc", None),
+            "openwindow>>abc -> unhandled"
+        );
+        assert_eq!(
+            format_decision(
+                "bell>>abc",
+                Some(&ResolvedSound {
+                    sound: Some(PathBuf::from("/tmp/a.wav")),
+                    command: None,
+                    volume: 0.5,
+                    volume_floor: None,
+                    rule: Some(1),
+                })
+            ),
+            "bell>>abc -> /tmp/a.wav at volume 0.5 (rule #1)"
+        );
+        assert_eq!(
+            format_decision(
+                "bell>>abc",
+                Some(&ResolvedSound {
+                    sound: None,
+                    command: None,
+                    volume: 1.0,
+                    volume_floor: None,
+                    rule: None,
+                })
+            ),
+            "bell>>abc -> silence (global)"
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use std::time::Instant;
+
+/// Things to do later, like sounds of rules with a `delay_ms`. The event loop wakes up at
+/// [`Schedule::next_deadline`] and takes what's due.
+#[derive(Debug)]
+pub struct Schedule<T> {
+    pending: Vec<(Instant, T)>,
+}
+
+impl<T> Default for Schedule<T> {
+    fn default() -> Self {
+        Schedule {
+            pending: Vec::new(),
+        }
+    }
+}
+
+impl<T> Schedule<T> {
+    /// Do `item` at `at`.
+    pub fn add(&mut self, at: Instant, item: T) {
+        self.pending.push((at, item));
+    }
+
+    /// When the next item is due.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.pending.iter().map(|(at, _)| *at).min()
+    }
+
+    /// Remove and return the items due at `now`, earliest first. Items due at the same time are
+    /// returned in the order they were added.
+    pub fn take_due(&mut self, now: Instant) -> Vec<T> {
+        let (mut due, pending) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition::<Vec<_>, _>(|(at, _)| *at <= now);
+        self.pending = pending;
+        due.sort_by_key(|(at, _)| *at);
+        due.into_iter().map(|(_, item)| item).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+#[allow(unused)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn ms(start: Instant, ms: u64) -> Instant {
+        start + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn test_take_due() {
+        let start = Instant::now();
+        let mut schedule = Schedule::default();
+        assert_eq!(schedule.next_deadline(), None);
+        schedule.add(ms(start, 300), "c");
+        schedule.add(ms(start, 100), "a");
+        schedule.add(ms(start, 200), "b");
+        schedule.add(ms(start, 100), "a2");
+        assert_eq!(schedule.next_deadline(), Some(ms(start, 100)));
+
+        assert!(schedule.take_due(ms(start, 99)).is_empty());
+        assert_eq!(schedule.take_due(ms(start, 200)), ["a", "a2", "b"]);
+        assert_eq!(schedule.len(), 1);
+        assert_eq!(schedule.next_deadline(), Some(ms(start, 300)));
+        assert_eq!(schedule.take_due(ms(start, 1000)), ["c"]);
+        assert!(schedule.is_empty());
+    }
+}
@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::AppError;
+
+/// How many milliseconds `instant` was before `now`.
+pub fn ms_ago(instant: Instant, now: Instant) -> u64 {
+    now.saturating_duration_since(instant).as_millis() as u64
+}
+
+/// The instant `ms` milliseconds before `now`, or `None` if the monotonic clock doesn't go back
+/// that far, e.g. right after booting.
+pub fn instant_ago(ms: u64, now: Instant) -> Option<Instant> {
+    now.checked_sub(Duration::from_millis(ms))
+}
+
+/// A level that builds up with bells close together, like [`crate::escalation::Escalation`] and
+/// [`crate::fatigue::FatigueState`].
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LevelSnapshot {
+    pub level: f32,
+    pub last_bell_ms_ago: Option<u64>,
+}
+
+/// The window a rule with `window_cooldown_ms` last played for.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CooldownSnapshot {
+    pub address: String,
+    pub played_ms_ago: u64,
+}
+
+/// What onionbell remembers between bells, for the `dump-state` and `restore-state` control
+/// commands. Times are kept relative to when the snapshot was taken, since instants mean nothing
+/// to another process. Rules are referred to by their index, so a snapshot only makes sense for
+/// the same rules.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Snapshot {
+    pub escalation: LevelSnapshot,
+
+    /// Keyed by the index of the rule.
+    pub fatigue: HashMap<usize, LevelSnapshot>,
+
+    /// Keyed by the index of the rule.
+    pub window_cooldowns: HashMap<usize, CooldownSnapshot>,
+
+    pub first_bell_ms_ago: Option<u64>,
+
+    /// How many times each rule has matched, keyed by the index of the rule.
+    pub counters: HashMap<usize, u64>,
+
+    /// Rules with `once_per_window` and the keys of the windows they fired for, sorted.
+    pub fired: Vec<(usize, String)>,
+
+    /// When each window last requested attention, keyed by its address.
+    pub urgent_ms_ago: HashMap<String, u64>,
+}
+
+impl Snapshot {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("snapshots always serialize")
+    }
+
+    pub fn from_json(source: &str) -> Result<Snapshot, AppError> {
+        Ok(serde_json::from_str(source)?)
+    }
+
+    /// Drop what's remembered for rules at or after `rules`, which don't exist in this process.
+    pub fn retain_rules(&mut self, rules: usize) {
+        self.fatigue.retain(|i, _| *i < rules);
+        self.window_cooldowns.retain(|i, _| *i < rules);
+        self.counters.retain(|i, _| *i < rules);
+        self.fired.retain(|(i, _)| *i < rules);
+    }
+}
+
+#[allow(unused)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_json() {
+        let snapshot = Snapshot {
+            escalation: LevelSnapshot {
+                level: 0.25,
+                last_bell_ms_ago: Some(1200),
+            },
+            fatigue: HashMap::from([(1, LevelSnapshot::default())]),
+            window_cooldowns: HashMap::from([(
+                2,
+                CooldownSnapshot {
+                    address: "558e91924520".into(),
+                    played_ms_ago: 300,
+                },
+            )]),
+            first_bell_ms_ago: Some(5000),
+            counters: HashMap::from([(0, 3), (2, 1)]),
+            fired: vec![(0, "kitty\x1fvim\x1f1".into())],
+            urgent_ms_ago: HashMap::from([("deadbeef".into(), 10)]),
+        };
+        let json = snapshot.to_json();
+        assert!(!json.contains('\n'));
+        assert_eq!(Snapshot::from_json(&json).unwrap(), snapshot);
+
+        // Everything is optional.
+        assert_eq!(Snapshot::from_json("{}").unwrap(), Snapshot::default());
+        assert!(Snapshot::from_json(r#"{ "counters": 1 }"#).is_err());
+    }
+
+    #[test]
+    fn test_retain_rules() {
+        let mut snapshot = Snapshot {
+            counters: HashMap::from([(0, 3), (2, 1)]),
+            fired: vec![(0, "a".into()), (2, "b".into())],
+            ..Default::default()
+        };
+        snapshot.retain_rules(2);
+        assert_eq!(snapshot.counters, HashMap::from([(0, 3)]));
+        assert_eq!(snapshot.fired, vec![(0, "a".to_string())]);
+    }
+
+    #[test]
+    fn test_ms_ago() {
+        let now = Instant::now();
+        let then = instant_ago(1500, now).unwrap();
+        assert_eq!(ms_ago(then, now), 1500);
+        assert_eq!(ms_ago(now, then), 0);
+    }
+}
@@ -0,0 +1,513 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use rodio::buffer::SamplesBuffer;
+use rodio::decoder::DecoderError;
+use rodio::source::{SineWave, UniformSourceIterator, Zero};
+use rodio::{ChannelCount, Decoder, Sample, SampleRate, Source};
+
+use crate::config::Beeps;
+use crate::util;
+
+/// How long a `sound_cmd` may run before it's killed.
+pub const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Audio data of a preloaded sound.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sound {
+    /// Content of the audio file, decoded every time it's played.
+    Encoded(Vec<u8>),
+
+    /// Samples decoded ahead of time, e.g. loaded from the disk cache.
+    Decoded(DecodedSound),
+
+    /// An audio file that's not preloaded because of `max_preload_bytes`, read every time it's
+    /// played.
+    Lazy(PathBuf),
+
+    /// A `sound_cmd` whose output is played, run every time it's played.
+    Command(String),
+
+    /// A `beeps` pattern, synthesized every time it's played.
+    Beeps(Beeps),
+}
+
+impl Sound {
+    /// How many bytes of memory the audio data takes.
+    pub fn size(&self) -> usize {
+        match self {
+            Sound::Encoded(data) => data.len(),
+            Sound::Decoded(sound) => sound.samples.len() * size_of::<Sample>(),
+            Sound::Lazy(_) | Sound::Command(_) | Sound::Beeps(_) => 0,
+        }
+    }
+
+    /// Build a source to play this sound.
+    pub fn source(&self) -> Result<Box<dyn Source + Send>, DecoderError> {
+        match self {
+            Sound::Encoded(data) => Ok(Box::new(Decoder::try_from(io::Cursor::new(data.clone()))?)),
+            Sound::Decoded(sound) => Ok(Box::new(sound.source())),
+            Sound::Lazy(path) => {
+                let data = fs::read(path).map_err(|x| DecoderError::IoError(x.to_string()))?;
+                Sound::Encoded(data).source()
+            }
+            Sound::Command(command) => {
+                let data = util::run_command(command, COMMAND_TIMEOUT)
+                    .map_err(|x| DecoderError::IoError(x.to_string()))?;
+                Sound::Encoded(data).source()
+            }
+            Sound::Beeps(pattern) => Ok(beeps(pattern)),
+        }
+    }
+}
+
+/// List every sound with its size, largest first, followed by the total.
+pub fn format_sound_list(sounds: &HashMap<PathBuf, Sound>) -> String {
+    let mut sounds = sounds.iter().collect::<Vec<_>>();
+    sounds.sort_by(|a, b| b.1.size().cmp(&a.1.size()).then(a.0.cmp(b.0)));
+    let mut result = String::new();
+    for (path, sound) in &sounds {
+        let kind = match sound {
+            Sound::Encoded(_) => "encoded",
+            Sound::Decoded(_) => "decoded",
+            Sound::Lazy(_) => "lazy",
+            Sound::Command(_) => "command",
+            Sound::Beeps(_) => "beeps",
+        };
+        result += &format!("{:>12} {} {}\n", sound.size(), kind, path.to_string_lossy());
+    }
+    result += &format!(
+        "{:>12} total in {} sounds\n",
+        sounds.iter().map(|(_, x)| x.size()).sum::<usize>(),
+        sounds.len()
+    );
+    result
+}
+
+/// Resample `source` to `sample_rate` ourselves, keeping its channels, instead of leaving it to
+/// the output device. `None` keeps the source as is.
+pub fn resample(
+    source: Box<dyn Source + Send>,
+    sample_rate: Option<SampleRate>,
+) -> Box<dyn Source + Send> {
+    match sample_rate {
+        Some(sample_rate) if sample_rate != source.sample_rate() => {
+            let channels = source.channels();
+            Box::new(UniformSourceIterator::new(source, channels, sample_rate))
+        }
+        _ => source,
+    }
+}
+
+/// Prepend `prelude` of silence to `source`, so the output device is awake before the sound
+/// begins. A zero `prelude` keeps the source as is.
+pub fn prelude(source: Box<dyn Source + Send>, prelude: Duration) -> Box<dyn Source + Send> {
+    if prelude.is_zero() {
+        return source;
+    }
+    Box::new(source.delay(prelude))
+}
+
+/// The parts of the `beeps` pattern in order: a sine wave for every beep, with silence in
+/// between. They're made as they're played.
+pub fn beep_segments(beeps: &Beeps) -> impl Iterator<Item = Box<dyn Source + Send>> + Send + use<> {
+    let beep = Duration::from_millis(beeps.ms);
+    let gap = Duration::from_millis(beeps.gap);
+    let freq = beeps.freq;
+    (0..beeps.count).flat_map(move |i| {
+        // The same format as the sine waves, so the segments play back to back.
+        let silence = (i > 0)
+            .then(|| Box::new(Zero::new(1, 48000).take_duration(gap)) as Box<dyn Source + Send>);
+        let sine: Box<dyn Source + Send> = Box::new(SineWave::new(freq).take_duration(beep));
+        silence.into_iter().chain([sine])
+    })
+}
+
+/// A source playing the `beeps` pattern.
+pub fn beeps(beeps: &Beeps) -> Box<dyn Source + Send> {
+    Box::new(rodio::source::from_iter(beep_segments(beeps)))
+}
+
+/// Mix every channel of `source` down to mono if `downmix` is set.
+pub fn downmix(source: Box<dyn Source + Send>, downmix: bool) -> Box<dyn Source + Send> {
+    if !downmix || source.channels() == 1 {
+        return source;
+    }
+    Box::new(Downmix(source))
+}
+
+/// A mono source averaging the channels of every frame of the inner source.
+struct Downmix<S>(S);
+
+impl<S: Source> Iterator for Downmix<S> {
+    type Item = Sample;
+
+    fn next(&mut self) -> Option<Sample> {
+        // The channel count may change at the start of a span, which is also the start of a frame.
+        let channels = self.0.channels();
+        let mut sum = self.0.next()?;
+        for _ in 1..channels {
+            sum += self.0.next()?;
+        }
+        Some(sum / channels as f32)
+    }
+}
+
+impl<S: Source> Source for Downmix<S> {
+    fn current_span_len(&self) -> Option<usize> {
+        let channels = self.0.channels() as usize;
+        self.0.current_span_len().map(|x| x.div_ceil(channels))
+    }
+
+    fn channels(&self) -> ChannelCount {
+        1
+    }
+
+    fn sample_rate(&self) -> SampleRate {
+        self.0.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.0.total_duration()
+    }
+}
+
+/// Number of samples, over all channels, in `duration` of audio.
+fn samples_in(duration: Duration, channels: ChannelCount, sample_rate: SampleRate) -> u64 {
+    (duration.as_secs_f64() * sample_rate as f64) as u64 * channels as u64
+}
+
+/// Lets a playing [`Fade`] be faded out from elsewhere, e.g. when another sound interrupts it.
+#[derive(Debug, Clone, Default)]
+pub struct FadeHandle {
+    fade_out: Arc<AtomicBool>,
+    finished: Arc<AtomicBool>,
+}
+
+impl FadeHandle {
+    /// Start fading the source out. It ends once the fade-out is over.
+    pub fn fade_out(&self) {
+        self.fade_out.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether the source has played to its end.
+    pub fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::Relaxed)
+    }
+}
+
+/// A source that fades in linearly when it starts, and fades out linearly once its
+/// [`FadeHandle`] asks for it.
+pub struct Fade<S> {
+    inner: S,
+    handle: FadeHandle,
+
+    /// Length of the fade-in in samples.
+    fade_in: u64,
+
+    /// Length of the fade-out in samples.
+    fade_out: u64,
+
+    /// Samples played so far.
+    position: u64,
+
+    /// The position where the fade-out started.
+    fade_out_start: Option<u64>,
+}
+
+impl<S: Source> Fade<S> {
+    pub fn new(inner: S, fade_in: Duration, fade_out: Duration) -> (Fade<S>, FadeHandle) {
+        let (channels, sample_rate) = (inner.channels(), inner.sample_rate());
+        let handle = FadeHandle::default();
+        let fade = Fade {
+            inner,
+            handle: handle.clone(),
+            fade_in: samples_in(fade_in, channels, sample_rate),
+            fade_out: samples_in(fade_out, channels, sample_rate),
+            position: 0,
+            fade_out_start: None,
+        };
+        (fade, handle)
+    }
+}
+
+impl<S: Source> Iterator for Fade<S> {
+    type Item = Sample;
+
+    fn next(&mut self) -> Option<Sample> {
+        if self.fade_out_start.is_none() && self.handle.fade_out.load(Ordering::Relaxed) {
+            self.fade_out_start = Some(self.position);
+        }
+        let mut gain = 1.0;
+        if self.position < self.fade_in {
+            gain *= self.position as f32 / self.fade_in as f32;
+        }
+        if let Some(start) = self.fade_out_start {
+            let elapsed = self.position - start;
+            if elapsed >= self.fade_out {
+                self.handle.finished.store(true, Ordering::Relaxed);
+                return None;
+            }
+            gain *= 1.0 - elapsed as f32 / self.fade_out as f32;
+        }
+        let Some(sample) = self.inner.next() else {
+            self.handle.finished.store(true, Ordering::Relaxed);
+            return None;
+        };
+        self.position += 1;
+        Some(sample * gain)
+    }
+}
+
+impl<S: Source> Source for Fade<S> {
+    fn current_span_len(&self) -> Option<usize> {
+        self.inner.current_span_len()
+    }
+
+    fn channels(&self) -> ChannelCount {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> SampleRate {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+/// Interleaved samples of a decoded sound.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedSound {
+    pub channels: ChannelCount,
+    pub sample_rate: SampleRate,
+    pub samples: Vec<Sample>,
+}
+
+impl DecodedSound {
+    /// Decode the content of an audio file.
+    pub fn decode(data: Vec<u8>) -> Result<DecodedSound, DecoderError> {
+        let decoder = Decoder::try_from(io::Cursor::new(data))?;
+        Ok(DecodedSound {
+            channels: decoder.channels(),
+            sample_rate: decoder.sample_rate(),
+            samples: decoder.collect(),
+        })
+    }
+
+    pub fn source(&self) -> SamplesBuffer {
+        SamplesBuffer::new(self.channels, self.sample_rate, self.samples.clone())
+    }
+}
+
+#[allow(unused)]
+mod test {
+    use super::*;
+    use crate::builtin;
+
+    #[test]
+    fn test_decoded_source() {
+        let data = builtin::get("ding").unwrap().to_vec();
+        let encoded = Sound::Encoded(data.clone());
+        let decoded = DecodedSound::decode(data).unwrap();
+        assert_eq!(decoded.channels, 1);
+        assert_eq!(
+            Sound::Decoded(decoded.clone()).source().unwrap().count(),
+            encoded.source().unwrap().count()
+        );
+        assert!(Sound::Encoded(b"not audio".to_vec()).source().is_err());
+    }
+
+    #[test]
+    fn test_lazy_source() {
+        let path = std::env::temp_dir().join(format!("onionbell-lazy-{}.ogg", std::process::id()));
+        let sound = Sound::Lazy(path.clone());
+        assert_eq!(sound.size(), 0);
+        assert!(sound.source().is_err());
+
+        let data = builtin::get("knock").unwrap();
+        fs::write(&path, data).unwrap();
+        assert_eq!(
+            sound.source().unwrap().count(),
+            Sound::Encoded(data.to_vec()).source().unwrap().count()
+        );
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_beeps() {
+        let pattern = Beeps {
+            count: 3,
+            freq: 880.0,
+            ms: 120,
+            gap: 80,
+        };
+        assert_eq!(beep_segments(&pattern).count(), 5);
+        let sound = Sound::Beeps(pattern.clone());
+        assert_eq!(sound.size(), 0);
+        let source = sound.source().unwrap();
+        assert_eq!(source.channels(), 1);
+        assert_eq!(source.sample_rate(), 48000);
+        // 3 beeps of 120ms with 2 gaps of 80ms, at 48kHz.
+        assert_eq!(pattern.duration(), Duration::from_millis(520));
+        assert_eq!(source.count(), 48 * 520);
+
+        let single = Beeps {
+            count: 1,
+            ..pattern.clone()
+        };
+        assert_eq!(beep_segments(&single).count(), 1);
+        assert_eq!(beeps(&single).count(), 48 * 120);
+        let none = Beeps {
+            count: 0,
+            ..pattern
+        };
+        assert!(beep_segments(&none).next().is_none());
+        assert_eq!(beeps(&none).count(), 0);
+    }
+
+    #[test]
+    fn test_command_source() {
+        let path =
+            std::env::temp_dir().join(format!("onionbell-command-{}.ogg", std::process::id()));
+        let data = builtin::get("knock").unwrap();
+        fs::write(&path, data).unwrap();
+        let sound = Sound::Command(format!(
+            "cat {}",
+            util::shell_quote(&path.to_string_lossy())
+        ));
+        assert_eq!(
+            sound.source().unwrap().count(),
+            Sound::Encoded(data.to_vec()).source().unwrap().count()
+        );
+        assert!(Sound::Command("exit 1".to_string()).source().is_err());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_format_sound_list() {
+        let sounds = HashMap::from([
+            (PathBuf::from("/tmp/a.wav"), Sound::Encoded(vec![0; 10])),
+            (
+                PathBuf::from("/tmp/b.wav"),
+                Sound::Decoded(DecodedSound {
+                    channels: 1,
+                    sample_rate: 8000,
+                    samples: vec![0.0; 100],
+                }),
+            ),
+            (PathBuf::from("/tmp/c.wav"), Sound::Encoded(vec![0; 10])),
+            (
+                PathBuf::from("/tmp/d.wav"),
+                Sound::Lazy(PathBuf::from("/tmp/d.wav")),
+            ),
+        ]);
+        assert_eq!(
+            format_sound_list(&sounds),
+            [
+                "         400 decoded /tmp/b.wav\n",
+                "          10 encoded /tmp/a.wav\n",
+                "          10 encoded /tmp/c.wav\n",
+                "           0 lazy /tmp/d.wav\n",
+                "         420 total in 4 sounds\n",
+            ]
+            .concat()
+        );
+        assert_eq!(
+            format_sound_list(&HashMap::new()),
+            "           0 total in 0 sounds\n"
+        );
+    }
+
+    #[test]
+    fn test_resample() {
+        let sound = Sound::Encoded(builtin::get("chime").unwrap().to_vec());
+        let original_rate = sound.source().unwrap().sample_rate();
+        assert_ne!(original_rate, 48000);
+
+        let resampled = resample(sound.source().unwrap(), Some(48000));
+        assert_eq!(resampled.sample_rate(), 48000);
+        assert_eq!(resampled.channels(), 1);
+        assert_eq!(
+            resample(sound.source().unwrap(), None).sample_rate(),
+            original_rate
+        );
+    }
+
+    #[test]
+    fn test_prelude() {
+        // 4 samples of a stereo 10Hz source last 200 milliseconds.
+        let source = || Box::new(SamplesBuffer::new(2, 10, vec![1.0; 4])) as Box<dyn Source + Send>;
+        let delayed = prelude(source(), Duration::from_millis(300));
+        assert_eq!((delayed.channels(), delayed.sample_rate()), (2, 10));
+        assert_eq!(delayed.total_duration(), Some(Duration::from_millis(500)));
+        assert_eq!(
+            delayed.collect::<Vec<_>>(),
+            [0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 1.0, 1.0, 1.0]
+        );
+        assert_eq!(
+            prelude(source(), Duration::ZERO).collect::<Vec<_>>(),
+            [1.0; 4]
+        );
+    }
+
+    #[test]
+    fn test_downmix() {
+        let source = SamplesBuffer::new(2, 10, vec![1.0, 0.0, 0.5, 0.5, -1.0, 0.2]);
+        let mono = downmix(Box::new(source), true);
+        assert_eq!(mono.channels(), 1);
+        assert_eq!(mono.sample_rate(), 10);
+        assert_eq!(mono.collect::<Vec<_>>(), [0.5, 0.5, -0.4]);
+
+        // It composes with fades, which count mono samples.
+        let source = SamplesBuffer::new(2, 10, vec![1.0; 8]);
+        let (fade, _) = Fade::new(
+            downmix(Box::new(source), true),
+            Duration::from_millis(200),
+            Duration::ZERO,
+        );
+        assert_eq!(fade.collect::<Vec<_>>(), [0.0, 0.5, 1.0, 1.0]);
+
+        let source = SamplesBuffer::new(2, 10, vec![1.0, 0.0]);
+        assert_eq!(downmix(Box::new(source), false).channels(), 2);
+    }
+
+    #[test]
+    fn test_fade_in() {
+        // 10 samples of a mono 10Hz source last 1 second.
+        let source = SamplesBuffer::new(1, 10, vec![1.0; 10]);
+        let (fade, handle) = Fade::new(source, Duration::from_millis(400), Duration::ZERO);
+        assert_eq!(
+            fade.collect::<Vec<_>>(),
+            [0.0, 0.25, 0.5, 0.75, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0]
+        );
+        assert!(handle.is_finished());
+    }
+
+    #[test]
+    fn test_fade_out() {
+        let source = SamplesBuffer::new(2, 10, vec![1.0; 20]);
+        let (mut fade, handle) = Fade::new(source, Duration::ZERO, Duration::from_millis(200));
+        assert_eq!(fade.next(), Some(1.0));
+        assert_eq!(fade.next(), Some(1.0));
+
+        // 200ms of stereo 10Hz audio are 4 samples, after which the source ends early.
+        handle.fade_out();
+        assert!(!handle.is_finished());
+        assert_eq!(fade.collect::<Vec<_>>(), [1.0, 0.75, 0.5, 0.25]);
+        assert!(handle.is_finished());
+
+        // Without a fade-out duration, it stops right away.
+        let source = SamplesBuffer::new(1, 10, vec![1.0; 10]);
+        let (mut fade, handle) = Fade::new(source, Duration::ZERO, Duration::ZERO);
+        fade.next();
+        handle.fade_out();
+        assert_eq!(fade.next(), None);
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::util;
+
+/// Directory of state kept across restarts, which is `$XDG_STATE_HOME/onionbell` or
+/// `~/.local/state/onionbell`.
+pub fn state_dir() -> Option<PathBuf> {
+    util::xdg_dir("XDG_STATE_HOME", ".local/state").map(|x| x.join("onionbell"))
+}
+
+/// Path of the file in `state_dir` remembering whether bells are enabled, set by the `enable` and
+/// `disable` control commands.
+pub fn enabled_path(state_dir: &Path) -> PathBuf {
+    state_dir.join("enabled")
+}
+
+/// Read whether bells are enabled from the file at `path`. Only a file saying `disabled` disables
+/// them, so a missing or unreadable file never mutes onionbell by accident.
+pub fn read_enabled(path: &Path) -> bool {
+    fs::read_to_string(path).map_or(true, |x| x.trim() != "disabled")
+}
+
+/// Remember whether bells are `enabled` in the file at `path`, creating its directory if needed.
+pub fn write_enabled(path: &Path, enabled: bool) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, if enabled { "enabled\n" } else { "disabled\n" })
+}
+
+/// Path of the file in `state_dir` remembering the windows muted with `mute-active`, when
+/// `persist_mutes` is enabled.
+pub fn muted_path(state_dir: &Path) -> PathBuf {
+    state_dir.join("muted")
+}
+
+/// Read the addresses of muted windows from the file at `path`, one per line. A missing or
+/// unreadable file mutes nothing.
+pub fn read_muted(path: &Path) -> HashSet<String> {
+    fs::read_to_string(path)
+        .map(|x| {
+            x.lines()
+                .filter(|x| !x.is_empty())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Remember the addresses of `muted` windows in the file at `path`, creating its directory if
+/// needed.
+pub fn write_muted(path: &Path, muted: &HashSet<String>) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut addresses = muted.iter().map(String::as_str).collect::<Vec<_>>();
+    addresses.sort();
+    fs::write(
+        path,
+        addresses
+            .iter()
+            .map(|x| format!("{x}\n"))
+            .collect::<String>(),
+    )
+}
+
+#[allow(unused)]
+mod test {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    fn test_enabled() {
+        let dir = env::temp_dir().join(format!("onionbell-state-test-{}", std::process::id()));
+        let path = dir.join("onionbell").join("enabled");
+        assert!(read_enabled(&path));
+
+        write_enabled(&path, false).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "disabled\n");
+        assert!(!read_enabled(&path));
+
+        write_enabled(&path, true).unwrap();
+        assert!(read_enabled(&path));
+
+        fs::write(&path, "garbage").unwrap();
+        assert!(read_enabled(&path));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_muted() {
+        let dir = env::temp_dir().join(format!("onionbell-muted-test-{}", std::process::id()));
+        let path = dir.join("onionbell").join("muted");
+        assert!(read_muted(&path).is_empty());
+
+        let muted = HashSet::from(["558e9243ab50".to_string(), "558e91924520".to_string()]);
+        write_muted(&path, &muted).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "558e91924520\n558e9243ab50\n"
+        );
+        assert_eq!(read_muted(&path), muted);
+
+        write_muted(&path, &HashSet::new()).unwrap();
+        assert!(read_muted(&path).is_empty());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,133 @@
+use std::fmt;
+use std::time::Duration;
+
+/// Upper bounds of the latency histogram buckets. Anything slower falls into an extra overflow
+/// bucket.
+const BUCKET_BOUNDS: [Duration; 8] = [
+    Duration::from_micros(100),
+    Duration::from_micros(250),
+    Duration::from_micros(500),
+    Duration::from_millis(1),
+    Duration::from_micros(2500),
+    Duration::from_millis(5),
+    Duration::from_millis(10),
+    Duration::from_millis(50),
+];
+
+/// A fixed-bucket histogram of latencies.
+#[derive(Debug, Default, Clone)]
+pub struct LatencyHistogram {
+    buckets: [u64; BUCKET_BOUNDS.len() + 1],
+    count: u64,
+    total: Duration,
+    max: Duration,
+}
+
+impl LatencyHistogram {
+    /// Index of the bucket `latency` falls into.
+    pub fn bucket_index(latency: Duration) -> usize {
+        BUCKET_BOUNDS
+            .iter()
+            .position(|bound| latency <= *bound)
+            .unwrap_or(BUCKET_BOUNDS.len())
+    }
+
+    pub fn record(&mut self, latency: Duration) {
+        self.buckets[Self::bucket_index(latency)] += 1;
+        self.count += 1;
+        self.total += latency;
+        self.max = self.max.max(latency);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn mean(&self) -> Duration {
+        if self.count == 0 {
+            Duration::ZERO
+        } else {
+            self.total / self.count as u32
+        }
+    }
+
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    pub fn buckets(&self) -> &[u64] {
+        &self.buckets
+    }
+}
+
+impl fmt::Display for LatencyHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "count = {}, mean = {:?}, max = {:?}",
+            self.count,
+            self.mean(),
+            self.max
+        )?;
+        for (bound, count) in BUCKET_BOUNDS.iter().zip(&self.buckets) {
+            writeln!(f, "  <= {:?}: {}", bound, count)?;
+        }
+        writeln!(
+            f,
+            "  >  {:?}: {}",
+            BUCKET_BOUNDS[BUCKET_BOUNDS.len() - 1],
+            self.buckets[BUCKET_BOUNDS.len()]
+        )
+    }
+}
+
+/// Timing statistics of playback, reported by the `stats` control command.
+#[derive(Debug, Default, Clone)]
+pub struct Stats {
+    /// Time spent decoding the audio data of a bell.
+    pub decode: LatencyHistogram,
+
+    /// Time spent handing the decoded source over to the mixer.
+    pub enqueue: LatencyHistogram,
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "decode: {}", self.decode)?;
+        write!(f, "enqueue: {}", self.enqueue)
+    }
+}
+
+#[allow(unused)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bucket_index() {
+        assert_eq!(LatencyHistogram::bucket_index(Duration::ZERO), 0);
+        assert_eq!(
+            LatencyHistogram::bucket_index(Duration::from_micros(100)),
+            0
+        );
+        assert_eq!(
+            LatencyHistogram::bucket_index(Duration::from_micros(101)),
+            1
+        );
+        assert_eq!(LatencyHistogram::bucket_index(Duration::from_millis(3)), 5);
+        assert_eq!(LatencyHistogram::bucket_index(Duration::from_millis(50)), 7);
+        assert_eq!(LatencyHistogram::bucket_index(Duration::from_secs(1)), 8);
+    }
+
+    #[test]
+    fn test_record() {
+        let mut histogram = LatencyHistogram::default();
+        assert_eq!(histogram.mean(), Duration::ZERO);
+        histogram.record(Duration::from_micros(50));
+        histogram.record(Duration::from_micros(150));
+        histogram.record(Duration::from_secs(1));
+        assert_eq!(histogram.count(), 3);
+        assert_eq!(histogram.max(), Duration::from_secs(1));
+        assert_eq!(histogram.mean(), Duration::from_micros(333400));
+        assert_eq!(histogram.buckets(), &[1, 1, 0, 0, 0, 0, 0, 0, 1]);
+    }
+}
@@ -1,4 +1,13 @@
-use std::io::Read;
+use std::env;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::warn;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
 
 use crate::error::AppError;
 
@@ -7,3 +16,224 @@ pub fn reader_to_string(mut reader: impl Read) -> Result<String, AppError> {
     reader.read_to_string(&mut buf)?;
     Ok(buf)
 }
+
+/// Get `$XDG_RUNTIME_DIR`, or `/run/user/<uid>` if it's unset or empty, which is where most
+/// session managers put it anyway. Containers and minimal sessions often don't set the variable.
+pub fn runtime_dir() -> Result<PathBuf, AppError> {
+    // SAFETY: getuid() is always successful and has no side effects.
+    let uid = unsafe { libc::getuid() };
+    resolve_runtime_dir(env::var("XDG_RUNTIME_DIR").ok(), uid, Path::is_dir)
+}
+
+/// The logic behind [`runtime_dir`], with the environment passed in.
+pub fn resolve_runtime_dir(
+    xdg_runtime_dir: Option<String>,
+    uid: u32,
+    is_dir: impl Fn(&Path) -> bool,
+) -> Result<PathBuf, AppError> {
+    if let Some(dir) = xdg_runtime_dir.filter(|x| !x.is_empty()) {
+        return Ok(dir.into());
+    }
+    let fallback = PathBuf::from("/run/user").join(uid.to_string());
+    if is_dir(&fallback) {
+        warn!(
+            "XDG_RUNTIME_DIR is not set, falling back to {}",
+            fallback.to_string_lossy()
+        );
+        Ok(fallback)
+    } else {
+        Err(AppError::RuntimeDirNotFound(fallback))
+    }
+}
+
+/// The directory in the environment variable `name`, like `XDG_CACHE_HOME`, or `fallback` under
+/// `$HOME` if it's unset. Empty variables count as unset, as the XDG base directory spec asks.
+pub fn xdg_dir(name: &str, fallback: &str) -> Option<PathBuf> {
+    resolve_xdg_dir(env::var(name).ok(), env::var("HOME").ok(), fallback)
+}
+
+/// The logic behind [`xdg_dir`], with the environment passed in.
+pub fn resolve_xdg_dir(
+    xdg_dir: Option<String>,
+    home: Option<String>,
+    fallback: &str,
+) -> Option<PathBuf> {
+    match (xdg_dir.filter(|x| !x.is_empty()), home) {
+        (Some(dir), _) => Some(dir.into()),
+        (None, Some(home)) if !home.is_empty() => Some(PathBuf::from(home).join(fallback)),
+        _ => None,
+    }
+}
+
+/// Create the random number generator shared by everything random in onionbell. A fixed `seed`
+/// makes all random choices reproducible; otherwise it's seeded from system entropy.
+pub fn make_rng(seed: Option<u64>) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_os_rng(),
+    }
+}
+
+/// Quote `s` as a single word for `sh`.
+pub fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// Write the BEL character to `out` and flush it, but only if `out` is a terminal, which shows
+/// the bell even without audio, e.g. over SSH. Elsewhere, like in a log file, it would only be
+/// noise. Returns whether it was written.
+pub fn ring_terminal_bell(out: &mut impl Write, is_terminal: bool) -> io::Result<bool> {
+    if !is_terminal {
+        return Ok(false);
+    }
+    out.write_all(b"\x07")?;
+    out.flush()?;
+    Ok(true)
+}
+
+/// Run `command` with `sh -c` and return what it wrote to stdout. The command is killed if it
+/// doesn't exit within `timeout`, and exiting unsuccessfully is an error.
+pub fn run_command(command: &str, timeout: Duration) -> io::Result<Vec<u8>> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    // Read stdout on another thread, so a full pipe doesn't keep the command from exiting.
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let reader = thread::spawn(move || {
+        let mut buf = Vec::new();
+        stdout.read_to_end(&mut buf).map(|_| buf)
+    });
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            let output = reader
+                .join()
+                .unwrap_or_else(|_| Err(io::Error::other("failed to read stdout")))?;
+            if !status.success() {
+                return Err(io::Error::other(format!(
+                    "`{command}` failed with {status}"
+                )));
+            }
+            return Ok(output);
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("`{command}` timed out"),
+            ));
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[allow(unused)]
+mod test {
+    use rand::Rng;
+
+    use super::*;
+
+    #[test]
+    fn test_runtime_dir_from_env() {
+        assert_eq!(
+            resolve_runtime_dir(Some("/tmp/runtime".into()), 1000, |_| false).unwrap(),
+            PathBuf::from("/tmp/runtime")
+        );
+    }
+
+    #[test]
+    fn test_runtime_dir_fallback() {
+        let run_user = |x: &Path| x == Path::new("/run/user/1000");
+        assert_eq!(
+            resolve_runtime_dir(None, 1000, run_user).unwrap(),
+            PathBuf::from("/run/user/1000")
+        );
+        assert_eq!(
+            resolve_runtime_dir(Some("/tmp/run".into()), 1000, run_user).unwrap(),
+            PathBuf::from("/tmp/run")
+        );
+        // An empty variable is the same as an unset one.
+        assert_eq!(
+            resolve_runtime_dir(Some(String::new()), 1000, run_user).unwrap(),
+            PathBuf::from("/run/user/1000")
+        );
+
+        let Err(err) = resolve_runtime_dir(None, 1000, |_| false) else {
+            unreachable!()
+        };
+        assert_eq!(
+            err.to_string(),
+            "XDG_RUNTIME_DIR is not set, and the fallback /run/user/1000 does not exist"
+        );
+    }
+
+    #[test]
+    fn test_xdg_dir() {
+        let home = || Some("/home/me".to_string());
+        assert_eq!(
+            resolve_xdg_dir(Some("/tmp/cache".into()), home(), ".cache"),
+            Some(PathBuf::from("/tmp/cache"))
+        );
+        // An empty variable is the same as an unset one.
+        assert_eq!(
+            resolve_xdg_dir(Some(String::new()), home(), ".cache"),
+            Some(PathBuf::from("/home/me/.cache"))
+        );
+        assert_eq!(
+            resolve_xdg_dir(None, home(), ".cache"),
+            Some(PathBuf::from("/home/me/.cache"))
+        );
+        assert_eq!(resolve_xdg_dir(None, Some(String::new()), ".cache"), None);
+        assert_eq!(resolve_xdg_dir(None, None, ".cache"), None);
+    }
+
+    #[test]
+    fn test_seeded_rng() {
+        let choices = |seed| {
+            let mut rng = make_rng(Some(seed));
+            (0..16)
+                .map(|_| rng.random_range(0..8))
+                .collect::<Vec<u32>>()
+        };
+        assert_eq!(choices(42), choices(42));
+        assert_ne!(choices(42), choices(43));
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+        let output = run_command(
+            &format!("printf %s {}", shell_quote("$HOME 'x'")),
+            Duration::from_secs(5),
+        );
+        assert_eq!(output.unwrap(), b"$HOME 'x'");
+    }
+
+    #[test]
+    fn test_run_command() {
+        assert_eq!(
+            run_command("printf abc", Duration::from_secs(5)).unwrap(),
+            b"abc"
+        );
+        assert!(run_command("exit 1", Duration::from_secs(5)).is_err());
+        let err = run_command("exec sleep 10", Duration::from_millis(50)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn test_ring_terminal_bell() {
+        let mut out = Vec::new();
+        assert!(ring_terminal_bell(&mut out, true).unwrap());
+        assert_eq!(out, b"\x07");
+
+        let mut out = Vec::new();
+        assert!(!ring_terminal_bell(&mut out, false).unwrap());
+        assert!(out.is_empty());
+    }
+}